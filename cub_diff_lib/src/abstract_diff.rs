@@ -6,8 +6,12 @@
 
 use std::fmt;
 use std::io;
+use std::ops::Range;
 
-use crate::lines::{first_inequality_fm_head, first_inequality_fm_tail, Line, LineIfce, LinesIfce};
+use crate::diff_algorithm::{Algorithm, DiffAlgorithm, DiffComponent, IndentHeuristic};
+use crate::lines::{
+    first_inequality_fm_head, first_inequality_fm_tail, Line, LineIfce, Lines, LinesIfce,
+};
 use crate::ApplyOffset;
 
 pub struct AbstractChunk {
@@ -16,7 +20,7 @@ pub struct AbstractChunk {
 }
 
 impl AbstractChunk {
-    fn end_index(&self) -> usize {
+    pub(crate) fn end_index(&self) -> usize {
         self.start_index + self.lines.len()
     }
 
@@ -142,6 +146,30 @@ impl AbstractHunk {
             self.chunk[POST].lines.len() - self.post_context_len
         }
     }
+
+    // The edit (start index, length and replacement lines) that turns
+    // chunk "from" into chunk "to"
+    fn edit(&self, from: usize, to: usize) -> Edit<'_> {
+        let chunk = &self.chunk[from];
+        (chunk.start_index, chunk.lines.len(), &self.chunk[to].lines)
+    }
+
+    // As for edit() but for the changed lines only (i.e. less the context)
+    fn core_edit(&self, from: usize, to: usize) -> Edit<'_> {
+        let shorter = self.chunk[ANTE]
+            .lines
+            .len()
+            .min(self.chunk[POST].lines.len());
+        let head = self.ante_context_len.min(shorter);
+        let tail = self.post_context_len.min(shorter - head);
+        let chunk = &self.chunk[from];
+        let to_lines = &self.chunk[to].lines;
+        (
+            chunk.start_index + head,
+            chunk.lines.len() - head - tail,
+            &to_lines[head..to_lines.len() - tail],
+        )
+    }
 }
 
 #[derive(Debug, Default)]
@@ -153,15 +181,458 @@ pub struct ApplnResult {
     failures: u64,
 }
 
+impl ApplnResult {
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    pub fn successes(&self) -> u64 {
+        self.successes
+    }
+
+    pub fn merges(&self) -> u64 {
+        self.merges
+    }
+
+    pub fn already_applied(&self) -> u64 {
+        self.already_applied
+    }
+
+    // The number of hunks that weren't applied (including those left in
+    // the result between conflict markers)
+    pub fn failures(&self) -> u64 {
+        self.failures
+    }
+}
+
 pub struct AbstractDiff {
     hunks: Vec<AbstractHunk>,
 }
 
+// A run of changed lines and the number of lines of context wanted
+// before and after it
+#[derive(Debug, Clone)]
+struct Change {
+    ante: Range<usize>,
+    post: Range<usize>,
+    before: usize,
+    after: usize,
+}
+
+// The (ante, post) index ranges of each run of changed lines
+fn change_runs(components: &[DiffComponent]) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut runs = vec![];
+    let mut ante_index = 0;
+    let mut post_index = 0;
+    let mut run_start: Option<(usize, usize)> = None;
+    for diff_component in components.iter() {
+        match diff_component {
+            DiffComponent::Unchanged(_, _) => {
+                if let Some(start) = run_start.take() {
+                    runs.push((start.0..ante_index, start.1..post_index));
+                }
+                ante_index += 1;
+                post_index += 1;
+            }
+            DiffComponent::Deletion(_) => {
+                run_start.get_or_insert((ante_index, post_index));
+                ante_index += 1;
+            }
+            DiffComponent::Insertion(_) => {
+                run_start.get_or_insert((ante_index, post_index));
+                post_index += 1;
+            }
+        }
+    }
+    if let Some(start) = run_start {
+        runs.push((start.0..ante_index, start.1..post_index));
+    }
+    runs
+}
+
+// The start index, length and replacement lines of a change to a file
+type Edit<'a> = (usize, usize, &'a [Line]);
+
+// "lines" (which start at index "start" of their file) with the (in
+// order) "edits" made
+fn edited_lines(lines: &[Line], start: usize, edits: &[Edit]) -> Lines {
+    let mut edited: Lines = vec![];
+    let mut index = start;
+    for (edit_start, length, replacement) in edits.iter() {
+        edited.extend_from_slice(&lines[index - start..edit_start - start]);
+        edited.extend_from_slice(replacement);
+        index = edit_start + length;
+    }
+    edited.extend_from_slice(&lines[index - start..]);
+    edited
+}
+
+// The hunks of two diffs (one made on top of the other) whose lines in
+// the intermediate file overlap or abut and those lines (starting at
+// index "start") as told by both diffs
+struct MidCluster {
+    earlier: Range<usize>,
+    later: Range<usize>,
+    start: usize,
+    lines: Lines,
+}
+
+// The hunks for the differences between "ante_lines" and "post_lines"
+// (runs of lines starting at the given indices of their files)
+fn hunks_between<A: DiffAlgorithm>(
+    ante_lines: &[Line],
+    post_lines: &[Line],
+    (ante_start, post_start): (usize, usize),
+    context_len: usize,
+    algorithm: &A,
+) -> Vec<AbstractHunk> {
+    let mut hunks = AbstractDiff::from_lines(ante_lines, post_lines, context_len, algorithm).hunks;
+    for hunk in hunks.iter_mut() {
+        hunk.chunk[ANTE].start_index += ante_start;
+        hunk.chunk[POST].start_index += post_start;
+    }
+    hunks
+}
+
+// Build hunks for the changes combining those whose context would overlap
+fn hunks_from_changes(
+    ante_lines: &[Line],
+    post_lines: &[Line],
+    changes: &[Change],
+) -> Vec<AbstractHunk> {
+    let mut hunks = vec![];
+    let mut iter = changes.iter().peekable();
+    while let Some(first) = iter.next() {
+        let mut last = first;
+        while let Some(next) = iter.peek() {
+            if next.ante.start - last.ante.end > last.after.saturating_add(next.before) {
+                break;
+            }
+            last = next;
+            iter.next();
+        }
+        let head_len = first.before.min(first.ante.start);
+        let tail_len = last.after.min(ante_lines.len() - last.ante.end);
+        let ante_chunk = AbstractChunk {
+            start_index: first.ante.start - head_len,
+            lines: ante_lines[first.ante.start - head_len..last.ante.end + tail_len].to_vec(),
+        };
+        let post_chunk = AbstractChunk {
+            start_index: first.post.start - head_len,
+            lines: post_lines[first.post.start - head_len..last.post.end + tail_len].to_vec(),
+        };
+        hunks.push(AbstractHunk::new(ante_chunk, post_chunk));
+    }
+    hunks
+}
+
 impl AbstractDiff {
     pub fn new(hunks: Vec<AbstractHunk>) -> AbstractDiff {
         AbstractDiff { hunks }
     }
 
+    // Generate the diff that turns "ante_lines" into "post_lines" with
+    // "context_len" lines of context around each change.  Changes whose
+    // context would overlap are combined into a single hunk.
+    pub fn from_lines<A: DiffAlgorithm>(
+        ante_lines: &[Line],
+        post_lines: &[Line],
+        context_len: usize,
+        algorithm: &A,
+    ) -> AbstractDiff {
+        let changes: Vec<Change> = change_runs(&algorithm.diff_lines(ante_lines, post_lines))
+            .into_iter()
+            .map(|(ante, post)| Change {
+                ante,
+                post,
+                before: context_len,
+                after: context_len,
+            })
+            .collect();
+        let hunks = hunks_from_changes(ante_lines, post_lines, &changes);
+        AbstractDiff { hunks }
+    }
+
+    // The changes that this diff makes to "lines" (the contents of the
+    // file that it applies to) with the context that each hunk has and
+    // the lines that result.  The index of the first hunk that doesn't
+    // match "lines" exactly is returned on failure.
+    fn changes_to(&self, lines: &[Line]) -> Result<(Vec<(usize, Change)>, Lines), usize> {
+        let algorithm = IndentHeuristic(Algorithm::default());
+        let mut changes = vec![];
+        let mut post_lines: Lines = vec![];
+        let mut index = 0;
+        for (hunk_index, hunk) in self.hunks.iter().enumerate() {
+            let ante_chunk = hunk.ante_chunk();
+            let post_chunk = hunk.post_chunk();
+            if ante_chunk.start_index < index || !ante_chunk.matches_lines(lines, 0) {
+                return Err(hunk_index);
+            }
+            post_lines.extend_from_slice(&lines[index..ante_chunk.start_index]);
+            let ante_offset = ante_chunk.start_index;
+            let post_offset = post_lines.len();
+            let runs = change_runs(&algorithm.diff_lines(&ante_chunk.lines, &post_chunk.lines));
+            for (ante, post) in runs {
+                let change = Change {
+                    before: ante.start,
+                    after: ante_chunk.lines.len() - ante.end,
+                    ante: ante.start + ante_offset..ante.end + ante_offset,
+                    post: post.start + post_offset..post.end + post_offset,
+                };
+                changes.push((hunk_index, change));
+            }
+            post_lines.extend_from_slice(&post_chunk.lines);
+            index = ante_chunk.end_index();
+        }
+        post_lines.extend_from_slice(&lines[index..]);
+        Ok((changes, post_lines))
+    }
+
+    // Rebuild this diff's hunks with "context_len" lines of context taken
+    // from "lines" (the contents of the file that it applies to).
+    pub fn with_context(&self, lines: &[Line], context_len: usize) -> Result<AbstractDiff, usize> {
+        let (changes, post_lines) = self.changes_to(lines)?;
+        let changes: Vec<Change> = changes
+            .into_iter()
+            .map(|(_, change)| Change {
+                before: context_len,
+                after: context_len,
+                ..change
+            })
+            .collect();
+        let hunks = hunks_from_changes(lines, &post_lines, &changes);
+        Ok(AbstractDiff { hunks })
+    }
+
+    // Rebuild this diff with the context of the hunk at "hunk_index"
+    // extended by "extra_before" and "extra_after" lines (taken from
+    // "lines") merging it with its neighbours if they then overlap.
+    pub fn with_expanded_hunk(
+        &self,
+        lines: &[Line],
+        hunk_index: usize,
+        extra_before: usize,
+        extra_after: usize,
+    ) -> Result<AbstractDiff, usize> {
+        let (changes, post_lines) = self.changes_to(lines)?;
+        let mut expanded: Vec<Change> = vec![];
+        for (index, (this_hunk, mut change)) in changes.iter().cloned().enumerate() {
+            // keep the changes within a hunk together
+            if index > 0 && changes[index - 1].0 == this_hunk {
+                change.before = usize::MAX;
+                expanded.last_mut().unwrap().after = usize::MAX;
+            } else if this_hunk == hunk_index {
+                change.before += extra_before;
+            }
+            if this_hunk == hunk_index && changes.get(index + 1).map(|c| c.0) != Some(this_hunk) {
+                change.after += extra_after;
+            }
+            expanded.push(change);
+        }
+        let hunks = hunks_from_changes(lines, &post_lines, &expanded);
+        Ok(AbstractDiff { hunks })
+    }
+
+    // Group this diff's hunks with those of "later" (a diff made on top
+    // of it) whose lines in the intermediate file overlap or abut.  The
+    // index of the first of later's hunks that contradicts this diff
+    // (about the contents of the intermediate file) is returned on failure.
+    fn mid_clusters(&self, later: &AbstractDiff) -> Result<Vec<MidCluster>, usize> {
+        let mut clusters = vec![];
+        let (mut i, mut j) = (0, 0);
+        while i < self.hunks.len() || j < later.hunks.len() {
+            // gather the hunks whose intermediate lines overlap or abut
+            let start = self
+                .hunks
+                .get(i)
+                .map(|h| h.chunk[POST].start_index)
+                .into_iter()
+                .chain(later.hunks.get(j).map(|h| h.chunk[ANTE].start_index))
+                .min()
+                .unwrap();
+            let mut end = start;
+            let (i_start, j_start) = (i, j);
+            loop {
+                if i < self.hunks.len() && self.hunks[i].chunk[POST].start_index <= end {
+                    end = end.max(self.hunks[i].chunk[POST].end_index());
+                    i += 1;
+                } else if j < later.hunks.len() && later.hunks[j].chunk[ANTE].start_index <= end {
+                    end = end.max(later.hunks[j].chunk[ANTE].end_index());
+                    j += 1;
+                } else {
+                    break;
+                }
+            }
+            // the intermediate lines as told by both diffs
+            let mut mid_lines: Vec<Option<Line>> = vec![None; end - start];
+            for hunk in self.hunks[i_start..i].iter() {
+                let chunk = &hunk.chunk[POST];
+                for (index, line) in chunk.lines.iter().enumerate() {
+                    mid_lines[chunk.start_index - start + index] = Some(line.clone());
+                }
+            }
+            for (hunk_index, hunk) in later.hunks.iter().enumerate().take(j).skip(j_start) {
+                let chunk = &hunk.chunk[ANTE];
+                for (index, line) in chunk.lines.iter().enumerate() {
+                    let mid_line = &mut mid_lines[chunk.start_index - start + index];
+                    match mid_line {
+                        Some(mid_line) if mid_line != line => return Err(hunk_index),
+                        _ => *mid_line = Some(line.clone()),
+                    }
+                }
+            }
+            // NB: the lines are all known as the chunks are contiguous
+            let mid_lines: Lines = mid_lines.into_iter().map(Option::unwrap).collect();
+            clusters.push(MidCluster {
+                earlier: i_start..i,
+                later: j_start..j,
+                start,
+                lines: mid_lines,
+            });
+        }
+        Ok(clusters)
+    }
+
+    // The diff equivalent to applying this diff and then "later" (a diff
+    // made on top of it) worked out from the two diffs alone: hunks that
+    // touch the same lines of the intermediate file are combined and the
+    // lines that they cover rediffed with "context_len" lines of context.
+    // The index of the first of later's hunks that contradicts this diff
+    // (about the contents of the intermediate file) is returned on failure.
+    pub fn squashed<A: DiffAlgorithm>(
+        &self,
+        later: &AbstractDiff,
+        context_len: usize,
+        algorithm: &A,
+    ) -> Result<AbstractDiff, usize> {
+        let mut hunks = vec![];
+        let (mut ante_offset, mut post_offset): (i64, i64) = (0, 0);
+        for cluster in self.mid_clusters(later)? {
+            let earlier = &self.hunks[cluster.earlier];
+            let later = &later.hunks[cluster.later];
+            let earlier_edits: Vec<Edit> = earlier.iter().map(|h| h.edit(POST, ANTE)).collect();
+            let later_edits: Vec<Edit> = later.iter().map(|h| h.edit(ANTE, POST)).collect();
+            let ante_lines = edited_lines(&cluster.lines, cluster.start, &earlier_edits);
+            let post_lines = edited_lines(&cluster.lines, cluster.start, &later_edits);
+            let starts = (
+                cluster.start.apply_offset(-ante_offset),
+                cluster.start.apply_offset(post_offset),
+            );
+            hunks.extend(hunks_between(
+                &ante_lines,
+                &post_lines,
+                starts,
+                context_len,
+                algorithm,
+            ));
+            ante_offset += earlier.iter().map(|h| h.length_diff(false)).sum::<i64>();
+            post_offset += later.iter().map(|h| h.length_diff(false)).sum::<i64>();
+        }
+        Ok(AbstractDiff { hunks })
+    }
+
+    // The diffs (later', self') that make the same changes as applying
+    // this diff and then "later" (a diff made on top of it) but in the
+    // opposite order, worked out from the two diffs alone with up to
+    // "context_len" lines of context, or None if the diffs don't commute
+    // (i.e. some of their changed lines overlap or abut).  The index of
+    // the first of later's hunks that contradicts this diff is returned
+    // on failure.
+    pub fn commuted<A: DiffAlgorithm>(
+        &self,
+        later: &AbstractDiff,
+        context_len: usize,
+        algorithm: &A,
+    ) -> Result<Option<(AbstractDiff, AbstractDiff)>, usize> {
+        let mut later_hunks = vec![];
+        let mut earlier_hunks = vec![];
+        let (mut earlier_offset, mut later_offset): (i64, i64) = (0, 0);
+        for cluster in self.mid_clusters(later)? {
+            let earlier = &self.hunks[cluster.earlier];
+            let later = &later.hunks[cluster.later];
+            let earlier_edits: Vec<Edit> =
+                earlier.iter().map(|h| h.core_edit(POST, ANTE)).collect();
+            let later_edits: Vec<Edit> = later.iter().map(|h| h.core_edit(ANTE, POST)).collect();
+            let touch = |(start, length, _): &Edit, (l_start, l_length, _): &Edit| {
+                start <= &(l_start + l_length) && l_start <= &(start + length)
+            };
+            if earlier_edits
+                .iter()
+                .any(|edit| later_edits.iter().any(|l_edit| touch(edit, l_edit)))
+            {
+                return Ok(None);
+            }
+            let mut all_edits = earlier_edits.clone();
+            all_edits.extend(later_edits.iter().cloned());
+            all_edits.sort_by_key(|edit| edit.0);
+            // the lines before, between (with later's changes only) and after
+            let ante_lines = edited_lines(&cluster.lines, cluster.start, &earlier_edits);
+            let mid_lines = edited_lines(&cluster.lines, cluster.start, &all_edits);
+            let post_lines = edited_lines(&cluster.lines, cluster.start, &later_edits);
+            let ante_start = cluster.start.apply_offset(-earlier_offset);
+            let mid_start = cluster.start.apply_offset(later_offset - earlier_offset);
+            let post_start = cluster.start.apply_offset(later_offset);
+            later_hunks.extend(hunks_between(
+                &ante_lines,
+                &mid_lines,
+                (ante_start, mid_start),
+                context_len,
+                algorithm,
+            ));
+            earlier_hunks.extend(hunks_between(
+                &mid_lines,
+                &post_lines,
+                (mid_start, post_start),
+                context_len,
+                algorithm,
+            ));
+            earlier_offset += earlier.iter().map(|h| h.length_diff(false)).sum::<i64>();
+            later_offset += later.iter().map(|h| h.length_diff(false)).sum::<i64>();
+        }
+        Ok(Some((
+            AbstractDiff { hunks: later_hunks },
+            AbstractDiff {
+                hunks: earlier_hunks,
+            },
+        )))
+    }
+
+    // The index in the post file of the line at "index" in the ante file
+    // (or vice versa if "reverse") or None if the diff deletes (or
+    // replaces) the line
+    pub fn mapped_index(&self, index: usize, reverse: bool) -> Option<usize> {
+        let (ante, post) = if reverse { (POST, ANTE) } else { (ANTE, POST) };
+        let mut offset: i64 = 0;
+        for hunk in self.hunks.iter() {
+            let ante_chunk = &hunk.chunk[ante];
+            let post_chunk = &hunk.chunk[post];
+            if index < ante_chunk.start_index {
+                break;
+            } else if index < ante_chunk.end_index() {
+                let ante_index = index - ante_chunk.start_index;
+                return IndentHeuristic(Algorithm::default())
+                    .diff_lines(&ante_chunk.lines, &post_chunk.lines)
+                    .iter()
+                    .find_map(|component| match component {
+                        DiffComponent::Unchanged(a, p) if *a == ante_index => {
+                            Some(post_chunk.start_index + p)
+                        }
+                        _ => None,
+                    });
+            }
+            offset = post_chunk.end_index() as i64 - ante_chunk.end_index() as i64;
+        }
+        Some(index.apply_offset(offset))
+    }
+
+    pub fn hunks(&self) -> &Vec<AbstractHunk> {
+        &self.hunks
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hunks.is_empty()
+    }
+
     // Apply this diff to lines
     pub fn apply_to_lines<W>(
         &self,
@@ -195,8 +666,8 @@ impl AbstractDiff {
                 for line in &lines[lines_index..cpd.start_index] {
                     result.lines.push(line.clone());
                 }
-                let end = hunk.chunk[ante].lines.len() - cpd.post_context_redn;
-                for line in &hunk.chunk[ante].lines[cpd.ante_context_redn..end] {
+                let end = hunk.chunk[post].lines.len() - cpd.post_context_redn;
+                for line in &hunk.chunk[post].lines[cpd.ante_context_redn..end] {
                     result.lines.push(line.clone());
                 }
                 lines_index = cpd.start_index + hunk.chunk[ante].lines.len()
@@ -298,6 +769,7 @@ impl AbstractDiff {
             }
             result.lines.push(Line::conflict_end_marker());
             let end_line = result.lines.len();
+            result.failures += 1;
             if let Some(file_path) = repd_file_path {
                 writeln!(
                     err_w,
@@ -6,19 +6,43 @@ use std::str::FromStr;
 use regex::{Captures, Regex};
 
 use crate::abstract_diff::{AbstractChunk, AbstractHunk};
+use crate::diff_algorithm::{Algorithm, DiffAlgorithm, DiffComponent, IndentHeuristic};
+use crate::diff_stats::DiffStats;
+use crate::intra_line::{intra_line_changes, pair_runs, Granularity, IntraLineChange};
 use crate::lines::{Line, LineIfce, Lines};
 use crate::text_diff::{
-    extract_source_lines, DiffParseError, DiffParseResult, TextDiff, TextDiffHunk, TextDiffParser,
+    check_hunk_order, extract_source_lines, file_header_line, merged_ops, ops_diff_stats,
+    parse_rendered, patch_id_hasher, reversed_ops, split_ops, start_index, start_line_num,
+    terminated_last_line_ops, write_patch_id_ops, DiffBuildResult, DiffParseError, DiffParseResult,
+    HunkOp, PathAndTimestamp, TextDiff, TextDiffHunk, TextDiffParser,
 };
-use crate::{DiffFormat, ALT_TIMESTAMP_RE_STR, PATH_RE_STR, TIMESTAMP_RE_STR};
+use crate::{DiffFormat, ALT_TIMESTAMP_RE_STR, PATH_RE_STR, SPACED_PATH_RE_STR, TIMESTAMP_RE_STR};
 
+#[derive(Clone)]
 pub struct ContextDiffChunk {
     offset: usize,
     start_line_num: usize,
-    _length: usize,
+    length: usize,
     numlines: usize,
 }
 
+impl ContextDiffChunk {
+    pub fn start_line_num(&self) -> usize {
+        self.start_line_num
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    // The 0 based index of the chunk's first line (or the following line
+    // if the chunk is empty)
+    fn start_index(&self) -> usize {
+        start_index(self.start_line_num, self.length)
+    }
+}
+
+#[derive(Clone)]
 pub struct ContextDiffHunk {
     pub lines: Lines,
     pub ante_chunk: ContextDiffChunk,
@@ -37,25 +61,33 @@ impl TextDiffHunk for ContextDiffHunk {
     }
 
     fn ante_lines(&self) -> Lines {
+        // NB: skip the chunk header lines
         if self.ante_chunk.numlines == 1 {
-            let start = self.post_chunk.offset;
+            let start = self.post_chunk.offset + 1;
             let end = self.post_chunk.offset + self.post_chunk.numlines;
             extract_source_lines(&self.lines[start..end], 2, |l| l.starts_with('+'))
         } else {
-            let start = self.ante_chunk.offset;
+            let start = self.ante_chunk.offset + 1;
             let end = self.ante_chunk.offset + self.ante_chunk.numlines;
             extract_source_lines(&self.lines[start..end], 2, |_| false)
         }
     }
 
     fn post_lines(&self) -> Lines {
-        let start = self.post_chunk.offset;
-        let end = self.post_chunk.offset + self.post_chunk.numlines;
-        extract_source_lines(&self.lines[start..end], 2, |_| false)
+        // NB: the post chunk is omitted if it has no changes
+        if self.post_chunk.numlines == 1 {
+            let start = self.ante_chunk.offset + 1;
+            let end = self.ante_chunk.offset + self.ante_chunk.numlines;
+            extract_source_lines(&self.lines[start..end], 2, |l| l.starts_with('-'))
+        } else {
+            let start = self.post_chunk.offset + 1;
+            let end = self.post_chunk.offset + self.post_chunk.numlines;
+            extract_source_lines(&self.lines[start..end], 2, |_| false)
+        }
     }
 
     fn adds_trailing_white_space(&self) -> bool {
-        let start = self.post_chunk.offset;
+        let start = self.post_chunk.offset + 1;
         let end = self.post_chunk.offset + self.post_chunk.numlines;
         for line in self.lines[start..end].iter() {
             if (line.starts_with('+') || line.starts_with('!')) && line.has_trailing_white_space() {
@@ -66,22 +98,395 @@ impl TextDiffHunk for ContextDiffHunk {
     }
 
     fn get_abstract_diff_hunk(&self) -> AbstractHunk {
-        // NB: convert starting line numbers to 0 based indices
+        // NB: convert starting line numbers to 0 based indices (except
+        // for empty chunks which are identified by the preceding line)
+        let ante_lines = self.ante_lines();
+        let post_lines = self.post_lines();
         let ante_chunk = AbstractChunk {
-            start_index: self.ante_chunk.start_line_num - 1,
-            lines: self.ante_lines(),
+            start_index: if ante_lines.is_empty() {
+                self.ante_chunk.start_line_num
+            } else {
+                self.ante_chunk.start_line_num - 1
+            },
+            lines: ante_lines,
         };
         let post_chunk = AbstractChunk {
-            start_index: self.post_chunk.start_line_num - 1,
-            lines: self.post_lines(),
+            start_index: if post_lines.is_empty() {
+                self.post_chunk.start_line_num
+            } else {
+                self.post_chunk.start_line_num - 1
+            },
+            lines: post_lines,
         };
         AbstractHunk::new(ante_chunk, post_chunk)
     }
+
+    fn intra_line_changes(&self, granularity: Granularity) -> Vec<IntraLineChange> {
+        // the n'th run of "!" lines in the ante chunk matches the n'th in the post chunk
+        let changed_runs = |chunk: &ContextDiffChunk| {
+            let mut runs: Vec<Vec<usize>> = vec![];
+            let mut in_run = false;
+            for index in chunk.offset + 1..chunk.offset + chunk.numlines {
+                if self.lines[index].starts_with('!') {
+                    if !in_run {
+                        runs.push(vec![]);
+                        in_run = true;
+                    }
+                    runs.last_mut().unwrap().push(index);
+                } else {
+                    in_run = false;
+                }
+            }
+            runs
+        };
+        let mut pairs = vec![];
+        for (mut ante_run, mut post_run) in changed_runs(&self.ante_chunk)
+            .into_iter()
+            .zip(changed_runs(&self.post_chunk))
+        {
+            pair_runs(&mut pairs, &mut ante_run, &mut post_run);
+        }
+        intra_line_changes(&self.lines, &pairs, 2, granularity)
+    }
+
+    // NB: a changed ("!") line in the ante chunk and its counterpart in
+    // the post chunk count as one modification (see ops_diff_stats())
+    fn diff_stats(&self) -> DiffStats {
+        ops_diff_stats(self.indexed_ops().into_iter().map(|(prefix, _)| prefix))
+    }
+
+    fn ante_header_range(&self) -> (usize, usize) {
+        (self.ante_chunk.start_line_num, self.ante_chunk.length)
+    }
+
+    fn post_header_range(&self) -> (usize, usize) {
+        (self.post_chunk.start_line_num, self.post_chunk.length)
+    }
+
+    fn select_lines(&self, line_indices: &[usize]) -> Option<Self> {
+        let mut has_changes = false;
+        let mut ops = vec![];
+        for (prefix, index) in self.indexed_ops() {
+            let is_selected = line_indices.contains(&index);
+            match prefix {
+                '-' if !is_selected => ops.push((' ', self.line_text(index))),
+                '+' if !is_selected => (),
+                _ => {
+                    has_changes |= prefix != ' ';
+                    ops.push((prefix, self.line_text(index)));
+                }
+            }
+        }
+        if has_changes {
+            Some(ContextDiffHunk::from_ops(
+                self.ante_chunk.start_index(),
+                self.post_chunk.start_index(),
+                &terminated_last_line_ops(ops),
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn split(&self) -> Vec<Self> {
+        let ops = self.ops();
+        let ante_start = self.ante_chunk.start_index();
+        let post_start = self.post_chunk.start_index();
+        split_ops(&ops)
+            .into_iter()
+            .map(|(ante_before, post_before, piece_ops)| {
+                ContextDiffHunk::from_ops(
+                    ante_start + ante_before,
+                    post_start + post_before,
+                    piece_ops,
+                )
+            })
+            .collect()
+    }
+
+    fn merge_adjacent(&self, other: &Self) -> Option<Self> {
+        let ops = merged_ops(
+            self.ante_chunk.start_index(),
+            &self.ops(),
+            other.ante_chunk.start_index(),
+            &other.ops(),
+        )?;
+        Some(ContextDiffHunk::from_ops(
+            self.ante_chunk.start_index(),
+            self.post_chunk.start_index(),
+            &ops,
+        ))
+    }
+
+    fn reversed(&self) -> Self {
+        ContextDiffHunk::from_ops(
+            self.post_chunk.start_index(),
+            self.ante_chunk.start_index(),
+            &reversed_ops(&self.ops()),
+        )
+    }
+
+    fn resync(&mut self) {
+        if let Some(index) = self.lines[self.ante_chunk.offset + 1..]
+            .iter()
+            .position(|line| line.starts_with("--- "))
+        {
+            self.post_chunk.offset = self.ante_chunk.offset + 1 + index;
+        }
+        self.ante_chunk.numlines = self.post_chunk.offset - self.ante_chunk.offset;
+        self.post_chunk.numlines = self.lines.len() - self.post_chunk.offset;
+    }
+
+    fn recount(&mut self, post_start_line_num: usize) {
+        self.ante_chunk.length = self.ante_lines().len();
+        if self.ante_chunk.length > 0 {
+            self.ante_chunk.start_line_num = self.ante_chunk.start_line_num.max(1);
+        }
+        self.post_chunk.start_line_num = post_start_line_num;
+        self.post_chunk.length = self.post_lines().len();
+        self.lines[self.ante_chunk.offset] = Line::new(format!(
+            "*** {} ****\n",
+            range_text(self.ante_chunk.start_line_num, self.ante_chunk.length)
+        ));
+        self.lines[self.post_chunk.offset] = Line::new(format!(
+            "--- {} ----\n",
+            range_text(self.post_chunk.start_line_num, self.post_chunk.length)
+        ));
+    }
+}
+
+// Context diffs give the first and last line numbers of a chunk
+// (or just one number if they're the same or the chunk is empty).
+fn range_text(start_line_num: usize, length: usize) -> String {
+    match length {
+        0 | 1 => start_line_num.to_string(),
+        length => format!("{},{}", start_line_num, start_line_num + length - 1),
+    }
+}
+
+fn push_hunk_line(lines: &mut Lines, prefix: char, line: &Line) {
+    if line.ends_with('\n') {
+        lines.push(Line::new(format!("{prefix} {line}")));
+    } else {
+        lines.push(Line::new(format!("{prefix} {line}\n")));
+        lines.push(Line::new("\\ No newline at end of file\n".to_string()));
+    }
+}
+
+impl ContextDiffHunk {
+    // Render a hunk from its lines in unified diff order (i.e. with ' ',
+    // '-' or '+' prefixes) showing runs containing both deletions and
+    // insertions as changes and omitting chunks without any changes.
+    fn from_ops(ante_start_index: usize, post_start_index: usize, ops: &[HunkOp]) -> Self {
+        let mut prefixes: Vec<char> = ops.iter().map(|op| op.0).collect();
+        let mut run_start = 0;
+        for index in 0..=ops.len() {
+            if index == ops.len() || ops[index].0 == ' ' {
+                let run = &ops[run_start..index];
+                if run.iter().any(|op| op.0 == '-') && run.iter().any(|op| op.0 == '+') {
+                    for prefix in prefixes[run_start..index].iter_mut() {
+                        *prefix = '!';
+                    }
+                }
+                run_start = index + 1;
+            }
+        }
+        let chunk_ops = |excluded: char| -> Vec<(char, &Line)> {
+            prefixes
+                .iter()
+                .zip(ops.iter())
+                .filter(|(_, op)| op.0 != excluded)
+                .map(|(prefix, op)| (*prefix, &op.1))
+                .collect()
+        };
+        let ante_ops = chunk_ops('+');
+        let post_ops = chunk_ops('-');
+        let ante_start_line_num = start_line_num(ante_start_index, ante_ops.len());
+        let post_start_line_num = start_line_num(post_start_index, post_ops.len());
+
+        let mut lines = vec![Line::new("***************\n".to_string())];
+        lines.push(Line::new(format!(
+            "*** {} ****\n",
+            range_text(ante_start_line_num, ante_ops.len())
+        )));
+        if ante_ops.iter().any(|(prefix, _)| *prefix != ' ') {
+            for (prefix, line) in ante_ops.iter() {
+                push_hunk_line(&mut lines, *prefix, line);
+            }
+        }
+        let post_offset = lines.len();
+        lines.push(Line::new(format!(
+            "--- {} ----\n",
+            range_text(post_start_line_num, post_ops.len())
+        )));
+        if post_ops.iter().any(|(prefix, _)| *prefix != ' ') {
+            for (prefix, line) in post_ops.iter() {
+                push_hunk_line(&mut lines, *prefix, line);
+            }
+        }
+
+        let ante_chunk = ContextDiffChunk {
+            offset: 1,
+            start_line_num: ante_start_line_num,
+            length: ante_ops.len(),
+            numlines: post_offset - 1,
+        };
+        let post_chunk = ContextDiffChunk {
+            offset: post_offset,
+            start_line_num: post_start_line_num,
+            length: post_ops.len(),
+            numlines: lines.len() - post_offset,
+        };
+        ContextDiffHunk {
+            lines,
+            ante_chunk,
+            post_chunk,
+        }
+    }
+
+    pub fn from_abstract_hunk<A: DiffAlgorithm>(
+        abstract_hunk: &AbstractHunk,
+        algorithm: &A,
+    ) -> Self {
+        let abs_ante_chunk = abstract_hunk.ante_chunk();
+        let abs_post_chunk = abstract_hunk.post_chunk();
+        let ops: Vec<HunkOp> = algorithm
+            .diff_lines(&abs_ante_chunk.lines, &abs_post_chunk.lines)
+            .into_iter()
+            .map(|component| match component {
+                DiffComponent::Deletion(index) => ('-', abs_ante_chunk.lines[index].clone()),
+                DiffComponent::Insertion(index) => ('+', abs_post_chunk.lines[index].clone()),
+                DiffComponent::Unchanged(index, _) => (' ', abs_ante_chunk.lines[index].clone()),
+            })
+            .collect();
+        Self::from_ops(abs_ante_chunk.start_index, abs_post_chunk.start_index, &ops)
+    }
+
+    // The indices of the hunk's (non "\ No newline") lines in unified
+    // diff order paired with their unified diff prefixes
+    pub(crate) fn indexed_ops(&self) -> Vec<(char, usize)> {
+        let chunk_indices = |chunk: &ContextDiffChunk| -> Vec<usize> {
+            (chunk.offset + 1..chunk.offset + chunk.numlines)
+                .filter(|&index| !self.lines[index].starts_with('\\'))
+                .collect()
+        };
+        let ante_indices = chunk_indices(&self.ante_chunk);
+        let post_indices = chunk_indices(&self.post_chunk);
+        let is_context = |index: usize| self.lines[index].starts_with("  ");
+        let mut ops = vec![];
+        // NB: chunks without changes may have been omitted
+        if ante_indices.is_empty() || post_indices.is_empty() {
+            let prefix = if ante_indices.is_empty() { '+' } else { '-' };
+            for index in ante_indices.into_iter().chain(post_indices) {
+                ops.push((if is_context(index) { ' ' } else { prefix }, index));
+            }
+            return ops;
+        }
+        let (mut ante_iter, mut post_iter) = (
+            ante_indices.into_iter().peekable(),
+            post_indices.into_iter().peekable(),
+        );
+        loop {
+            while let Some(index) = ante_iter.next_if(|&index| !is_context(index)) {
+                ops.push(('-', index));
+            }
+            while let Some(index) = post_iter.next_if(|&index| !is_context(index)) {
+                ops.push(('+', index));
+            }
+            // context lines appear in both chunks
+            match (ante_iter.next(), post_iter.next()) {
+                (Some(index), _) | (None, Some(index)) => ops.push((' ', index)),
+                (None, None) => break,
+            }
+        }
+        ops
+    }
+
+    // A "git patch-id" style hash of the hunk's lines which ignores
+    // line numbers and white space
+    pub fn patch_id(&self) -> Vec<u8> {
+        let mut hasher = patch_id_hasher();
+        write_patch_id_ops(&mut hasher, &self.ops());
+        hasher.finish()
+    }
+
+    // The hunk's lines in unified diff order without their prefixes
+    pub(crate) fn ops(&self) -> Vec<HunkOp> {
+        self.indexed_ops()
+            .into_iter()
+            .map(|(prefix, index)| (prefix, self.line_text(index)))
+            .collect()
+    }
+
+    // The text of the line at "index" without its prefix
+    fn line_text(&self, index: usize) -> Line {
+        let text = &self.lines[index][2.min(self.lines[index].len())..];
+        match self.lines.get(index + 1) {
+            Some(next_line) if next_line.starts_with('\\') => {
+                Line::new(text.strip_suffix('\n').unwrap_or(text).to_string())
+            }
+            _ => Line::new(text.to_string()),
+        }
+    }
+}
+
+impl From<&AbstractHunk> for ContextDiffHunk {
+    fn from(abstract_hunk: &AbstractHunk) -> Self {
+        ContextDiffHunk::from_abstract_hunk(abstract_hunk, &IndentHeuristic(Algorithm::default()))
+    }
+}
+
+// Build a context diff from its parts (checking that they make sense)
+pub struct ContextDiffBuilder {
+    ante_pat: PathAndTimestamp,
+    post_pat: PathAndTimestamp,
+    hunks: Vec<AbstractHunk>,
+}
+
+impl ContextDiffBuilder {
+    pub fn new(ante_file_path: &str, post_file_path: &str) -> Self {
+        ContextDiffBuilder {
+            ante_pat: PathAndTimestamp::new(ante_file_path, None),
+            post_pat: PathAndTimestamp::new(post_file_path, None),
+            hunks: vec![],
+        }
+    }
+
+    pub fn ante_time_stamp(mut self, time_stamp: &str) -> Self {
+        self.ante_pat = PathAndTimestamp::new(self.ante_pat.file_path(), Some(time_stamp));
+        self
+    }
+
+    pub fn post_time_stamp(mut self, time_stamp: &str) -> Self {
+        self.post_pat = PathAndTimestamp::new(self.post_pat.file_path(), Some(time_stamp));
+        self
+    }
+
+    pub fn hunk(mut self, hunk: AbstractHunk) -> Self {
+        self.hunks.push(hunk);
+        self
+    }
+
+    pub fn build(&self) -> DiffBuildResult<ContextDiff> {
+        let mut lines = vec![
+            file_header_line("***", self.ante_pat.file_path(), self.ante_pat.time_stamp())?,
+            file_header_line("---", self.post_pat.file_path(), self.post_pat.time_stamp())?,
+        ];
+        check_hunk_order(&self.hunks)?;
+        for hunk in self.hunks.iter() {
+            lines.extend(ContextDiffHunk::from(hunk).lines);
+        }
+        parse_rendered(&lines, |lines| {
+            ContextDiffParser::new().get_diff_at(lines, 0)
+        })
+    }
 }
 
 pub struct ContextDiffParser {
     ante_file_cre: Regex,
     post_file_cre: Regex,
+    ante_spaced_file_cre: Regex,
+    post_spaced_file_cre: Regex,
     hunk_start_cre: Regex,
     hunk_ante_cre: Regex,
     hunk_post_cre: Regex,
@@ -154,12 +559,19 @@ impl Default for ContextDiffParser {
 impl TextDiffParser<ContextDiffHunk> for ContextDiffParser {
     fn new() -> ContextDiffParser {
         let e_ts_re_str = format!("({TIMESTAMP_RE_STR}|{ALT_TIMESTAMP_RE_STR})");
-        let ante_file_cre_str = format!(r"^\*\*\* ({PATH_RE_STR})(\s+{e_ts_re_str})?(\n)?$");
-        let post_file_cre_str = format!(r"^--- ({PATH_RE_STR})(\s+{e_ts_re_str})?(\n)?$");
+        let ante_file_cre_str = format!(r"^\*\*\* ({PATH_RE_STR})(\s+{e_ts_re_str})?\t?(\n)?$");
+        let post_file_cre_str = format!(r"^--- ({PATH_RE_STR})(\s+{e_ts_re_str})?\t?(\n)?$");
+        // NB: like git and diff, paths containing spaces must end with a tab
+        let ante_spaced_file_cre_str =
+            format!(r"^\*\*\* ({SPACED_PATH_RE_STR})\t(\s*{e_ts_re_str})?(\n)?$");
+        let post_spaced_file_cre_str =
+            format!(r"^--- ({SPACED_PATH_RE_STR})\t(\s*{e_ts_re_str})?(\n)?$");
 
         ContextDiffParser {
             ante_file_cre: Regex::new(&ante_file_cre_str).unwrap(),
             post_file_cre: Regex::new(&post_file_cre_str).unwrap(),
+            ante_spaced_file_cre: Regex::new(&ante_spaced_file_cre_str).unwrap(),
+            post_spaced_file_cre: Regex::new(&post_spaced_file_cre_str).unwrap(),
             hunk_start_cre: Regex::new(r"^\*{15}\s*(.*)(\n)?$").unwrap(),
             hunk_ante_cre: Regex::new(r"^\*\*\*\s+(\d+)(,(\d+))?\s+\*\*\*\*\s*(.*)(\n)?$").unwrap(),
             hunk_post_cre: Regex::new(r"^---\s+(\d+)(,(\d+))?\s+----(.*)(\n)?$").unwrap(),
@@ -167,11 +579,15 @@ impl TextDiffParser<ContextDiffHunk> for ContextDiffParser {
     }
 
     fn ante_file_rec<'t>(&self, line: &'t Line) -> Option<Captures<'t>> {
-        self.ante_file_cre.captures(line)
+        self.ante_spaced_file_cre
+            .captures(line)
+            .or_else(|| self.ante_file_cre.captures(line))
     }
 
     fn post_file_rec<'t>(&self, line: &'t Line) -> Option<Captures<'t>> {
-        self.post_file_cre.captures(line)
+        self.post_spaced_file_cre
+            .captures(line)
+            .or_else(|| self.post_file_cre.captures(line))
     }
 
     fn get_hunk_at(
@@ -190,6 +606,9 @@ impl TextDiffParser<ContextDiffHunk> for ContextDiffParser {
         let mut o_post_sal: Option<(usize, usize)> = None;
         let mut post_start_index = index;
         while ante_count < ante_sal.1 {
+            if index >= lines.len() {
+                return Err(DiffParseError::SyntaxError(DiffFormat::Context, index + 1));
+            }
             post_start_index = index;
             o_post_sal = self.get_post_sal_at(lines, index)?;
             if o_post_sal.is_some() {
@@ -199,20 +618,25 @@ impl TextDiffParser<ContextDiffHunk> for ContextDiffParser {
             index += 1;
         }
         if o_post_sal.is_none() {
-            if lines[index].starts_with(r"\ ") {
+            if index < lines.len() && lines[index].starts_with(r"\ ") {
                 index += 1;
             }
             post_start_index = index;
-            o_post_sal = self.get_post_sal_at(lines, index)?;
+            if index < lines.len() {
+                o_post_sal = self.get_post_sal_at(lines, index)?;
+            }
             if o_post_sal.is_none() {
                 return Err(DiffParseError::SyntaxError(DiffFormat::Context, index + 1));
             }
         }
         let post_sal = o_post_sal.unwrap();
+        index = post_start_index + 1;
         while post_count < post_sal.1 {
-            if !(lines[index].starts_with("! ")
-                || lines[index].starts_with("+ ")
-                || lines[index].starts_with(' '))
+            // NB: an omitted post chunk may end the input
+            if index >= lines.len()
+                || !(lines[index].starts_with("! ")
+                    || lines[index].starts_with("+ ")
+                    || lines[index].starts_with(' '))
             {
                 if post_count == 0 {
                     break;
@@ -228,13 +652,66 @@ impl TextDiffParser<ContextDiffHunk> for ContextDiffParser {
         let ante_chunk = ContextDiffChunk {
             offset: ante_start_index - start_index,
             start_line_num: ante_sal.0,
-            _length: ante_sal.1,
+            length: ante_sal.1,
             numlines: post_start_index - ante_start_index,
         };
         let post_chunk = ContextDiffChunk {
             offset: post_start_index - start_index,
             start_line_num: post_sal.0,
-            _length: post_sal.1,
+            length: post_sal.1,
+            numlines: index - post_start_index,
+        };
+        let hunk = ContextDiffHunk {
+            lines: lines[start_index..index].to_vec(),
+            ante_chunk,
+            post_chunk,
+        };
+        Ok(Some(hunk))
+    }
+
+    fn get_uncounted_hunk_at(
+        &self,
+        lines: &[Line],
+        start_index: usize,
+    ) -> DiffParseResult<Option<ContextDiffHunk>> {
+        if !self.hunk_start_cre.is_match(&lines[start_index]) {
+            return Ok(None);
+        }
+        let ante_start_index = start_index + 1;
+        let ante_sal = self.get_ante_sal_at(lines, ante_start_index)?;
+        let mut index = ante_start_index + 1;
+        while index < lines.len() && !self.hunk_post_cre.is_match(&lines[index]) {
+            if !["  ", "- ", "! ", "\\"]
+                .iter()
+                .any(|prefix| lines[index].starts_with(prefix))
+            {
+                return Err(DiffParseError::SyntaxError(DiffFormat::Context, index + 1));
+            }
+            index += 1;
+        }
+        if index >= lines.len() {
+            return Err(DiffParseError::SyntaxError(DiffFormat::Context, index + 1));
+        }
+        let post_start_index = index;
+        let post_sal = self.get_post_sal_at(lines, post_start_index)?.unwrap();
+        index += 1;
+        while index < lines.len()
+            && ["  ", "+ ", "! ", "\\"]
+                .iter()
+                .any(|prefix| lines[index].starts_with(prefix))
+        {
+            index += 1;
+        }
+        let ante_chunk = ContextDiffChunk {
+            offset: ante_start_index - start_index,
+            start_line_num: ante_sal.0,
+            length: ante_sal.1,
+            numlines: post_start_index - ante_start_index,
+        };
+        let post_chunk = ContextDiffChunk {
+            offset: post_start_index - start_index,
+            start_line_num: post_sal.0,
+            length: post_sal.1,
             numlines: index - post_start_index,
         };
         let hunk = ContextDiffHunk {
@@ -248,9 +725,14 @@ impl TextDiffParser<ContextDiffHunk> for ContextDiffParser {
 
 #[cfg(test)]
 mod tests {
-    use crate::context_diff::ContextDiffParser;
+    use crate::context_diff::{ContextDiffBuilder, ContextDiffParser};
+    use crate::diff_stats::DiffStatsCategory;
+    use crate::intra_line::{Granularity, IntraLineChange};
     use crate::lines::{Lines, LinesIfce};
-    use crate::text_diff::TextDiffParser;
+    use crate::text_diff::{HunkDiagnostic, HunkSelection, TextDiffHunk, TextDiffParser};
+    use crate::unified_diff::UnifiedDiffParser;
+    use std::io;
+    use std::ops::Range;
 
     static CONTEXT_DIFF: &str = "*** lao	2002-02-21 23:30:39.942229878 -0800
 --- tzu	2002-02-21 23:30:50.442260588 -0800
@@ -281,6 +763,19 @@ mod tests {
 + The door of all subtleties!
 ";
 
+    static LAO: &str = "The Way that can be told of is not the eternal Way;
+The name that can be named is not the eternal name.
+The Nameless is the origin of Heaven and Earth;
+The Named is the mother of all things.
+Therefore let there always be non-being,
+  so we may see their subtlety,
+And let there always be being,
+  so we may see their outcome.
+The two are the same,
+But after they are produced,
+  they have different names.
+";
+
     #[test]
     fn context_diff_parse() {
         let diff_lines = Lines::from_string(CONTEXT_DIFF);
@@ -289,4 +784,334 @@ mod tests {
         assert!(parser.get_diff_at(&diff_lines, 0).unwrap().is_some());
         assert!(parser.get_diff_at(&diff_lines, 1).unwrap().is_none());
     }
+
+    #[test]
+    fn context_diff_chunk_lines() {
+        // NB: the chunk header lines aren't source lines and diff omits
+        // the chunk that has no changes
+        let ante = Lines::from_string("1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n");
+        let parser = ContextDiffParser::new();
+        for (text, post, trailing_white_space) in [
+            (
+                "*** a\n--- b\n***************\n*** 1,10 ****\n  1\n  2\n  3\n  4\n  5\n  6\n  7\n! 8\n! 9\n  10\n--- 1,10 ----\n  1\n  2\n  3\n+ new\n  4\n  5\n  6\n  7\n! 9 \n  10\n",
+                "1\n2\n3\nnew\n4\n5\n6\n7\n9 \n10\n",
+                true,
+            ),
+            (
+                "*** a\n--- b\n***************\n*** 1,6 ****\n--- 1,7 ----\n  1\n  2\n  3\n+ new \n  4\n  5\n  6\n",
+                "1\n2\n3\nnew \n4\n5\n6\n7\n8\n9\n10\n",
+                true,
+            ),
+            (
+                "*** a\n--- b\n***************\n*** 2,8 ****\n  2\n  3\n  4\n- 5\n  6\n  7\n  8\n--- 2,7 ----\n",
+                "1\n2\n3\n4\n6\n7\n8\n9\n10\n",
+                false,
+            ),
+        ] {
+            let diff_lines = Lines::from_string(text);
+            let diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+            assert_eq!(diff.len(), diff_lines.len());
+            let hunk = &diff.hunks()[0];
+            let ante_start = hunk.ante_chunk.start_line_num() - 1;
+            assert_eq!(
+                hunk.ante_lines(),
+                ante[ante_start..ante_start + hunk.ante_chunk.length()].to_vec()
+            );
+            let post = Lines::from_string(post);
+            let post_start = hunk.post_chunk.start_line_num() - 1;
+            assert_eq!(
+                hunk.post_lines(),
+                post[post_start..post_start + hunk.post_chunk.length()].to_vec()
+            );
+            assert_eq!(hunk.adds_trailing_white_space(), trailing_white_space);
+            let result = diff
+                .abstract_diff()
+                .apply_to_lines(&ante, false, &mut io::sink(), None);
+            assert_eq!(result.failures(), 0);
+            assert_eq!(result.lines(), &post[..]);
+        }
+    }
+
+    #[test]
+    fn context_diff_intra_line_changes() {
+        let diff_lines = Lines::from_string(CONTEXT_DIFF);
+        let parser = ContextDiffParser::new();
+        let diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        assert_eq!(diff.hunks().len(), 2);
+        assert_eq!(diff.hunks()[0].ante_lines().len(), 7);
+        assert_eq!(diff.hunks()[0].post_lines().len(), 6);
+        let changes = diff.hunks()[0].intra_line_changes(Granularity::Character);
+        assert_eq!(
+            changes,
+            vec![IntraLineChange {
+                ante_line_index: 5,
+                ante_spans: vec![Range { start: 6, end: 7 }],
+                post_line_index: 11,
+                post_spans: vec![Range { start: 6, end: 7 }],
+            }]
+        );
+    }
+
+    #[test]
+    fn context_diff_stats() {
+        let diff_lines = Lines::from_string(CONTEXT_DIFF);
+        let parser = ContextDiffParser::new();
+        let diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        let stats = diff.hunks()[0].diff_stats();
+        assert_eq!(stats.count(DiffStatsCategory::Inserted), 1);
+        assert_eq!(stats.count(DiffStatsCategory::Deleted), 2);
+        assert_eq!(stats.count(DiffStatsCategory::Modified), 1);
+        assert_eq!(stats.count(DiffStatsCategory::Unchanged), 4);
+        let stats = diff.diff_stats();
+        assert_eq!(stats.count(DiffStatsCategory::Inserted), 4);
+        assert_eq!(stats.count(DiffStatsCategory::Deleted), 2);
+        assert_eq!(stats.count(DiffStatsCategory::Modified), 1);
+        assert_eq!(stats.count(DiffStatsCategory::Unchanged), 7);
+
+        // the same changes in unified format have the same statistics
+        let unified_text = "--- lao
++++ tzu
+@@ -1,7 +1,6 @@
+-The Way that can be told of is not the eternal Way;
+-The name that can be named is not the eternal name.
+ The Nameless is the origin of Heaven and Earth;
+-The Named is the mother of all things.
++The named is the mother of all things.
++
+ Therefore let there always be non-being,
+   so we may see their subtlety,
+ And let there always be being,
+@@ -9,3 +8,6 @@
+ The two are the same,
+ But after they are produced,
+   they have different names.
++They both may be called deep and profound.
++Deeper and more profound,
++The door of all subtleties!
+";
+        let unified_diff = UnifiedDiffParser::new()
+            .get_diff_at(&Lines::from_string(unified_text), 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            unified_diff.hunks()[0].diff_stats(),
+            diff.hunks()[0].diff_stats()
+        );
+        assert_eq!(unified_diff.diff_stats(), stats);
+    }
+
+    #[test]
+    fn context_diff_recontext() {
+        let diff_lines = Lines::from_string(CONTEXT_DIFF);
+        let parser = ContextDiffParser::new();
+        let diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        let lao = Lines::from_string(LAO);
+
+        let diff = diff.with_context(&lao, 0).unwrap();
+        assert_eq!(diff.hunks().len(), 3);
+        let diff = diff.with_context(&lao, 1).unwrap();
+        assert_eq!(diff.hunks().len(), 2);
+        assert_eq!(*diff.hunks()[0].lines[1], "*** 1,5 ****\n");
+        assert_eq!(*diff.hunks()[1].lines[1], "*** 11 ****\n");
+        assert_eq!(*diff.hunks()[1].lines[2], "--- 10,13 ----\n");
+
+        let diff_lines: Lines = diff.iter().cloned().collect();
+        let reparsed = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        assert_eq!(reparsed.len(), diff_lines.len());
+        assert_eq!(reparsed.hunks()[1].post_lines().len(), 4);
+
+        let diff = diff.with_expanded_hunk(&lao, 1, 6, 0).unwrap();
+        assert_eq!(diff.hunks().len(), 1);
+        assert_eq!(*diff.hunks()[0].lines[1], "*** 1,11 ****\n");
+    }
+
+    #[test]
+    fn context_diff_recount() {
+        // hand edited to add a line to the second hunk
+        let edited = CONTEXT_DIFF.replace(
+            "+ They both may be called deep and profound.\n",
+            "+ They both may be called deep and profound.\n+ Edited in.\n",
+        );
+        let diff_lines = Lines::from_string(&edited);
+        let parser = ContextDiffParser::new();
+        let mut diff = parser
+            .get_uncounted_diff_at(&diff_lines, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(diff.len(), diff_lines.len());
+        assert_eq!(
+            diff.validate(),
+            vec![HunkDiagnostic::PostLengthMismatch {
+                hunk_index: 1,
+                header: 6,
+                actual: 7,
+            }]
+        );
+
+        diff.recount();
+        assert!(diff.validate().is_empty());
+        assert_eq!(*diff.hunks()[1].lines[1], "*** 9,11 ****\n");
+        assert_eq!(*diff.hunks()[1].lines[2], "--- 8,14 ----\n");
+        let diff_lines: Lines = diff.iter().cloned().collect();
+        let reparsed = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        assert_eq!(reparsed.len(), diff_lines.len());
+        assert_eq!(reparsed.hunks()[1].post_lines().len(), 7);
+    }
+
+    #[test]
+    fn context_diff_edit_hunk() {
+        let diff_lines = Lines::from_string(CONTEXT_DIFF);
+        let parser = ContextDiffParser::new();
+        let mut diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        diff.edit_hunk(0, |hunk| {
+            // drop the first removed line
+            hunk.lines.remove(2);
+        });
+        assert_eq!(*diff.hunks()[0].lines[1], "*** 1,6 ****\n");
+        assert_eq!(*diff.hunks()[0].lines[8], "--- 1,6 ----\n");
+        assert_eq!(*diff.hunks()[1].lines[2], "--- 9,14 ----\n");
+        assert!(diff.validate().is_empty());
+        let diff_lines: Lines = diff.iter().cloned().collect();
+        let reparsed = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        assert_eq!(reparsed.len(), diff_lines.len());
+    }
+
+    #[test]
+    fn context_diff_builder() {
+        let diff_lines = Lines::from_string(CONTEXT_DIFF);
+        let parser = ContextDiffParser::new();
+        let expected = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        let mut builder = ContextDiffBuilder::new("lao", "tzu")
+            .ante_time_stamp("2002-02-21 23:30:39.942229878 -0800")
+            .post_time_stamp("2002-02-21 23:30:50.442260588 -0800");
+        for hunk in expected.hunks().iter() {
+            builder = builder.hunk(hunk.get_abstract_diff_hunk());
+        }
+        let diff = builder.build().unwrap();
+        assert!(diff.iter().eq(expected.iter()));
+        assert_eq!(diff.header().post_pat, expected.header().post_pat);
+        assert!(ContextDiffBuilder::new("", "tzu").build().is_err());
+    }
+
+    #[test]
+    fn context_diff_selection() {
+        let diff_lines = Lines::from_string(CONTEXT_DIFF);
+        let parser = ContextDiffParser::new();
+        let diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        let lao = Lines::from_string(LAO);
+
+        // the removal of the first two lines and the "Named" to "named" change
+        let selection = HunkSelection::new().lines(0, &[2, 3, 5, 11]);
+        let selected = diff.selected(&selection).unwrap();
+        assert!(selected.validate().is_empty());
+        let expected = "***************\n\
+                        *** 1,7 ****\n\
+                        - The Way that can be told of is not the eternal Way;\n\
+                        - The name that can be named is not the eternal name.\n\
+                        \x20 The Nameless is the origin of Heaven and Earth;\n\
+                        ! The Named is the mother of all things.\n\
+                        \x20 Therefore let there always be non-being,\n\
+                        \x20   so we may see their subtlety,\n\
+                        \x20 And let there always be being,\n\
+                        --- 1,5 ----\n\
+                        \x20 The Nameless is the origin of Heaven and Earth;\n\
+                        ! The named is the mother of all things.\n\
+                        \x20 Therefore let there always be non-being,\n\
+                        \x20   so we may see their subtlety,\n\
+                        \x20 And let there always be being,\n";
+        let text: String = selected.hunks()[0].iter().map(|l| l.as_str()).collect();
+        assert_eq!(text, expected);
+
+        let diff_lines: Lines = selected.iter().cloned().collect();
+        let reparsed = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        let result = reparsed
+            .abstract_diff()
+            .apply_to_lines(&lao, false, &mut io::sink(), None);
+        assert_eq!(result.successes(), 1);
+        let expected = LAO
+            .replacen(
+                "The Way that can be told of is not the eternal Way;\n",
+                "",
+                1,
+            )
+            .replacen(
+                "The name that can be named is not the eternal name.\n",
+                "",
+                1,
+            )
+            .replace("The Named", "The named");
+        assert_eq!(result.lines(), &Lines::from_string(&expected)[..]);
+
+        // a deselected removal of a last line without a newline
+        let diff_lines = Lines::from_string(
+            "*** a/file\n--- b/file\n***************\n*** 1,2 ****\n  x\n! a\n\\ No newline at end of file\n--- 1,2 ----\n  x\n! b\n",
+        );
+        let diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        let selected = diff.selected(&HunkSelection::new().lines(0, &[7])).unwrap();
+        assert!(selected.validate().is_empty());
+        let text: String = selected.hunks()[0].iter().map(|l| l.as_str()).collect();
+        let expected = "***************\n\
+                        *** 1,2 ****\n\
+                        \x20 x\n\
+                        ! a\n\
+                        \\ No newline at end of file\n\
+                        --- 1,3 ----\n\
+                        \x20 x\n\
+                        ! a\n\
+                        ! b\n";
+        assert_eq!(text, expected);
+        let result = selected.abstract_diff().apply_to_lines(
+            &Lines::from_string("x\na"),
+            false,
+            &mut io::sink(),
+            None,
+        );
+        assert_eq!(result.successes(), 1);
+        assert_eq!(result.lines(), &Lines::from_string("x\na\nb\n")[..]);
+    }
+
+    #[test]
+    fn context_diff_split_and_merge() {
+        let diff_lines = Lines::from_string(CONTEXT_DIFF);
+        let parser = ContextDiffParser::new();
+        let mut diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+
+        assert_eq!(diff.split_hunk(0), 2);
+        assert_eq!(*diff.hunks()[0].lines[1], "*** 1,3 ****\n");
+        assert_eq!(*diff.hunks()[0].lines[5], "--- 1 ----\n");
+        assert_eq!(*diff.hunks()[1].lines[1], "*** 4,7 ****\n");
+        assert_eq!(*diff.hunks()[1].lines[6], "--- 2,6 ----\n");
+        // NB: the context between the pieces is shared out between them
+        assert!(diff.validate().is_empty());
+
+        diff.merge_adjacent_hunks();
+        assert_eq!(diff.hunks().len(), 2);
+        assert!(diff.iter().eq(diff_lines.iter()));
+    }
+
+    #[test]
+    fn context_diff_reversed() {
+        let diff_lines = Lines::from_string(CONTEXT_DIFF);
+        let parser = ContextDiffParser::new();
+        let diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        let reversed = diff.reversed();
+        assert_eq!(
+            *reversed.header().lines[0],
+            "*** tzu\t2002-02-21 23:30:50.442260588 -0800\n"
+        );
+        assert_eq!(*reversed.hunks()[0].lines[1], "*** 1,6 ****\n");
+        assert!(reversed.validate().is_empty());
+
+        let lao = Lines::from_string(LAO);
+        let post = diff
+            .abstract_diff()
+            .apply_to_lines(&lao, false, &mut io::sink(), None);
+        let result =
+            reversed
+                .abstract_diff()
+                .apply_to_lines(post.lines(), false, &mut io::sink(), None);
+        assert_eq!(result.successes(), 2);
+        assert_eq!(result.lines(), &lao);
+        assert!(reversed.reversed().iter().eq(diff_lines.iter()));
+    }
 }
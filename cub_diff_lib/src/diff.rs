@@ -1,18 +1,80 @@
 // Copyright 2019 Peter Williams <pwil3058@gmail.com>
 
+use std::fmt;
 use std::io::Write;
 use std::sync::Arc;
 
 use crypto_hash::{Algorithm, Hasher};
 
 use crate::context_diff::{ContextDiff, ContextDiffParser};
+use crate::diff_stats::{DiffStatChange, DiffStatEntry, DiffStats, DiffStatsCategory};
 use crate::git_binary_diff::{GitBinaryDiff, GitBinaryDiffParser};
-use crate::lines::Line;
+use crate::lines::{Line, Lines};
 use crate::preamble::{GitPreamble, Preamble, PreambleIfce, PreambleParser};
-use crate::text_diff::{DiffParseResult, TextDiffParser};
+use crate::text_diff::{
+    add_patch_id, parse_rendered, patch_id_hasher, write_patch_id_line, write_patch_id_ops,
+    DiffBuildError, DiffBuildResult, DiffParseError, DiffParseResult, HunkSelection,
+    TextDiffParser,
+};
 use crate::unified_diff::{UnifiedDiff, UnifiedDiffParser};
-use crate::MultiListIter;
+use crate::{requoted_path, DiffFormat, MultiListIter};
+
+// A "git patch-id --stable" compatible hash of the changes made by
+// "diff_pluses" which ignores line numbers, white space and (except for
+// binary diffs) object hashes so that the same change made at a different
+// offset or on a different branch has the same id.  The hashes of each
+// file's changes are summed (see add_patch_id()) with git's quirks: a
+// binary diff is identified by its object hashes and the "diff --git"
+// line of the file following it is skipped (or, if it's the last file,
+// the hash of nothing is added to the sum) and a diff with only a
+// preamble is hashed together with the file that follows it.
+pub(crate) fn patch_id(diff_pluses: &[DiffPlus]) -> Vec<u8> {
+    let mut sum = vec![0u8; 20];
+    if diff_pluses.is_empty() {
+        return sum;
+    }
+    fn flush(sum: &mut [u8], hasher: &mut Hasher) {
+        add_patch_id(sum, &hasher.finish());
+        *hasher = patch_id_hasher();
+    }
+    let mut hasher = patch_id_hasher();
+    let mut skip_diff_line = false;
+    let mut unflushed = false;
+    for diff_plus in diff_pluses.iter() {
+        diff_plus.write_patch_id_preamble(&mut hasher, skip_diff_line);
+        skip_diff_line = false;
+        unflushed = true;
+        match &diff_plus.diff {
+            Diff::GitBinary(_) => {
+                let index = diff_plus.git_preamble().and_then(|p| p.get_extra("index"));
+                if let Some(index) = index {
+                    let hashes = index.split_whitespace().next().unwrap_or_default();
+                    let (ante_hash, post_hash) = hashes.split_once("..").unwrap_or_default();
+                    hasher
+                        .write_all(ante_hash.as_bytes())
+                        .expect("hasher blew up!!!");
+                    hasher
+                        .write_all(post_hash.as_bytes())
+                        .expect("hasher blew up!!!");
+                }
+                flush(&mut sum, &mut hasher);
+                skip_diff_line = true;
+            }
+            Diff::GitPreambleOnly(_) => continue,
+            diff => {
+                diff.write_patch_id(&mut hasher);
+                flush(&mut sum, &mut hasher);
+                unflushed = false;
+            }
+        }
+    }
+    if unflushed {
+        flush(&mut sum, &mut hasher);
+    }
+    sum
+}
 
+#[derive(Clone)]
 pub enum Diff {
     Unified(UnifiedDiff),
     Context(ContextDiff),
@@ -77,6 +139,181 @@ impl Diff {
             _ => false,
         }
     }
+
+    pub fn reversed(&self) -> Diff {
+        match self {
+            Diff::Unified(diff) => Diff::Unified(diff.reversed()),
+            Diff::Context(diff) => Diff::Context(diff.reversed()),
+            Diff::GitBinary(diff) => Diff::GitBinary(diff.reversed()),
+            Diff::GitPreambleOnly(preamble) => Diff::GitPreambleOnly(preamble.reversed()),
+        }
+    }
+
+    // Write the file header lines and hunks (the parts of a text diff
+    // that contribute to its patch id) to "hasher".  NB: git hashes the
+    // "---"/"+++" lines as they are (quotes and all) so context diffs'
+    // paths are hashed as they'd appear in the equivalent unified diff.
+    fn write_patch_id(&self, hasher: &mut Hasher) {
+        match self {
+            Diff::Unified(diff) => {
+                for line in diff.header().lines.iter() {
+                    write_patch_id_line(hasher, line);
+                }
+                for hunk in diff.hunks().iter() {
+                    write_patch_id_ops(hasher, &hunk.ops());
+                }
+            }
+            Diff::Context(diff) => {
+                write_patch_id_line(
+                    hasher,
+                    &format!("---{}", requoted_path(diff.header().ante_pat.file_path())),
+                );
+                write_patch_id_line(
+                    hasher,
+                    &format!("+++{}", requoted_path(diff.header().post_pat.file_path())),
+                );
+                for hunk in diff.hunks().iter() {
+                    write_patch_id_ops(hasher, &hunk.ops());
+                }
+            }
+            Diff::GitBinary(_) | Diff::GitPreambleOnly(_) => (),
+        }
+    }
+
+    // The diff equivalent to applying this diff and then "later" (see
+    // TextDiff::squashed()) or None if there are no changes to the file's
+    // contents left.  Binary diffs can only be squashed with preamble only
+    // diffs.
+    pub fn squashed(&self, later: &Diff, context_len: usize) -> DiffParseResult<Option<Diff>> {
+        let diff = match (self, later) {
+            (Diff::Unified(diff), Diff::Unified(later)) => {
+                Diff::Unified(diff.squashed(later, context_len)?)
+            }
+            (Diff::Unified(diff), Diff::Context(later)) => {
+                Diff::Unified(diff.squashed(later, context_len)?)
+            }
+            (Diff::Context(diff), Diff::Unified(later)) => {
+                Diff::Context(diff.squashed(later, context_len)?)
+            }
+            (Diff::Context(diff), Diff::Context(later)) => {
+                Diff::Context(diff.squashed(later, context_len)?)
+            }
+            (Diff::GitPreambleOnly(_), Diff::GitPreambleOnly(_)) => return Ok(None),
+            (Diff::GitPreambleOnly(_), diff) | (diff, Diff::GitPreambleOnly(_)) => diff.clone(),
+            _ => {
+                return Err(DiffParseError::UnexpectedInput(
+                    DiffFormat::GitBinary,
+                    "binary diffs can't be squashed".to_string(),
+                ))
+            }
+        };
+        Ok(if diff.has_changes() { Some(diff) } else { None })
+    }
+
+    // As for squashed() but with text diffs being combined by applying
+    // them to "lines" (the contents of the file that this diff applies
+    // to) so that diffs that don't fit exactly can be squashed (see
+    // TextDiff::squashed_using()).
+    pub fn squashed_using(
+        &self,
+        later: &Diff,
+        lines: &[Line],
+        context_len: usize,
+    ) -> DiffParseResult<Option<Diff>> {
+        let mismatch = |format| {
+            DiffParseError::UnexpectedInput(
+                format,
+                "the diffs don't apply to the file's contents".to_string(),
+            )
+        };
+        let diff = match (self, later) {
+            (Diff::Unified(diff), Diff::Unified(later)) => Diff::Unified(
+                diff.squashed_using(later, lines, context_len)
+                    .ok_or_else(|| mismatch(DiffFormat::Unified))?,
+            ),
+            (Diff::Unified(diff), Diff::Context(later)) => Diff::Unified(
+                diff.squashed_using(later, lines, context_len)
+                    .ok_or_else(|| mismatch(DiffFormat::Unified))?,
+            ),
+            (Diff::Context(diff), Diff::Unified(later)) => Diff::Context(
+                diff.squashed_using(later, lines, context_len)
+                    .ok_or_else(|| mismatch(DiffFormat::Context))?,
+            ),
+            (Diff::Context(diff), Diff::Context(later)) => Diff::Context(
+                diff.squashed_using(later, lines, context_len)
+                    .ok_or_else(|| mismatch(DiffFormat::Context))?,
+            ),
+            _ => return self.squashed(later, context_len),
+        };
+        Ok(if diff.has_changes() { Some(diff) } else { None })
+    }
+
+    fn has_changes(&self) -> bool {
+        match self {
+            Diff::Unified(diff) => !diff.hunks().is_empty(),
+            Diff::Context(diff) => !diff.hunks().is_empty(),
+            _ => true,
+        }
+    }
+
+    // The diffs (later', self') that make the same changes in the opposite
+    // order (see TextDiff::commuted()) or None if they don't commute.  Only
+    // text diffs can be commuted.
+    pub fn commuted(
+        &self,
+        later: &Diff,
+        context_len: usize,
+    ) -> DiffParseResult<Option<(Diff, Diff)>> {
+        let commuted = match (self, later) {
+            (Diff::Unified(diff), Diff::Unified(later)) => diff
+                .commuted(later, context_len)?
+                .map(|(later, diff)| (Diff::Unified(later), Diff::Unified(diff))),
+            (Diff::Unified(diff), Diff::Context(later)) => diff
+                .commuted(later, context_len)?
+                .map(|(later, diff)| (Diff::Context(later), Diff::Unified(diff))),
+            (Diff::Context(diff), Diff::Unified(later)) => diff
+                .commuted(later, context_len)?
+                .map(|(later, diff)| (Diff::Unified(later), Diff::Context(diff))),
+            (Diff::Context(diff), Diff::Context(later)) => diff
+                .commuted(later, context_len)?
+                .map(|(later, diff)| (Diff::Context(later), Diff::Context(diff))),
+            _ => None,
+        };
+        Ok(commuted)
+    }
+
+    // The number that line "line_num" of the ante file has in the post
+    // file (or vice versa if "reverse") or None if the diff deletes (or
+    // replaces) it.  The lines of binary files can't be mapped.
+    pub fn mapped_line_num(&self, line_num: usize, reverse: bool) -> Option<usize> {
+        match self {
+            Diff::Unified(diff) if reverse => diff.ante_line_num(line_num),
+            Diff::Unified(diff) => diff.post_line_num(line_num),
+            Diff::Context(diff) if reverse => diff.ante_line_num(line_num),
+            Diff::Context(diff) => diff.post_line_num(line_num),
+            Diff::GitBinary(_) => None,
+            Diff::GitPreambleOnly(_) if line_num == 0 => None,
+            Diff::GitPreambleOnly(_) => Some(line_num),
+        }
+    }
+
+    // Binary diffs and those with only a preamble have no line counts
+    pub fn diff_stats(&self) -> DiffStats {
+        match self {
+            Diff::Unified(diff) => diff.diff_stats(),
+            Diff::Context(diff) => diff.diff_stats(),
+            Diff::GitBinary(_) | Diff::GitPreambleOnly(_) => DiffStats::default(),
+        }
+    }
+
+    // Only text diffs can be partially selected
+    pub fn selected(&self, selection: &HunkSelection) -> Option<Diff> {
+        match self {
+            Diff::Unified(diff) => diff.selected(selection).map(Diff::Unified),
+            Diff::Context(diff) => diff.selected(selection).map(Diff::Context),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -112,6 +349,7 @@ impl DiffParser {
     }
 }
 
+#[derive(Clone)]
 pub struct DiffPlus {
     preamble: Option<Preamble>,
     diff: Diff,
@@ -146,6 +384,10 @@ impl DiffPlus {
         &self.diff
     }
 
+    pub fn diff_mut(&mut self) -> &mut Diff {
+        &mut self.diff
+    }
+
     pub fn get_ante_file_path(&self, strip_level: usize) -> String {
         if let Some(ref preamble) = self.preamble {
             preamble.get_ante_file_path(strip_level)
@@ -177,6 +419,176 @@ impl DiffPlus {
         self.diff.adds_trailing_white_space()
     }
 
+    pub fn diff_stats(&self) -> DiffStats {
+        self.diff.diff_stats()
+    }
+
+    // The change as it would be shown in a diffstat summary
+    pub fn diff_stat_change(&self) -> DiffStatChange {
+        match &self.diff {
+            Diff::GitBinary(diff) => DiffStatChange::Binary(diff.sizes().ok()),
+            _ => {
+                let stats = self.diff_stats();
+                let modified = stats.count(DiffStatsCategory::Modified);
+                let insertions = stats.count(DiffStatsCategory::Inserted) + modified;
+                let deletions = stats.count(DiffStatsCategory::Deleted) + modified;
+                DiffStatChange::Text {
+                    changes: insertions + deletions,
+                    insertions,
+                    deletions,
+                }
+            }
+        }
+    }
+
+    pub(crate) fn diff_stat_entry(&self, strip_level: usize) -> DiffStatEntry {
+        let (ante_file_path, post_file_path) = if self.git_preamble().is_some() {
+            (
+                self.get_ante_file_path(strip_level),
+                self.get_post_file_path(strip_level),
+            )
+        } else {
+            let file_path = self.get_file_path(strip_level);
+            (file_path.clone(), file_path)
+        };
+        DiffStatEntry::new(ante_file_path, post_file_path, self.diff_stat_change())
+    }
+
+    // A diff plus containing only the selected changes (see TextDiff::selected())
+    pub fn selected(&self, selection: &HunkSelection) -> Option<DiffPlus> {
+        Some(DiffPlus {
+            preamble: self.preamble.clone(),
+            diff: self.diff.selected(selection)?,
+        })
+    }
+
+    // The diff plus that undoes this one's changes
+    pub fn reversed(&self) -> DiffPlus {
+        DiffPlus {
+            preamble: self.preamble.as_ref().map(|preamble| preamble.reversed()),
+            diff: self.diff.reversed(),
+        }
+    }
+
+    pub(crate) fn git_preamble(&self) -> Option<&GitPreamble> {
+        match (&self.preamble, &self.diff) {
+            (Some(Preamble::Git(preamble)), _) => Some(preamble),
+            (None, Diff::GitPreambleOnly(preamble)) => Some(preamble),
+            _ => None,
+        }
+    }
+
+    // The diff plus equivalent to applying this one and then "later" (see
+    // Diff::squashed()) or None if "later" undoes this one's changes.
+    // NB: non git preambles are dropped as their paths may no longer apply.
+    pub fn squashed(
+        &self,
+        later: &DiffPlus,
+        context_len: usize,
+    ) -> DiffParseResult<Option<DiffPlus>> {
+        self.squashed_by(later, |diff, later_diff| {
+            diff.squashed(later_diff, context_len)
+        })
+    }
+
+    // As for squashed() but using "lines" (the contents of the file that
+    // this diff applies to) to combine the diffs (see Diff::squashed_using())
+    pub fn squashed_using(
+        &self,
+        later: &DiffPlus,
+        lines: &[Line],
+        context_len: usize,
+    ) -> DiffParseResult<Option<DiffPlus>> {
+        self.squashed_by(later, |diff, later_diff| {
+            diff.squashed_using(later_diff, lines, context_len)
+        })
+    }
+
+    fn squashed_by<F>(&self, later: &DiffPlus, squash: F) -> DiffParseResult<Option<DiffPlus>>
+    where
+        F: FnOnce(&Diff, &Diff) -> DiffParseResult<Option<Diff>>,
+    {
+        let preamble = match (self.git_preamble(), later.git_preamble()) {
+            (Some(preamble), Some(later)) => match preamble.squashed(later) {
+                Some(preamble) => Some(preamble),
+                None => return Ok(None),
+            },
+            _ => None,
+        };
+        let diff = squash(&self.diff, &later.diff)?;
+        let diff_plus = match (preamble, diff) {
+            (Some(preamble), Some(diff)) => Some(DiffPlus {
+                preamble: Some(Preamble::Git(preamble)),
+                diff,
+            }),
+            (Some(preamble), None) if preamble.iter_extras().any(|(name, _)| name != "index") => {
+                Some(DiffPlus {
+                    preamble: None,
+                    diff: Diff::GitPreambleOnly(preamble),
+                })
+            }
+            (None, Some(diff)) => Some(DiffPlus {
+                preamble: None,
+                diff,
+            }),
+            _ => None,
+        };
+        Ok(diff_plus)
+    }
+
+    // The diff pluses (later', self') that make the same changes in the
+    // opposite order (see Diff::commuted()) or None if they don't commute.
+    // Diffs that create, delete, rename, copy or change the mode of the
+    // file don't commute.  NB: the preambles are kept as they are.
+    pub fn commuted(
+        &self,
+        later: &DiffPlus,
+        context_len: usize,
+    ) -> DiffParseResult<Option<(DiffPlus, DiffPlus)>> {
+        let changes_contents_only = |diff_plus: &DiffPlus| match diff_plus.git_preamble() {
+            Some(preamble) => preamble.iter_extras().all(|(name, _)| name == "index"),
+            None => true,
+        };
+        if !changes_contents_only(self) || !changes_contents_only(later) {
+            return Ok(None);
+        }
+        let commuted = self.diff.commuted(&later.diff, context_len)?;
+        Ok(commuted.map(|(later_diff, diff)| {
+            (
+                DiffPlus {
+                    preamble: later.preamble.clone(),
+                    diff: later_diff,
+                },
+                DiffPlus {
+                    preamble: self.preamble.clone(),
+                    diff,
+                },
+            )
+        }))
+    }
+
+    // The "git patch-id --stable" id of a patch containing only this diff
+    // (see patch_id())
+    pub fn patch_id(&self) -> Vec<u8> {
+        patch_id(std::slice::from_ref(self))
+    }
+
+    // Write the lines of the preamble (less its "index" line and, if
+    // "skip_diff_line" is true, its "diff --git" line) to "hasher"
+    fn write_patch_id_preamble(&self, hasher: &mut Hasher, skip_diff_line: bool) {
+        let preamble_lines = match (&self.preamble, &self.diff) {
+            (Some(preamble), _) => preamble.iter(),
+            (None, Diff::GitPreambleOnly(preamble)) => preamble.iter(),
+            _ => [].iter(),
+        };
+        for line in preamble_lines
+            .skip(if skip_diff_line { 1 } else { 0 })
+            .filter(|line| !line.starts_with("index "))
+        {
+            write_patch_id_line(hasher, line);
+        }
+    }
+
     pub fn hash_digest(&self) -> Vec<u8> {
         let mut hasher = Hasher::new(Algorithm::SHA256);
         if let Some(preamble) = &self.preamble {
@@ -195,6 +607,87 @@ impl DiffPlus {
     }
 }
 
+impl fmt::Display for DiffPlus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.iter() {
+            write!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+// Put a diff together with its preamble (checking that they agree)
+#[derive(Default)]
+pub struct DiffPlusBuilder {
+    preamble: Option<Preamble>,
+    diff: Option<Diff>,
+}
+
+impl DiffPlusBuilder {
+    pub fn new() -> Self {
+        DiffPlusBuilder::default()
+    }
+
+    pub fn preamble(mut self, preamble: Preamble) -> Self {
+        self.preamble = Some(preamble);
+        self
+    }
+
+    pub fn diff(mut self, diff: Diff) -> Self {
+        self.diff = Some(diff);
+        self
+    }
+
+    fn check_paths(&self, preamble: &Preamble, diff: &Diff) -> DiffBuildResult<()> {
+        let pairs = [
+            (preamble.get_ante_file_path(0), diff.get_ante_file_path(0)),
+            (preamble.get_post_file_path(0), diff.get_post_file_path(0)),
+        ];
+        for (expected, found) in pairs.iter() {
+            if let Some(found) = found {
+                if found != expected && found != "/dev/null" {
+                    return Err(DiffBuildError::PathMismatch(
+                        expected.to_string(),
+                        found.to_string(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn build(&self) -> DiffBuildResult<DiffPlus> {
+        let mut lines: Lines = vec![];
+        match (&self.preamble, &self.diff) {
+            (None, None) | (Some(Preamble::Diff(_)), None) => {
+                return Err(DiffBuildError::MissingDiff)
+            }
+            (Some(_), Some(Diff::GitPreambleOnly(_))) => {
+                return Err(DiffBuildError::Conflict(
+                    "a preamble only diff can't have another preamble".to_string(),
+                ))
+            }
+            (None, Some(Diff::GitBinary(_)))
+            | (Some(Preamble::Diff(_)), Some(Diff::GitBinary(_))) => {
+                return Err(DiffBuildError::Conflict(
+                    "binary diffs need a git preamble".to_string(),
+                ))
+            }
+            (Some(preamble), Some(diff)) => self.check_paths(preamble, diff)?,
+            _ => (),
+        }
+        if let Some(preamble) = &self.preamble {
+            lines.extend(preamble.iter().cloned());
+        }
+        if let Some(diff) = &self.diff {
+            lines.extend(diff.iter().cloned());
+        }
+        parse_rendered(&lines, |lines| {
+            DiffPlusParser::new().get_diff_plus_at(lines, 0)
+        })
+    }
+}
+
 #[derive(Default)]
 pub struct DiffPlusParser {
     preamble_parser: PreambleParser,
@@ -262,6 +755,8 @@ mod tests {
     use std::path::Path;
 
     use crate::lines::*;
+    use crate::preamble::GitPreambleBuilder;
+    use crate::unified_diff::UnifiedDiffGenerator;
 
     #[test]
     fn get_diff_plus_at_works_for_text_diffs() {
@@ -303,4 +798,46 @@ mod tests {
             assert!(diff.iter().count() == diff.len());
         }
     }
+
+    #[test]
+    fn diff_plus_builder_works() {
+        let ante_lines = Lines::from_string("one\ntwo\nthree\n");
+        let post_lines = Lines::from_string("one\n2\nthree\n");
+        let diff = UnifiedDiffGenerator::default()
+            .generate("a/file", "b/file", &ante_lines, &post_lines)
+            .unwrap();
+        let preamble = GitPreambleBuilder::new("file", "file")
+            .index("6826c6c", "a48404a", Some(0o100644))
+            .build()
+            .unwrap();
+        let expected: Lines = preamble.iter().chain(diff.iter()).cloned().collect();
+        let diff_plus = DiffPlusBuilder::new()
+            .preamble(Preamble::Git(preamble))
+            .diff(Diff::Unified(diff))
+            .build()
+            .unwrap();
+        assert!(diff_plus.iter().eq(expected.iter()));
+        assert!(matches!(diff_plus.preamble(), Some(Preamble::Git(_))));
+        assert!(matches!(diff_plus.diff(), Diff::Unified(_)));
+
+        let parsed = DiffPlusParser::new()
+            .get_diff_plus_at(&expected, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(parsed.hash_digest(), diff_plus.hash_digest());
+
+        let diff = UnifiedDiffGenerator::default()
+            .generate("a/other", "b/other", &ante_lines, &post_lines)
+            .unwrap();
+        let preamble = GitPreambleBuilder::new("file", "file").build().unwrap();
+        let result = DiffPlusBuilder::new()
+            .preamble(Preamble::Git(preamble))
+            .diff(Diff::Unified(diff))
+            .build();
+        assert!(matches!(result, Err(DiffBuildError::PathMismatch(_, _))));
+        assert!(matches!(
+            DiffPlusBuilder::new().build(),
+            Err(DiffBuildError::MissingDiff)
+        ));
+    }
 }
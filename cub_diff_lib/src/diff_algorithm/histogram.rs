@@ -0,0 +1,143 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Histogram diff (as used by git and JGit): anchor the diff on the
+//! common region containing the least frequently occurring items and
+//! recurse either side of it, falling back to Myers if every candidate
+//! item occurs too frequently.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Range;
+
+use super::{common_prefix_len, common_suffix_len, myers, push_changed, push_unchanged};
+use super::{DiffAlgorithm, DiffComponent};
+
+// Items occurring more often than this in the ante range are not used as anchors
+const MAX_CHAIN_LEN: usize = 64;
+
+pub struct Histogram;
+
+impl DiffAlgorithm for Histogram {
+    fn diff<T: Eq + Hash>(&self, ante: &[T], post: &[T]) -> Vec<DiffComponent> {
+        let mut components = vec![];
+        diff_ranges(ante, 0..ante.len(), post, 0..post.len(), &mut components);
+        components
+    }
+}
+
+struct Region {
+    ante_start: usize,
+    post_start: usize,
+    len: usize,
+    count: usize,
+}
+
+fn find_lowest_occurrence_region<T: Eq + Hash>(
+    ante: &[T],
+    ante_range: Range<usize>,
+    post: &[T],
+    post_range: Range<usize>,
+) -> Option<Region> {
+    let mut histogram: HashMap<&T, Vec<usize>> = HashMap::new();
+    for index in ante_range.clone() {
+        histogram.entry(&ante[index]).or_default().push(index);
+    }
+    let mut best: Option<Region> = None;
+    for post_index in post_range.clone() {
+        let positions = match histogram.get(&post[post_index]) {
+            Some(positions) if positions.len() <= MAX_CHAIN_LEN => positions,
+            _ => continue,
+        };
+        if let Some(ref region) = best {
+            if positions.len() > region.count {
+                continue;
+            }
+        }
+        for &ante_index in positions.iter() {
+            let (mut ante_start, mut post_start) = (ante_index, post_index);
+            while ante_start > ante_range.start
+                && post_start > post_range.start
+                && ante[ante_start - 1] == post[post_start - 1]
+            {
+                ante_start -= 1;
+                post_start -= 1;
+            }
+            let (mut ante_end, mut post_end) = (ante_index + 1, post_index + 1);
+            while ante_end < ante_range.end
+                && post_end < post_range.end
+                && ante[ante_end] == post[post_end]
+            {
+                ante_end += 1;
+                post_end += 1;
+            }
+            let count = (ante_start..ante_end)
+                .map(|index| histogram[&ante[index]].len())
+                .min()
+                .unwrap_or(positions.len());
+            let len = ante_end - ante_start;
+            let is_better = match best {
+                Some(ref region) => {
+                    count < region.count || (count == region.count && len > region.len)
+                }
+                None => true,
+            };
+            if is_better {
+                best = Some(Region {
+                    ante_start,
+                    post_start,
+                    len,
+                    count,
+                });
+            }
+        }
+    }
+    best
+}
+
+fn diff_ranges<T: Eq + Hash>(
+    ante: &[T],
+    mut ante_range: Range<usize>,
+    post: &[T],
+    mut post_range: Range<usize>,
+    components: &mut Vec<DiffComponent>,
+) {
+    let prefix_len = common_prefix_len(ante, ante_range.clone(), post, post_range.clone());
+    push_unchanged(components, ante_range.start, post_range.start, prefix_len);
+    ante_range.start += prefix_len;
+    post_range.start += prefix_len;
+    let suffix_len = common_suffix_len(ante, ante_range.clone(), post, post_range.clone());
+    ante_range.end -= suffix_len;
+    post_range.end -= suffix_len;
+
+    if ante_range.is_empty() || post_range.is_empty() {
+        push_changed(components, ante_range.clone(), post_range.clone());
+    } else if let Some(region) =
+        find_lowest_occurrence_region(ante, ante_range.clone(), post, post_range.clone())
+    {
+        diff_ranges(
+            ante,
+            ante_range.start..region.ante_start,
+            post,
+            post_range.start..region.post_start,
+            components,
+        );
+        push_unchanged(components, region.ante_start, region.post_start, region.len);
+        diff_ranges(
+            ante,
+            region.ante_start + region.len..ante_range.end,
+            post,
+            region.post_start + region.len..post_range.end,
+            components,
+        );
+    } else {
+        myers::diff_ranges(
+            ante,
+            ante_range.clone(),
+            post,
+            post_range.clone(),
+            components,
+        );
+    }
+
+    push_unchanged(components, ante_range.end, post_range.end, suffix_len);
+}
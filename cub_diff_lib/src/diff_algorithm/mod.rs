@@ -0,0 +1,195 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! This module implements the algorithms used to find the differences
+//! between two sequences (usually of lines) when generating diffs.
+
+use std::hash::Hash;
+use std::ops::Range;
+
+pub mod histogram;
+pub mod myers;
+pub mod patience;
+pub mod slider;
+
+pub use self::histogram::Histogram;
+pub use self::myers::Myers;
+pub use self::patience::Patience;
+pub use self::slider::IndentHeuristic;
+
+use crate::lines::Line;
+
+// Indices are into the "ante" and "post" sequences respectively
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffComponent {
+    Insertion(usize),
+    Unchanged(usize, usize),
+    Deletion(usize),
+}
+
+pub trait DiffAlgorithm {
+    // Return the components (in order) of a script that turns "ante" into "post"
+    fn diff<T: Eq + Hash>(&self, ante: &[T], post: &[T]) -> Vec<DiffComponent>;
+
+    // As for diff() but with access to the text for algorithms that use it
+    fn diff_lines(&self, ante_lines: &[Line], post_lines: &[Line]) -> Vec<DiffComponent> {
+        self.diff(ante_lines, post_lines)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Algorithm {
+    #[default]
+    Myers,
+    Patience,
+    Histogram,
+}
+
+impl DiffAlgorithm for Algorithm {
+    fn diff<T: Eq + Hash>(&self, ante: &[T], post: &[T]) -> Vec<DiffComponent> {
+        match self {
+            Algorithm::Myers => Myers.diff(ante, post),
+            Algorithm::Patience => Patience.diff(ante, post),
+            Algorithm::Histogram => Histogram.diff(ante, post),
+        }
+    }
+}
+
+// Helpers for the algorithm implementations
+
+fn common_prefix_len<T: Eq>(
+    ante: &[T],
+    ante_range: Range<usize>,
+    post: &[T],
+    post_range: Range<usize>,
+) -> usize {
+    ante[ante_range]
+        .iter()
+        .zip(post[post_range].iter())
+        .take_while(|(a, p)| a == p)
+        .count()
+}
+
+fn common_suffix_len<T: Eq>(
+    ante: &[T],
+    ante_range: Range<usize>,
+    post: &[T],
+    post_range: Range<usize>,
+) -> usize {
+    ante[ante_range]
+        .iter()
+        .rev()
+        .zip(post[post_range].iter().rev())
+        .take_while(|(a, p)| a == p)
+        .count()
+}
+
+fn push_unchanged(
+    components: &mut Vec<DiffComponent>,
+    ante_start: usize,
+    post_start: usize,
+    len: usize,
+) {
+    for i in 0..len {
+        components.push(DiffComponent::Unchanged(ante_start + i, post_start + i));
+    }
+}
+
+fn push_changed(
+    components: &mut Vec<DiffComponent>,
+    ante_range: Range<usize>,
+    post_range: Range<usize>,
+) {
+    for index in ante_range {
+        components.push(DiffComponent::Deletion(index));
+    }
+    for index in post_range {
+        components.push(DiffComponent::Insertion(index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Length of the longest common subsequence (the hard way)
+    fn lcs_len(ante: &[char], post: &[char]) -> usize {
+        let mut table = vec![vec![0; post.len() + 1]; ante.len() + 1];
+        for i in 1..=ante.len() {
+            for j in 1..=post.len() {
+                table[i][j] = if ante[i - 1] == post[j - 1] {
+                    table[i - 1][j - 1] + 1
+                } else {
+                    table[i - 1][j].max(table[i][j - 1])
+                };
+            }
+        }
+        table[ante.len()][post.len()]
+    }
+
+    // Check that the components are a valid script for turning "ante"
+    // into "post" and return the number of unchanged items.
+    fn check_script(ante: &[char], post: &[char], components: &[DiffComponent]) -> usize {
+        let mut ante_index = 0;
+        let mut post_index = 0;
+        let mut unchanged = 0;
+        for component in components.iter() {
+            match *component {
+                DiffComponent::Unchanged(a, p) => {
+                    assert_eq!((a, p), (ante_index, post_index));
+                    assert_eq!(ante[a], post[p]);
+                    ante_index += 1;
+                    post_index += 1;
+                    unchanged += 1;
+                }
+                DiffComponent::Deletion(a) => {
+                    assert_eq!(a, ante_index);
+                    ante_index += 1;
+                }
+                DiffComponent::Insertion(p) => {
+                    assert_eq!(p, post_index);
+                    post_index += 1;
+                }
+            }
+        }
+        assert_eq!((ante_index, post_index), (ante.len(), post.len()));
+        unchanged
+    }
+
+    static SAMPLES: &[(&str, &str)] = &[
+        ("", ""),
+        ("abc", ""),
+        ("", "abc"),
+        ("abcabba", "cbabac"),
+        ("abcdefghij", "abxdefyhij"),
+        ("xaxbxcxdx", "yaybycydy"),
+        ("the quick brown fox", "the quack brawn fax jumps"),
+        ("aaaaabbbbbccccc", "cccccbbbbbaaaaa"),
+        ("abcdefg", "gfedcba"),
+        ("a{b}c{d}e", "a{b}x{d}y{z}e"),
+    ];
+
+    #[test]
+    fn myers_is_minimal() {
+        for (ante, post) in SAMPLES.iter() {
+            let ante: Vec<char> = ante.chars().collect();
+            let post: Vec<char> = post.chars().collect();
+            let components = Myers.diff(&ante, &post);
+            assert_eq!(
+                check_script(&ante, &post, &components),
+                lcs_len(&ante, &post)
+            );
+        }
+    }
+
+    #[test]
+    fn all_algorithms_produce_valid_scripts() {
+        for algorithm in &[Algorithm::Myers, Algorithm::Patience, Algorithm::Histogram] {
+            for (ante, post) in SAMPLES.iter() {
+                let ante: Vec<char> = ante.chars().collect();
+                let post: Vec<char> = post.chars().collect();
+                let components = algorithm.diff(&ante, &post);
+                check_script(&ante, &post, &components);
+            }
+        }
+    }
+}
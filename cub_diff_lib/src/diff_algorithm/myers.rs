@@ -0,0 +1,184 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Myers' O(ND) difference algorithm using the linear space
+//! "middle snake" refinement.
+//! <http://www.xmailserver.org/diff2.pdf>
+
+use std::hash::Hash;
+use std::ops::{Index, IndexMut, Range};
+
+use super::{common_prefix_len, common_suffix_len, push_changed, push_unchanged};
+use super::{DiffAlgorithm, DiffComponent};
+
+pub struct Myers;
+
+impl DiffAlgorithm for Myers {
+    fn diff<T: Eq + Hash>(&self, ante: &[T], post: &[T]) -> Vec<DiffComponent> {
+        let mut components = vec![];
+        diff_ranges(ante, 0..ante.len(), post, 0..post.len(), &mut components);
+        components
+    }
+}
+
+// Furthest reaching x values indexed by diagonal (k = x - y)
+struct V {
+    offset: isize,
+    values: Vec<usize>,
+}
+
+impl V {
+    fn new(max_d: usize) -> Self {
+        V {
+            offset: max_d as isize,
+            values: vec![0; 2 * max_d + 1],
+        }
+    }
+}
+
+impl Index<isize> for V {
+    type Output = usize;
+
+    fn index(&self, k: isize) -> &usize {
+        &self.values[(k + self.offset) as usize]
+    }
+}
+
+impl IndexMut<isize> for V {
+    fn index_mut(&mut self, k: isize) -> &mut usize {
+        &mut self.values[(k + self.offset) as usize]
+    }
+}
+
+fn max_d(ante_len: usize, post_len: usize) -> usize {
+    (ante_len + post_len).div_ceil(2) + 1
+}
+
+// Find a point on an optimal edit path that splits the problem roughly in half
+fn find_middle_snake<T: Eq>(
+    ante: &[T],
+    ante_range: Range<usize>,
+    post: &[T],
+    post_range: Range<usize>,
+    vf: &mut V,
+    vb: &mut V,
+) -> Option<(usize, usize)> {
+    let n = ante_range.len();
+    let m = post_range.len();
+    let delta = n as isize - m as isize;
+    let odd = delta & 1 == 1;
+    vf[1] = 0;
+    vb[1] = 0;
+    for d in 0..max_d(n, m) as isize {
+        // forward paths
+        let mut k = d;
+        while k >= -d {
+            let mut x = if k == -d || (k != d && vf[k - 1] < vf[k + 1]) {
+                vf[k + 1]
+            } else {
+                vf[k - 1] + 1
+            };
+            let y = (x as isize - k) as usize;
+            let (x0, y0) = (x, y);
+            if x < n && y < m {
+                x += common_prefix_len(
+                    ante,
+                    ante_range.start + x..ante_range.end,
+                    post,
+                    post_range.start + y..post_range.end,
+                );
+            }
+            vf[k] = x;
+            if odd && (k - delta).abs() < d && vf[k] + vb[-(k - delta)] >= n {
+                return Some((ante_range.start + x0, post_range.start + y0));
+            }
+            k -= 2;
+        }
+        // backward paths
+        let mut k = d;
+        while k >= -d {
+            let mut x = if k == -d || (k != d && vb[k - 1] < vb[k + 1]) {
+                vb[k + 1]
+            } else {
+                vb[k - 1] + 1
+            };
+            let mut y = (x as isize - k) as usize;
+            if x < n && y < m {
+                let advance = common_suffix_len(
+                    ante,
+                    ante_range.start..ante_range.start + n - x,
+                    post,
+                    post_range.start..post_range.start + m - y,
+                );
+                x += advance;
+                y += advance;
+            }
+            vb[k] = x;
+            if !odd && (k - delta).abs() <= d && vb[k] + vf[-(k - delta)] >= n {
+                return Some((ante_range.start + n - x, post_range.start + m - y));
+            }
+            k -= 2;
+        }
+    }
+    None
+}
+
+pub(crate) fn diff_ranges<T: Eq>(
+    ante: &[T],
+    ante_range: Range<usize>,
+    post: &[T],
+    post_range: Range<usize>,
+    components: &mut Vec<DiffComponent>,
+) {
+    let mut vf = V::new(max_d(ante_range.len(), post_range.len()));
+    let mut vb = V::new(max_d(ante_range.len(), post_range.len()));
+    conquer(
+        ante, ante_range, post, post_range, &mut vf, &mut vb, components,
+    );
+}
+
+fn conquer<T: Eq>(
+    ante: &[T],
+    mut ante_range: Range<usize>,
+    post: &[T],
+    mut post_range: Range<usize>,
+    vf: &mut V,
+    vb: &mut V,
+    components: &mut Vec<DiffComponent>,
+) {
+    let prefix_len = common_prefix_len(ante, ante_range.clone(), post, post_range.clone());
+    push_unchanged(components, ante_range.start, post_range.start, prefix_len);
+    ante_range.start += prefix_len;
+    post_range.start += prefix_len;
+    let suffix_len = common_suffix_len(ante, ante_range.clone(), post, post_range.clone());
+    ante_range.end -= suffix_len;
+    post_range.end -= suffix_len;
+
+    if ante_range.is_empty() || post_range.is_empty() {
+        push_changed(components, ante_range.clone(), post_range.clone());
+    } else if let Some((x, y)) =
+        find_middle_snake(ante, ante_range.clone(), post, post_range.clone(), vf, vb)
+    {
+        conquer(
+            ante,
+            ante_range.start..x,
+            post,
+            post_range.start..y,
+            vf,
+            vb,
+            components,
+        );
+        conquer(
+            ante,
+            x..ante_range.end,
+            post,
+            y..post_range.end,
+            vf,
+            vb,
+            components,
+        );
+    } else {
+        push_changed(components, ante_range.clone(), post_range.clone());
+    }
+
+    push_unchanged(components, ante_range.end, post_range.end, suffix_len);
+}
@@ -0,0 +1,153 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Patience diff: anchor the diff on items that occur exactly once in
+//! both sequences (taking the longest increasing run of them) and
+//! recurse between the anchors, falling back to Myers where there are
+//! no unique items.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Range;
+
+use super::{common_prefix_len, common_suffix_len, myers, push_changed, push_unchanged};
+use super::{DiffAlgorithm, DiffComponent};
+
+pub struct Patience;
+
+impl DiffAlgorithm for Patience {
+    fn diff<T: Eq + Hash>(&self, ante: &[T], post: &[T]) -> Vec<DiffComponent> {
+        let mut components = vec![];
+        diff_ranges(ante, 0..ante.len(), post, 0..post.len(), &mut components);
+        components
+    }
+}
+
+// The (ante, post) index pairs of items that are unique in both ranges
+// forming the longest sequence that is increasing in both.
+fn unique_anchors<T: Eq + Hash>(
+    ante: &[T],
+    ante_range: Range<usize>,
+    post: &[T],
+    post_range: Range<usize>,
+) -> Vec<(usize, usize)> {
+    // item -> (ante count, ante index, post count, post index)
+    let mut occurrences: HashMap<&T, (usize, usize, usize, usize)> = HashMap::new();
+    for index in ante_range {
+        let entry = occurrences.entry(&ante[index]).or_insert((0, 0, 0, 0));
+        entry.0 += 1;
+        entry.1 = index;
+    }
+    for index in post_range {
+        if let Some(entry) = occurrences.get_mut(&post[index]) {
+            entry.2 += 1;
+            entry.3 = index;
+        }
+    }
+    let mut pairs: Vec<(usize, usize)> = occurrences
+        .values()
+        .filter(|entry| entry.0 == 1 && entry.2 == 1)
+        .map(|entry| (entry.1, entry.3))
+        .collect();
+    pairs.sort_unstable();
+
+    // patience sort on the post indices to find the longest increasing subsequence
+    let mut pile_tops: Vec<usize> = vec![];
+    let mut back_links: Vec<Option<usize>> = vec![None; pairs.len()];
+    for (i, pair) in pairs.iter().enumerate() {
+        let pile = pile_tops.partition_point(|&top| pairs[top].1 < pair.1);
+        if pile > 0 {
+            back_links[i] = Some(pile_tops[pile - 1]);
+        }
+        if pile == pile_tops.len() {
+            pile_tops.push(i);
+        } else {
+            pile_tops[pile] = i;
+        }
+    }
+    let mut anchors = vec![];
+    let mut link = pile_tops.last().cloned();
+    while let Some(i) = link {
+        anchors.push(pairs[i]);
+        link = back_links[i];
+    }
+    anchors.reverse();
+    anchors
+}
+
+fn diff_ranges<T: Eq + Hash>(
+    ante: &[T],
+    mut ante_range: Range<usize>,
+    post: &[T],
+    mut post_range: Range<usize>,
+    components: &mut Vec<DiffComponent>,
+) {
+    let prefix_len = common_prefix_len(ante, ante_range.clone(), post, post_range.clone());
+    push_unchanged(components, ante_range.start, post_range.start, prefix_len);
+    ante_range.start += prefix_len;
+    post_range.start += prefix_len;
+    let suffix_len = common_suffix_len(ante, ante_range.clone(), post, post_range.clone());
+    ante_range.end -= suffix_len;
+    post_range.end -= suffix_len;
+
+    if ante_range.is_empty() || post_range.is_empty() {
+        push_changed(components, ante_range.clone(), post_range.clone());
+    } else {
+        let anchors = unique_anchors(ante, ante_range.clone(), post, post_range.clone());
+        if anchors.is_empty() {
+            myers::diff_ranges(
+                ante,
+                ante_range.clone(),
+                post,
+                post_range.clone(),
+                components,
+            );
+        } else {
+            let (mut ante_start, mut post_start) = (ante_range.start, post_range.start);
+            for (ante_index, post_index) in anchors {
+                diff_ranges(
+                    ante,
+                    ante_start..ante_index,
+                    post,
+                    post_start..post_index,
+                    components,
+                );
+                components.push(DiffComponent::Unchanged(ante_index, post_index));
+                ante_start = ante_index + 1;
+                post_start = post_index + 1;
+            }
+            diff_ranges(
+                ante,
+                ante_start..ante_range.end,
+                post,
+                post_start..post_range.end,
+                components,
+            );
+        }
+    }
+
+    push_unchanged(components, ante_range.end, post_range.end, suffix_len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::{Lines, LinesIfce};
+
+    #[test]
+    fn patience_keeps_functions_intact() {
+        // the classic example where Myers matches up the braces and blank lines
+        let ante = Lines::from_string("fn a() {\n    one();\n}\n\nfn c() {\n    three();\n}\n");
+        let post = Lines::from_string(
+            "fn a() {\n    one();\n}\n\nfn b() {\n    two();\n}\n\nfn c() {\n    three();\n}\n",
+        );
+        let components = Patience.diff(&ante, &post);
+        let inserted: Vec<usize> = components
+            .iter()
+            .filter_map(|c| match c {
+                DiffComponent::Insertion(index) => Some(*index),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(inserted, vec![4, 5, 6, 7]);
+    }
+}
@@ -0,0 +1,408 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Post processing of diff components to slide ambiguous blocks of
+//! inserted/deleted lines to the position that is easiest to read.
+//! This is a port of git's "indent heuristic" (see xdiff/xdiffi.c).
+
+use std::hash::Hash;
+
+use super::{DiffAlgorithm, DiffComponent};
+use crate::lines::Line;
+
+const START_OF_FILE_PENALTY: i32 = 1;
+const END_OF_FILE_PENALTY: i32 = 21;
+const TOTAL_BLANK_WEIGHT: i32 = -30;
+const POST_BLANK_WEIGHT: i32 = 6;
+const RELATIVE_INDENT_PENALTY: i32 = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: i32 = 10;
+const RELATIVE_OUTDENT_PENALTY: i32 = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: i32 = 17;
+const RELATIVE_DEDENT_PENALTY: i32 = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: i32 = 17;
+const INDENT_WEIGHT: i32 = 60;
+const INDENT_HEURISTIC_MAX_SLIDING: usize = 100;
+const MAX_INDENT: i32 = 200;
+const MAX_BLANKS: i32 = 20;
+
+// A diff algorithm whose line diffs are post processed by the slider
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndentHeuristic<A: DiffAlgorithm>(pub A);
+
+impl<A: DiffAlgorithm> DiffAlgorithm for IndentHeuristic<A> {
+    fn diff<T: Eq + Hash>(&self, ante: &[T], post: &[T]) -> Vec<DiffComponent> {
+        self.0.diff(ante, post)
+    }
+
+    fn diff_lines(&self, ante_lines: &[Line], post_lines: &[Line]) -> Vec<DiffComponent> {
+        slide_components(&self.0.diff(ante_lines, post_lines), ante_lines, post_lines)
+    }
+}
+
+// Indentation with tabs expanded or None for blank lines
+fn get_indent(line: &str) -> Option<i32> {
+    let mut indent = 0;
+    for ch in line.chars() {
+        if !ch.is_whitespace() {
+            return Some(indent);
+        }
+        if ch == ' ' {
+            indent += 1;
+        } else if ch == '\t' {
+            indent += 8 - indent % 8;
+        }
+        if indent >= MAX_INDENT {
+            return Some(MAX_INDENT);
+        }
+    }
+    None
+}
+
+#[derive(Debug, Default)]
+struct SplitMeasurement {
+    end_of_file: bool,
+    indent: Option<i32>,
+    pre_blank: i32,
+    pre_indent: Option<i32>,
+    post_blank: i32,
+    post_indent: Option<i32>,
+}
+
+impl SplitMeasurement {
+    // Measure the lines around a split placed just before "split"
+    fn new(lines: &[Line], split: usize) -> Self {
+        let mut m = SplitMeasurement::default();
+        if split >= lines.len() {
+            m.end_of_file = true;
+        } else {
+            m.indent = get_indent(&lines[split]);
+        }
+        for line in lines[..split.min(lines.len())].iter().rev() {
+            m.pre_indent = get_indent(line);
+            if m.pre_indent.is_some() {
+                break;
+            }
+            m.pre_blank += 1;
+            if m.pre_blank == MAX_BLANKS {
+                m.pre_indent = Some(0);
+                break;
+            }
+        }
+        for line in lines.iter().skip(split + 1) {
+            m.post_indent = get_indent(line);
+            if m.post_indent.is_some() {
+                break;
+            }
+            m.post_blank += 1;
+            if m.post_blank == MAX_BLANKS {
+                m.post_indent = Some(0);
+                break;
+            }
+        }
+        m
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct SplitScore {
+    effective_indent: i32,
+    penalty: i32,
+}
+
+impl SplitScore {
+    fn add_split(&mut self, m: &SplitMeasurement) {
+        if m.pre_indent.is_none() && m.pre_blank == 0 {
+            self.penalty += START_OF_FILE_PENALTY;
+        }
+        if m.end_of_file {
+            self.penalty += END_OF_FILE_PENALTY;
+        }
+        let post_blank = if m.indent.is_none() {
+            1 + m.post_blank
+        } else {
+            0
+        };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+        self.penalty += POST_BLANK_WEIGHT * post_blank;
+        let indent = m.indent.or(m.post_indent);
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent.unwrap_or(-1);
+        if let (Some(indent), Some(pre_indent)) = (indent, m.pre_indent) {
+            if indent > pre_indent {
+                self.penalty += if any_blanks {
+                    RELATIVE_INDENT_WITH_BLANK_PENALTY
+                } else {
+                    RELATIVE_INDENT_PENALTY
+                };
+            } else if indent < pre_indent {
+                self.penalty += match m.post_indent {
+                    Some(post_indent) if post_indent > indent => {
+                        if any_blanks {
+                            RELATIVE_OUTDENT_WITH_BLANK_PENALTY
+                        } else {
+                            RELATIVE_OUTDENT_PENALTY
+                        }
+                    }
+                    _ => {
+                        if any_blanks {
+                            RELATIVE_DEDENT_WITH_BLANK_PENALTY
+                        } else {
+                            RELATIVE_DEDENT_PENALTY
+                        }
+                    }
+                };
+            }
+        }
+    }
+
+    // Is this score at least as good as "other"?
+    fn is_no_worse_than(&self, other: &SplitScore) -> bool {
+        let cmp_indents = (self.effective_indent > other.effective_indent) as i32
+            - (self.effective_indent < other.effective_indent) as i32;
+        INDENT_WEIGHT * cmp_indents + (self.penalty - other.penalty) <= 0
+    }
+}
+
+// Whether each unchanged gap (indexed by the number of unchanged lines
+// preceding it) contains any changed lines.
+fn changed_gaps(changed: &[bool]) -> Vec<bool> {
+    let mut gaps = vec![false];
+    for &is_changed in changed.iter() {
+        if is_changed {
+            *gaps.last_mut().unwrap() = true;
+        } else {
+            gaps.push(false);
+        }
+    }
+    gaps
+}
+
+// Slide the groups of changed lines in "lines" (as marked in "changed")
+// to their most readable positions, preferring alignment with changes in
+// the other file.
+fn compact_changes(lines: &[Line], changed: &mut [bool], other_changed: &[bool]) {
+    let other_gaps = changed_gaps(other_changed);
+    let mut start = 0;
+    loop {
+        while start < lines.len() && !changed[start] {
+            start += 1;
+        }
+        if start >= lines.len() {
+            break;
+        }
+        let mut end = start;
+        while end < lines.len() && changed[end] {
+            end += 1;
+        }
+        let mut earliest_end;
+        loop {
+            let group_size = end - start;
+            // slide up as far as possible merging with any groups met
+            while start > 0 && lines[start - 1] == lines[end - 1] {
+                start -= 1;
+                end -= 1;
+                changed[start] = true;
+                changed[end] = false;
+                while start > 0 && changed[start - 1] {
+                    start -= 1;
+                }
+            }
+            earliest_end = end;
+            // slide down as far as possible merging with any groups met
+            while end < lines.len() && lines[start] == lines[end] {
+                changed[start] = false;
+                changed[end] = true;
+                start += 1;
+                end += 1;
+                while end < lines.len() && changed[end] {
+                    end += 1;
+                }
+            }
+            if group_size == end - start {
+                break;
+            }
+        }
+        let group_size = end - start;
+        if end > earliest_end {
+            // the gap in the other file that the group sits in at each end position
+            let unchanged_before = start - changed[..start].iter().filter(|&&c| c).count();
+            let gap_at = |shift: usize| unchanged_before + shift - end;
+            let best_end = if let Some(shift) = (earliest_end..=end)
+                .rev()
+                .find(|&shift| other_gaps[gap_at(shift)])
+            {
+                shift
+            } else {
+                let first_shift = earliest_end
+                    .max((end - group_size).saturating_sub(1))
+                    .max(end.saturating_sub(INDENT_HEURISTIC_MAX_SLIDING));
+                let mut best: Option<(usize, SplitScore)> = None;
+                for shift in first_shift..=end {
+                    let mut score = SplitScore::default();
+                    score.add_split(&SplitMeasurement::new(lines, shift));
+                    score.add_split(&SplitMeasurement::new(lines, shift - group_size));
+                    if !matches!(best, Some((_, best_score)) if !score.is_no_worse_than(&best_score))
+                    {
+                        best = Some((shift, score));
+                    }
+                }
+                // unwrap() is safe as there's always at least one candidate
+                best.unwrap().0
+            };
+            for flag in changed[start..end].iter_mut() {
+                *flag = false;
+            }
+            for flag in changed[best_end - group_size..best_end].iter_mut() {
+                *flag = true;
+            }
+        }
+        start = end;
+    }
+}
+
+// Return the equivalent of "components" with the inserted and deleted
+// blocks moved to their most readable positions.
+pub fn slide_components(
+    components: &[DiffComponent],
+    ante_lines: &[Line],
+    post_lines: &[Line],
+) -> Vec<DiffComponent> {
+    let mut ante_changed = vec![false; ante_lines.len()];
+    let mut post_changed = vec![false; post_lines.len()];
+    for component in components.iter() {
+        match *component {
+            DiffComponent::Deletion(index) => ante_changed[index] = true,
+            DiffComponent::Insertion(index) => post_changed[index] = true,
+            DiffComponent::Unchanged(_, _) => (),
+        }
+    }
+    compact_changes(ante_lines, &mut ante_changed, &post_changed);
+    compact_changes(post_lines, &mut post_changed, &ante_changed);
+
+    let mut slid_components = Vec::with_capacity(components.len());
+    let (mut ante_index, mut post_index) = (0, 0);
+    while ante_index < ante_lines.len() || post_index < post_lines.len() {
+        if ante_index < ante_lines.len() && ante_changed[ante_index] {
+            slid_components.push(DiffComponent::Deletion(ante_index));
+            ante_index += 1;
+        } else if post_index < post_lines.len() && post_changed[post_index] {
+            slid_components.push(DiffComponent::Insertion(post_index));
+            post_index += 1;
+        } else {
+            slid_components.push(DiffComponent::Unchanged(ante_index, post_index));
+            ante_index += 1;
+            post_index += 1;
+        }
+    }
+    slid_components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff_algorithm::Myers;
+    use crate::lines::{Lines, LinesIfce};
+    use std::ops::Range;
+
+    // A script with a single block of deletions and insertions at the
+    // given positions (which may well be a poor choice).
+    fn script(
+        ante_len: usize,
+        deleted: Range<usize>,
+        post_len: usize,
+        inserted: Range<usize>,
+    ) -> Vec<DiffComponent> {
+        let mut components: Vec<DiffComponent> = (0..deleted.start.min(inserted.start))
+            .map(|index| DiffComponent::Unchanged(index, index))
+            .collect();
+        let (ante_end, post_end) = (deleted.end, inserted.end);
+        components.extend(deleted.map(DiffComponent::Deletion));
+        components.extend(inserted.map(DiffComponent::Insertion));
+        components.extend(
+            (ante_end..ante_len)
+                .zip(post_end..post_len)
+                .map(|(a, p)| DiffComponent::Unchanged(a, p)),
+        );
+        components
+    }
+
+    fn changed_lines(components: &[DiffComponent], lines: &[Line], deleted: bool) -> String {
+        components
+            .iter()
+            .filter_map(|component| match *component {
+                DiffComponent::Deletion(index) if deleted => Some(lines[index].as_str()),
+                DiffComponent::Insertion(index) if !deleted => Some(lines[index].as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deleted_rust_function_kept_whole() {
+        let ante = Lines::from_string(
+            "fn one() {\n    1\n}\n\nfn two() {\n    2\n}\n\nfn three() {\n    3\n}\n",
+        );
+        let post = Lines::from_string("fn one() {\n    1\n}\n\nfn three() {\n    3\n}\n");
+        // "}\n\nfn two() {\n    2\n" is an equally short but ugly choice
+        let components = script(ante.len(), 2..6, post.len(), 2..2);
+        assert_eq!(
+            changed_lines(&components, &ante, true),
+            "}\n\nfn two() {\n    2\n"
+        );
+        let components = slide_components(&components, &ante, &post);
+        assert_eq!(
+            changed_lines(&components, &ante, true),
+            "fn two() {\n    2\n}\n\n"
+        );
+    }
+
+    #[test]
+    fn inserted_c_function_kept_whole() {
+        let ante = Lines::from_string(
+            "int one(void)\n{\n\treturn 1;\n}\n\nint three(void)\n{\n\treturn 3;\n}\n",
+        );
+        let post = Lines::from_string(
+            "int one(void)\n{\n\treturn 1;\n}\n\nint two(void)\n{\n\treturn 2;\n}\n\nint three(void)\n{\n\treturn 3;\n}\n",
+        );
+        let components = script(ante.len(), 4..4, post.len(), 4..9);
+        let components = slide_components(&components, &ante, &post);
+        assert_eq!(
+            changed_lines(&components, &post, false),
+            "int two(void)\n{\n\treturn 2;\n}\n\n"
+        );
+    }
+
+    #[test]
+    fn nested_block_insertion_not_split_across_braces() {
+        let ante = Lines::from_string(
+            "    if a {\n        x();\n    }\n    if c {\n        z();\n    }\n",
+        );
+        let post = Lines::from_string(
+            "    if a {\n        x();\n    }\n    if b {\n        y();\n    }\n    if c {\n        z();\n    }\n",
+        );
+        let components = script(ante.len(), 2..2, post.len(), 2..5);
+        let components = slide_components(&components, &ante, &post);
+        assert_eq!(
+            changed_lines(&components, &post, false),
+            "    if b {\n        y();\n    }\n"
+        );
+    }
+
+    #[test]
+    fn changes_stay_aligned_with_other_side() {
+        // a replacement should not be split into a separate deletion and insertion
+        let ante = Lines::from_string("x\na\nx\nb\n");
+        let post = Lines::from_string("x\nc\nx\nb\n");
+        let components = slide_components(&Myers.diff(&ante, &post), &ante, &post);
+        assert_eq!(
+            components,
+            vec![
+                DiffComponent::Unchanged(0, 0),
+                DiffComponent::Deletion(1),
+                DiffComponent::Insertion(1),
+                DiffComponent::Unchanged(2, 2),
+                DiffComponent::Unchanged(3, 3),
+            ]
+        );
+    }
+}
@@ -0,0 +1,518 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Render a patch's changes in the summary formats used by git.
+
+use crate::diff_stats::{renamed_path, DiffStatChange, DiffStatEntry, DiffStatsLines};
+use crate::lines::{Line, Lines};
+use crate::patch::Patch;
+use crate::preamble::GitPreamble;
+use crate::requoted_path;
+
+// Scale a count to fit within a graph of the given width
+fn scaled(count: u64, width: u64, max_change: u64) -> u64 {
+    if count == 0 {
+        0
+    } else {
+        1 + count * (width - 1) / max_change
+    }
+}
+
+// Render a patch's changes as "git diff --stat", "--numstat" or "--summary"
+// style output
+pub struct DiffStatRenderer {
+    pub width: usize,
+    pub strip_level: usize,
+}
+
+impl Default for DiffStatRenderer {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            strip_level: 1,
+        }
+    }
+}
+
+impl DiffStatRenderer {
+    fn graph(
+        &self,
+        insertions: u64,
+        deletions: u64,
+        graph_width: usize,
+        max_change: u64,
+    ) -> String {
+        let (insertions, deletions) = if max_change > graph_width as u64 {
+            let width = graph_width as u64;
+            let mut total = scaled(insertions + deletions, width, max_change);
+            if total < 2 && insertions > 0 && deletions > 0 {
+                total = 2;
+            }
+            if insertions < deletions {
+                let insertions = scaled(insertions, width, max_change);
+                (insertions, total - insertions)
+            } else {
+                let deletions = scaled(deletions, width, max_change);
+                (total - deletions, deletions)
+            }
+        } else {
+            (insertions, deletions)
+        };
+        "+".repeat(insertions as usize) + &"-".repeat(deletions as usize)
+    }
+
+    pub fn render(&self, patch: &Patch) -> DiffStatsLines {
+        let entries: Vec<DiffStatEntry> = patch
+            .diff_pluses()
+            .iter()
+            .map(|diff_plus| diff_plus.diff_stat_entry(self.strip_level))
+            .collect();
+        let stats = patch.diff_stats();
+        if entries.is_empty() {
+            return DiffStatsLines::new(
+                vec![Line::new(" 0 files changed\n".to_string())],
+                stats,
+                entries,
+            );
+        }
+        let names: Vec<String> = entries.iter().map(|entry| entry.display_path()).collect();
+        let max_change = entries
+            .iter()
+            .map(|entry| entry.change().changes())
+            .max()
+            .unwrap_or(0);
+        let has_binary = entries
+            .iter()
+            .any(|entry| matches!(entry.change(), DiffStatChange::Binary(_)));
+        let number_width = max_change
+            .to_string()
+            .len()
+            .max(if has_binary { 3 } else { 0 });
+        let mut name_width = names
+            .iter()
+            .map(|name| name.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut graph_width = max_change as usize;
+        // " name | number graph" plus an empty last column
+        let fixed_width = number_width + 6;
+        if name_width + fixed_width + graph_width > self.width {
+            // give the graph at most 3/8 of the width (but at least 6 columns)
+            let max_graph_width = (self.width * 3 / 8).saturating_sub(fixed_width).max(6);
+            graph_width = graph_width.min(max_graph_width);
+            let name_room = self.width.saturating_sub(fixed_width + graph_width);
+            if name_width > name_room {
+                name_width = name_room;
+            } else {
+                graph_width = self.width.saturating_sub(fixed_width + name_width);
+            }
+        }
+        let mut lines: Lines = Vec::new();
+        let (mut insertions, mut deletions) = (0, 0);
+        for (name, entry) in names.iter().zip(entries.iter()) {
+            let name_len = name.chars().count();
+            let name = if name_len > name_width {
+                let tail: String = name
+                    .chars()
+                    .skip(name_len + 3 - name_width.max(3))
+                    .collect();
+                format!("...{tail}")
+            } else {
+                name.to_string()
+            };
+            let mut line = format!(" {name:<name_width$} |");
+            match *entry.change() {
+                DiffStatChange::Text {
+                    changes,
+                    insertions: file_insertions,
+                    deletions: file_deletions,
+                } => {
+                    line += &format!(" {changes:>number_width$}");
+                    if changes > 0 {
+                        let graph =
+                            self.graph(file_insertions, file_deletions, graph_width, max_change);
+                        line += &format!(" {graph}");
+                    }
+                    insertions += file_insertions;
+                    deletions += file_deletions;
+                }
+                DiffStatChange::Binary(sizes) => {
+                    line += &format!(" {:>number_width$}", "Bin");
+                    if let Some((ante_size, post_size)) = sizes {
+                        line += &format!(" {ante_size} -> {post_size} bytes");
+                    }
+                }
+            }
+            line.push('\n');
+            lines.push(Line::new(line));
+        }
+        let num_files = entries.len();
+        let mut summary = format!(
+            " {num_files} file{} changed",
+            if num_files == 1 { "" } else { "s" }
+        );
+        // as per git, don't omit both counts when neither is non zero
+        if insertions > 0 || deletions == 0 {
+            summary += &format!(
+                ", {insertions} insertion{}(+)",
+                if insertions == 1 { "" } else { "s" }
+            );
+        }
+        if deletions > 0 || insertions == 0 {
+            summary += &format!(
+                ", {deletions} deletion{}(-)",
+                if deletions == 1 { "" } else { "s" }
+            );
+        }
+        summary.push('\n');
+        lines.push(Line::new(summary));
+        DiffStatsLines::new(lines, stats, entries)
+    }
+
+    // Tab separated inserted and deleted line counts for each file
+    // ("-" for binary files) followed by its path quoted as git does
+    pub fn render_numstat(&self, patch: &Patch) -> Lines {
+        patch
+            .diff_pluses()
+            .iter()
+            .map(|diff_plus| {
+                let entry = diff_plus.diff_stat_entry(self.strip_level);
+                let counts = match *entry.change() {
+                    DiffStatChange::Text {
+                        insertions,
+                        deletions,
+                        ..
+                    } => format!("{insertions}\t{deletions}"),
+                    DiffStatChange::Binary(_) => "-\t-".to_string(),
+                };
+                Line::new(format!("{counts}\t{}\n", entry.display_path()))
+            })
+            .collect()
+    }
+
+    // Lines describing the files created, deleted, renamed, copied or
+    // whose mode was changed (as recorded in the git preambles)
+    pub fn render_summary(&self, patch: &Patch) -> Lines {
+        let mut lines: Lines = Vec::new();
+        for diff_plus in patch.diff_pluses() {
+            if let Some(preamble) = diff_plus.git_preamble() {
+                for line in self.summary_lines(preamble) {
+                    lines.push(Line::new(line));
+                }
+            }
+        }
+        lines
+    }
+
+    fn summary_lines(&self, preamble: &GitPreamble) -> Vec<String> {
+        let mut lines = vec![];
+        let file_path = requoted_path(&preamble.get_file_path(self.strip_level));
+        if let Some(mode) = preamble.get_extra("new file mode") {
+            lines.push(format!(" create mode {mode} {file_path}\n"));
+        } else if let Some(mode) = preamble.get_extra("deleted file mode") {
+            lines.push(format!(" delete mode {mode} {file_path}\n"));
+        } else {
+            let mut show_name = true;
+            for (name, extra) in [("rename", "rename to"), ("copy", "copy to")] {
+                if preamble.get_extra(extra).is_some() {
+                    let paths = renamed_path(
+                        &preamble.get_ante_file_path(self.strip_level),
+                        &preamble.get_post_file_path(self.strip_level),
+                    );
+                    let similarity = preamble.get_extra("similarity index").unwrap_or("100%");
+                    lines.push(format!(" {name} {paths} ({similarity})\n"));
+                    show_name = false;
+                }
+            }
+            if let Some(dissimilarity) = preamble.get_extra("dissimilarity index") {
+                lines.push(format!(" rewrite {file_path} ({dissimilarity})\n"));
+                show_name = false;
+            }
+            if let (Some(old_mode), Some(new_mode)) = (
+                preamble.get_extra("old mode"),
+                preamble.get_extra("new mode"),
+            ) {
+                if show_name {
+                    lines.push(format!(
+                        " mode change {old_mode} => {new_mode} {file_path}\n"
+                    ));
+                } else {
+                    lines.push(format!(" mode change {old_mode} => {new_mode}\n"));
+                }
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff_stats::{DiffStatParser, DiffStatsCategory};
+    use crate::lines::LinesIfce;
+    use crate::patch::PatchParser;
+
+    #[test]
+    fn render_numstat_and_summary() {
+        let text = "diff --git a/f b/f
+index 0ff3bbb..fb3ced1 100644
+--- a/f
++++ b/f
+@@ -1,2 +1,3 @@
+-1
++one
++1.5
+ 2
+diff --git a/dir/a.txt b/dir/b.txt
+old mode 100644
+new mode 100755
+similarity index 90%
+rename from dir/a.txt
+rename to dir/b.txt
+index 1111111..2222222
+--- a/dir/a.txt
++++ b/dir/b.txt
+@@ -1,3 +1,3 @@
+ 1
+-2
++two
+ 3
+diff --git a/mode b/mode
+old mode 100644
+new mode 100755
+diff --git a/new b/new
+new file mode 100644
+index 0000000..3333333
+--- /dev/null
++++ b/new
+@@ -0,0 +1 @@
++new
+diff --git a/old b/old
+deleted file mode 100644
+index 4444444..0000000
+--- a/old
++++ /dev/null
+@@ -1,2 +0,0 @@
+-old
+-lines
+diff --git a/bin b/bin
+new file mode 100644
+index 0000000000000000000000000000000000000000..8352675d67aed6625ece79af41c27fdb4ee2e867
+GIT binary patch
+literal 3
+KcmZQzWC8#H2LJ>B
+
+literal 0
+HcmV?d00001
+
+";
+        let patch = PatchParser::new()
+            .parse_lines(&Lines::from_string(text))
+            .unwrap()
+            .unwrap();
+        let renderer = DiffStatRenderer::default();
+        let numstat: String = renderer
+            .render_numstat(&patch)
+            .iter()
+            .map(|line| line.as_str())
+            .collect();
+        assert_eq!(
+            numstat,
+            "2\t1\tf\n1\t1\tdir/{a.txt => b.txt}\n0\t0\tmode\n1\t0\tnew\n0\t2\told\n-\t-\tbin\n"
+        );
+        let summary: String = renderer
+            .render_summary(&patch)
+            .iter()
+            .map(|line| line.as_str())
+            .collect();
+        let expected = " rename dir/{a.txt => b.txt} (90%)
+ mode change 100644 => 100755
+ mode change 100644 => 100755 mode
+ create mode 100644 new
+ delete mode 100644 old
+ create mode 100644 bin
+";
+        assert_eq!(summary, expected);
+
+        // paths are quoted as per "git diff --numstat --summary"
+        let text = r#"diff --git "a/caf\303\251" "b/caf\303\251"
+deleted file mode 100644
+index 587be6b..0000000
+--- "a/caf\303\251"
++++ /dev/null
+@@ -1 +0,0 @@
+-x
+diff --git a/old "b/na\303\257ve"
+similarity index 100%
+rename from old
+rename to "na\303\257ve"
+diff --git "a/\303\261ew" "b/\303\261ew"
+new file mode 100644
+index 0000000..8ba3a16
+--- /dev/null
++++ "b/\303\261ew"
+@@ -0,0 +1 @@
++n
+"#;
+        let patch = PatchParser::new()
+            .parse_lines(&Lines::from_string(text))
+            .unwrap()
+            .unwrap();
+        let numstat: String = renderer
+            .render_numstat(&patch)
+            .iter()
+            .map(|line| line.as_str())
+            .collect();
+        assert_eq!(
+            numstat,
+            r#"0	1	"caf\303\251"
+0	0	old => "na\303\257ve"
+1	0	"\303\261ew"
+"#
+        );
+        let summary: String = renderer
+            .render_summary(&patch)
+            .iter()
+            .map(|line| line.as_str())
+            .collect();
+        let expected = r#" delete mode 100644 "caf\303\251"
+ rename old => "na\303\257ve" (100%)
+ create mode 100644 "\303\261ew"
+"#;
+        assert_eq!(summary, expected);
+    }
+
+    #[test]
+    fn render_diff_stats() {
+        let text = "diff --git a/f b/f
+index 0ff3bbb..fb3ced1 100644
+--- a/f
++++ b/f
+@@ -2,7 +2,8 @@
+ 2
+ 3
+ 4
+-5
++five
++5.5
+ 6
+ 7
+ 8
+diff --git a/dir/a.txt b/dir/b.txt
+similarity index 90%
+rename from dir/a.txt
+rename to dir/b.txt
+index 1111111..2222222 100644
+--- a/dir/a.txt
++++ b/dir/b.txt
+@@ -1,3 +1,3 @@
+ 1
+-2
++two
+ 3
+diff --git a/bin b/bin
+new file mode 100644
+index 0000000000000000000000000000000000000000..8352675d67aed6625ece79af41c27fdb4ee2e867
+GIT binary patch
+literal 3
+KcmZQzWC8#H2LJ>B
+
+literal 0
+HcmV?d00001
+
+";
+        let parser = PatchParser::new();
+        let patch = parser
+            .parse_lines(&Lines::from_string(text))
+            .unwrap()
+            .unwrap();
+        let diff_stat_lines = DiffStatRenderer::default().render(&patch);
+        let expected = " f                    |   3 ++-
+ dir/{a.txt => b.txt} |   2 +-
+ bin                  | Bin 0 -> 3 bytes
+ 3 files changed, 3 insertions(+), 2 deletions(-)
+";
+        assert_eq!(
+            diff_stat_lines
+                .iter()
+                .map(|line| line.as_str())
+                .collect::<String>(),
+            expected
+        );
+        let stats = diff_stat_lines.stats();
+        assert_eq!(stats.count(DiffStatsCategory::Inserted), 1);
+        assert_eq!(stats.count(DiffStatsCategory::Deleted), 0);
+        assert_eq!(stats.count(DiffStatsCategory::Modified), 2);
+
+        let mut lines = Lines::from_string("A description\n---\n");
+        lines.extend(diff_stat_lines.iter().cloned());
+        lines.push(Line::new("\n".to_string()));
+        lines.extend(Lines::from_string(text));
+        assert_eq!(
+            DiffStatParser::new().get_summary_line_range_at(&lines, 1),
+            Some((1, 5))
+        );
+        let patch = parser.parse_lines(&lines).unwrap().unwrap();
+        assert_eq!(patch.diff_pluses().len(), 3);
+        assert_eq!(
+            patch.header().diff_stats().unwrap().entries(),
+            diff_stat_lines.entries()
+        );
+
+        // paths are quoted as per "git diff --stat"
+        let text = r#"diff --git "a/caf\303\251" "b/caf\303\251"
+index 587be6b..975fbec 100644
+--- "a/caf\303\251"
++++ "b/caf\303\251"
+@@ -1 +1 @@
+-x
++y
+diff --git a/old "b/na\303\257ve"
+similarity index 100%
+rename from old
+rename to "na\303\257ve"
+"#;
+        let patch = parser
+            .parse_lines(&Lines::from_string(text))
+            .unwrap()
+            .unwrap();
+        let diff_stat_lines = DiffStatRenderer::default().render(&patch);
+        let expected = r#" "caf\303\251"         | 2 +-
+ old => "na\303\257ve" | 0
+ 2 files changed, 1 insertion(+), 1 deletion(-)
+"#;
+        assert_eq!(
+            diff_stat_lines
+                .iter()
+                .map(|line| line.as_str())
+                .collect::<String>(),
+            expected
+        );
+        let lines: Lines = diff_stat_lines.iter().cloned().collect();
+        let reparsed = DiffStatParser::new()
+            .get_diff_stats_lines_at(&lines, 0)
+            .unwrap();
+        assert_eq!(reparsed.entries(), diff_stat_lines.entries());
+
+        let mut text = "--- /dev/null\n+++ b/big\n@@ -0,0 +1,100 @@\n".to_string();
+        for i in 0..100 {
+            text += &format!("+{i}\n");
+        }
+        let patch = parser
+            .parse_lines(&Lines::from_string(&text))
+            .unwrap()
+            .unwrap();
+        let renderer = DiffStatRenderer {
+            width: 40,
+            ..DiffStatRenderer::default()
+        };
+        let diff_stat_lines = renderer.render(&patch);
+        assert_eq!(
+            diff_stat_lines.iter().next().unwrap().as_str(),
+            format!(" big | 100 {}\n", "+".repeat(28))
+        );
+        assert_eq!(
+            diff_stat_lines.iter().nth(1).unwrap().as_str(),
+            " 1 file changed, 100 insertions(+)\n"
+        );
+    }
+}
@@ -1,11 +1,14 @@
 //Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
 
 use std::fmt::{self, Display, Formatter};
+use std::ops::AddAssign;
 use std::slice::Iter;
+use std::str::FromStr;
 
 use regex::Regex;
 
 use crate::lines::{Line, Lines};
+use crate::{requoted_path, unquoted_path};
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum DiffStatsCategory {
@@ -41,12 +44,30 @@ impl DiffStats {
     }
 }
 
+impl AddAssign for DiffStats {
+    fn add_assign(&mut self, other: DiffStats) {
+        for (count, other_count) in self.stats.iter_mut().zip(other.stats.iter()) {
+            *count += other_count;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct DiffStatsLines {
     lines: Lines,
     stats: DiffStats,
+    entries: Vec<DiffStatEntry>,
 }
 
 impl DiffStatsLines {
+    pub(crate) fn new(lines: Lines, stats: DiffStats, entries: Vec<DiffStatEntry>) -> Self {
+        Self {
+            lines,
+            stats,
+            entries,
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.lines.len()
     }
@@ -62,6 +83,230 @@ impl DiffStatsLines {
     pub fn stats(&self) -> &DiffStats {
         &self.stats
     }
+
+    pub fn entries(&self) -> &[DiffStatEntry] {
+        &self.entries
+    }
+}
+
+// The changes to a single file as shown in a diffstat summary.  NB: the
+// insertions and deletions of a text change are the graph's "+" and "-"
+// counts which will have been scaled if the graph was too wide.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffStatChange {
+    Text {
+        changes: u64,
+        insertions: u64,
+        deletions: u64,
+    },
+    Binary(Option<(usize, usize)>),
+}
+
+impl DiffStatChange {
+    pub(crate) fn changes(&self) -> u64 {
+        match self {
+            DiffStatChange::Text { changes, .. } => *changes,
+            DiffStatChange::Binary(_) => 0,
+        }
+    }
+
+    // Whether this (claimed) change is consistent with the actual change.
+    // Scaled graphs can only be checked for the presence of insertions
+    // and deletions and missing binary sizes can't be checked at all.
+    pub fn agrees_with(&self, actual: &DiffStatChange) -> bool {
+        match (self, actual) {
+            (
+                DiffStatChange::Text {
+                    changes,
+                    insertions,
+                    deletions,
+                },
+                DiffStatChange::Text {
+                    changes: actual_changes,
+                    insertions: actual_insertions,
+                    deletions: actual_deletions,
+                },
+            ) => {
+                if changes != actual_changes {
+                    false
+                } else if insertions + deletions == *changes {
+                    insertions == actual_insertions && deletions == actual_deletions
+                } else {
+                    (*insertions > 0) == (*actual_insertions > 0)
+                        && (*deletions > 0) == (*actual_deletions > 0)
+                }
+            }
+            (DiffStatChange::Binary(sizes), DiffStatChange::Binary(actual_sizes)) => {
+                sizes.is_none() || actual_sizes.is_none() || sizes == actual_sizes
+            }
+            // binary diffs without data have no (text) changes
+            (DiffStatChange::Binary(None), DiffStatChange::Text { changes, .. }) => *changes == 0,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffStatEntry {
+    ante_file_path: String,
+    post_file_path: String,
+    change: DiffStatChange,
+}
+
+impl DiffStatEntry {
+    pub(crate) fn new(
+        ante_file_path: String,
+        post_file_path: String,
+        change: DiffStatChange,
+    ) -> Self {
+        DiffStatEntry {
+            ante_file_path,
+            post_file_path,
+            change,
+        }
+    }
+
+    pub fn ante_file_path(&self) -> &str {
+        &self.ante_file_path
+    }
+
+    pub fn post_file_path(&self) -> &str {
+        &self.post_file_path
+    }
+
+    pub fn is_renamed(&self) -> bool {
+        self.ante_file_path != self.post_file_path
+    }
+
+    pub fn change(&self) -> &DiffStatChange {
+        &self.change
+    }
+
+    // Long paths may have been truncated to "...tail" so only the tail
+    // can be matched
+    pub fn matches_file_path(&self, file_path: &str) -> bool {
+        if let Some(tail) = self.post_file_path.strip_prefix("...") {
+            file_path.ends_with(tail)
+        } else {
+            file_path == self.post_file_path
+        }
+    }
+
+    // The path as git shows it (quoted if necessary)
+    pub(crate) fn display_path(&self) -> String {
+        if self.is_renamed() {
+            renamed_path(&self.ante_file_path, &self.post_file_path)
+        } else {
+            requoted_path(&self.post_file_path)
+        }
+    }
+}
+
+// A discrepancy between a patch's diffstat summary and its diffs
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffStatMismatch {
+    // listed in the summary but not changed by the patch
+    Unmatched(DiffStatEntry),
+    // changed by the patch but not listed in the summary
+    Unlisted(String),
+    // listed with a change that doesn't agree with the actual diff
+    Changed(DiffStatEntry, DiffStatChange),
+}
+
+// Show a renamed file's path in the form "dir/{old => new}/file" as git does
+// (or "old => new" with the paths quoted if either needs quoting)
+pub(crate) fn renamed_path(ante: &str, post: &str) -> String {
+    let (quoted_ante, quoted_post) = (requoted_path(ante), requoted_path(post));
+    if quoted_ante != ante || quoted_post != post {
+        return format!("{quoted_ante} => {quoted_post}");
+    }
+    let (ante_bytes, post_bytes) = (ante.as_bytes(), post.as_bytes());
+    let mut prefix_len = 0;
+    for (index, (a_byte, p_byte)) in ante_bytes.iter().zip(post_bytes.iter()).enumerate() {
+        if a_byte != p_byte {
+            break;
+        }
+        if *a_byte == b'/' {
+            prefix_len = index + 1;
+        }
+    }
+    // the suffix may share the prefix's final '/' but must not overlap it otherwise
+    let floor = prefix_len.saturating_sub(1);
+    let mut suffix_len = 0;
+    let (mut a_index, mut p_index) = (ante_bytes.len(), post_bytes.len());
+    while a_index >= floor && p_index >= floor && ante_bytes.get(a_index) == post_bytes.get(p_index)
+    {
+        if ante_bytes.get(a_index) == Some(&b'/') {
+            suffix_len = ante_bytes.len() - a_index;
+        }
+        if a_index == 0 || p_index == 0 {
+            break;
+        }
+        a_index -= 1;
+        p_index -= 1;
+    }
+    if prefix_len + suffix_len == 0 {
+        return format!("{ante} => {post}");
+    }
+    let ante_mid_len = ante.len().saturating_sub(prefix_len + suffix_len);
+    let post_mid_len = post.len().saturating_sub(prefix_len + suffix_len);
+    format!(
+        "{}{{{} => {}}}{}",
+        &ante[..prefix_len],
+        &ante[prefix_len..prefix_len + ante_mid_len],
+        &post[prefix_len..prefix_len + post_mid_len],
+        &ante[ante.len() - suffix_len..]
+    )
+}
+
+lazy_static! {
+    static ref QUOTED_PATH_CRE: Regex = Regex::new(r#"^"(?:[^"\\]|\\.)*"$"#).unwrap();
+}
+
+// Undo git's quoting of a diffstat path (if it's quoted)
+fn unquoted_stat_path(text: &str) -> String {
+    if QUOTED_PATH_CRE.is_match(text) {
+        unquoted_path(&text[1..text.len() - 1])
+    } else {
+        text.to_string()
+    }
+}
+
+// The inverse of renamed_path()
+fn split_renamed_path(path: &str) -> (String, String) {
+    // git doesn't abbreviate renames involving paths that it quotes and
+    // the paths it doesn't quote contain no '"'
+    if path.contains('"') {
+        let is_path = |text: &str| QUOTED_PATH_CRE.is_match(text) || !text.contains('"');
+        for (index, _) in path.match_indices(" => ") {
+            let (ante, post) = (&path[..index], &path[index + 4..]);
+            if is_path(ante) && is_path(post) {
+                return (unquoted_stat_path(ante), unquoted_stat_path(post));
+            }
+        }
+        let path = unquoted_stat_path(path);
+        return (path.clone(), path);
+    }
+    if let (Some(open), Some(close)) = (path.find('{'), path.rfind('}')) {
+        if let Some(arrow) = path[open..close].find(" => ") {
+            let (prefix, suffix) = (&path[..open], &path[close + 1..]);
+            let ante_mid = &path[open + 1..open + arrow];
+            let post_mid = &path[open + arrow + 4..close];
+            let joined = |mid: &str| {
+                if mid.is_empty() && prefix.ends_with('/') && suffix.starts_with('/') {
+                    format!("{prefix}{}", &suffix[1..])
+                } else {
+                    format!("{prefix}{mid}{suffix}")
+                }
+            };
+            return (joined(ante_mid), joined(post_mid));
+        }
+    }
+    if let Some((ante, post)) = path.split_once(" => ") {
+        (ante.to_string(), post.to_string())
+    } else {
+        (path.to_string(), path.to_string())
+    }
 }
 
 pub struct DiffStatParser {
@@ -90,8 +335,10 @@ impl DiffStatParser {
         DiffStatParser {
             empty_cre: Regex::new(r"^#? 0 files changed(\n)?$").unwrap(),
             end_cre: Regex::new(&end_cre_str).unwrap(),
-            file_stats_cre: Regex::new(r"^#? (\S+)\s*\|((binary)|(\s*(\d+)(\s+\+*-*!*)?))(\n)$")
-                .unwrap(),
+            file_stats_cre: Regex::new(
+                r"^#? (\S.*?)\s*\|\s*((binary)|(Bin( (\d+) -> (\d+) bytes)?)|((\d+)(\s+(\+*)(-*)(!*))?))(\n)$",
+            )
+            .unwrap(),
             blank_line_cre: Regex::new(r"^\s*(\n)$").unwrap(),
             divider_line_cre: Regex::new(r"^---(\n)$").unwrap(),
         }
@@ -125,12 +372,197 @@ impl DiffStatParser {
         // TODO: worry about malformed summary
         None
     }
+
+    fn get_entry(&self, line: &Line) -> Option<DiffStatEntry> {
+        let captures = self.file_stats_cre.captures(line)?;
+        let (ante_file_path, post_file_path) = split_renamed_path(captures.get(1)?.as_str());
+        let count = |index: usize| -> u64 {
+            match captures.get(index) {
+                Some(capture) => capture.as_str().chars().count() as u64,
+                None => 0,
+            }
+        };
+        let change = if captures.get(3).is_some() {
+            DiffStatChange::Binary(None)
+        } else if captures.get(4).is_some() {
+            match (captures.get(6), captures.get(7)) {
+                (Some(ante_size), Some(post_size)) => DiffStatChange::Binary(Some((
+                    usize::from_str(ante_size.as_str()).ok()?,
+                    usize::from_str(post_size.as_str()).ok()?,
+                ))),
+                _ => DiffStatChange::Binary(None),
+            }
+        } else {
+            DiffStatChange::Text {
+                changes: u64::from_str(captures.get(9)?.as_str()).ok()?,
+                insertions: count(11),
+                deletions: count(12),
+            }
+        };
+        Some(DiffStatEntry {
+            ante_file_path,
+            post_file_path,
+            change,
+        })
+    }
+
+    // The diffstat summary starting at the given index parsed into its
+    // per file entries and totals
+    pub fn get_diff_stats_lines_at(
+        &self,
+        lines: &[Line],
+        start_index: usize,
+    ) -> Option<DiffStatsLines> {
+        let (start_index, end_index) = self.get_summary_line_range_at(lines, start_index)?;
+        let mut stats = DiffStats::default();
+        if let Some(captures) = self.end_cre.captures(&lines[end_index]) {
+            for (index, category) in [
+                (3, DiffStatsCategory::Inserted),
+                (5, DiffStatsCategory::Deleted),
+                (7, DiffStatsCategory::Modified),
+            ] {
+                if let Some(count) = captures.get(index) {
+                    stats.incr_count(category, u64::from_str(count.as_str()).ok()?);
+                }
+            }
+        }
+        let entries = lines[start_index..end_index]
+            .iter()
+            .filter_map(|line| self.get_entry(line))
+            .collect();
+        Some(DiffStatsLines {
+            lines: lines[start_index..=end_index].to_vec(),
+            stats,
+            entries,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::lines::LinesIfce;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn renamed_path_works() {
+        assert_eq!(
+            renamed_path("dir/a.txt", "dir/b.txt"),
+            "dir/{a.txt => b.txt}"
+        );
+        assert_eq!(renamed_path("a/x/c", "a/y/c"), "a/{x => y}/c");
+        assert_eq!(renamed_path("a/b", "a/c/b"), "a/{ => c}/b");
+        assert_eq!(renamed_path("a", "b"), "a => b");
+        assert_eq!(
+            renamed_path("dir/old", "dir/na\u{ef}ve"),
+            r#"dir/old => "dir/na\303\257ve""#
+        );
+    }
+
+    #[test]
+    fn split_renamed_path_works() {
+        for (ante, post) in [
+            ("dir/a.txt", "dir/b.txt"),
+            ("a/x/c", "a/y/c"),
+            ("a/b", "a/c/b"),
+            ("a/c/b", "a/b"),
+            ("a", "b"),
+            ("dir/old", "dir/na\u{ef}ve"),
+        ] {
+            assert_eq!(
+                split_renamed_path(&renamed_path(ante, post)),
+                (ante.to_string(), post.to_string())
+            );
+        }
+        assert_eq!(
+            split_renamed_path("a/b"),
+            ("a/b".to_string(), "a/b".to_string())
+        );
+        // from "git diff --stat" for files whose names git quotes
+        assert_eq!(
+            split_renamed_path(r#""caf\303\251""#),
+            ("caf\u{e9}".to_string(), "caf\u{e9}".to_string())
+        );
+        assert_eq!(
+            split_renamed_path(r#"old => "na\303\257ve""#),
+            ("old".to_string(), "na\u{ef}ve".to_string())
+        );
+        assert_eq!(
+            split_renamed_path(r#""a => \"b\"" => c"#),
+            ("a => \"b\"".to_string(), "c".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_diff_stats() {
+        let text = "---
+ f                    |   3 ++-
+ dir/{a.txt => b.txt} |   2 +-
+ big                  | 100 ++++++++++++++++++++++++++++
+ .../deep/path.rs     |   0
+ bin                  | Bin 0 -> 3 bytes
+ old                  | binary
+ 6 files changed, 102 insertions(+), 2 deletions(-)
+";
+        let lines = Lines::from_string(text);
+        let diff_stats_lines = DiffStatParser::new()
+            .get_diff_stats_lines_at(&lines, 0)
+            .unwrap();
+        assert_eq!(diff_stats_lines.len(), lines.len());
+        assert_eq!(
+            diff_stats_lines.stats().count(DiffStatsCategory::Inserted),
+            102
+        );
+        assert_eq!(
+            diff_stats_lines.stats().count(DiffStatsCategory::Deleted),
+            2
+        );
+        let entries = diff_stats_lines.entries();
+        assert_eq!(entries.len(), 6);
+        assert_eq!(entries[0].post_file_path(), "f");
+        assert!(!entries[0].is_renamed());
+        assert_eq!(
+            *entries[0].change(),
+            DiffStatChange::Text {
+                changes: 3,
+                insertions: 2,
+                deletions: 1
+            }
+        );
+        assert!(entries[1].is_renamed());
+        assert_eq!(entries[1].ante_file_path(), "dir/a.txt");
+        assert_eq!(entries[1].post_file_path(), "dir/b.txt");
+        let actual = DiffStatChange::Text {
+            changes: 100,
+            insertions: 100,
+            deletions: 0,
+        };
+        assert!(entries[2].change().agrees_with(&actual));
+        let actual = DiffStatChange::Text {
+            changes: 100,
+            insertions: 99,
+            deletions: 1,
+        };
+        assert!(!entries[2].change().agrees_with(&actual));
+        assert!(entries[3].matches_file_path("some/deep/path.rs"));
+        assert!(!entries[3].matches_file_path("some/shallow/path.rs"));
+        assert_eq!(*entries[4].change(), DiffStatChange::Binary(Some((0, 3))));
+        assert!(!entries[4]
+            .change()
+            .agrees_with(&DiffStatChange::Binary(Some((0, 4)))));
+        assert_eq!(*entries[5].change(), DiffStatChange::Binary(None));
+        assert!(entries[5]
+            .change()
+            .agrees_with(&DiffStatChange::Binary(Some((7, 0)))));
+
+        let lines = Lines::from_string(" 0 files changed\n");
+        let diff_stats_lines = DiffStatParser::new()
+            .get_diff_stats_lines_at(&lines, 0)
+            .unwrap();
+        assert!(diff_stats_lines.entries().is_empty());
+    }
 }
@@ -15,7 +15,7 @@ use crate::lines::{Line, Lines};
 use crate::text_diff::{DiffParseError, DiffParseResult};
 use crate::DiffFormat;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum GitBinaryDiffMethod {
     Delta,
     Literal,
@@ -47,7 +47,7 @@ impl FromStr for GitBinaryDiffMethod {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GitBinaryDiffData {
     lines: Lines,
     method: GitBinaryDiffMethod,
@@ -91,9 +91,24 @@ impl GitBinaryDiffData {
         };
         git_delta::patch_delta(data, &delta).map_err(DiffParseError::GitDeltaError)
     }
+
+    // The size of the contents that result from applying this data
+    pub fn result_size(&self) -> DiffParseResult<usize> {
+        match self.method {
+            GitBinaryDiffMethod::Literal => Ok(self.len_raw),
+            GitBinaryDiffMethod::Delta => {
+                let delta = self.get_raw_data()?;
+                let (_, bytes_used) =
+                    git_delta::get_delta_hdr_size(&delta).map_err(DiffParseError::GitDeltaError)?;
+                let (size, _) = git_delta::get_delta_hdr_size(&delta[bytes_used..])
+                    .map_err(DiffParseError::GitDeltaError)?;
+                Ok(size)
+            }
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GitBinaryDiff {
     lines: Lines,
     forward: GitBinaryDiffData,
@@ -142,6 +157,28 @@ impl GitBinaryDiff {
             }
         }
     }
+
+    // The sizes (in bytes) of the ante and post contents
+    pub fn sizes(&self) -> DiffParseResult<(usize, usize)> {
+        Ok((self.reverse.result_size()?, self.forward.result_size()?))
+    }
+
+    // The diff with its forward and reverse data swapped
+    pub fn reversed(&self) -> GitBinaryDiff {
+        let blank_line = Line::new("\n".to_string());
+        let mut lines = vec![self.lines[0].clone()];
+        lines.extend(self.reverse.iter().cloned());
+        lines.push(blank_line.clone());
+        lines.extend(self.forward.iter().cloned());
+        // NB: the final blank line is missing if the diff ends the file
+        if self.lines.len() > self.forward.len() + self.reverse.len() + 2 {
+            lines.push(blank_line);
+        }
+        GitBinaryDiffParser::new()
+            .get_diff_at(&lines, 0)
+            .expect("reversed diffs should always parse")
+            .expect("reversed diffs should always parse")
+    }
 }
 
 pub struct GitBinaryDiffParser {
@@ -254,4 +291,38 @@ mod tests {
             assert!(diff.reverse.get_raw_data().is_ok());
         }
     }
+
+    #[test]
+    fn git_binary_diff_reversed() {
+        let lines = Lines::read_from(Path::new("../test_diffs/test_2.binary_diff")).unwrap();
+        let parser = GitBinaryDiffParser::new();
+        for start_index in &[2, 12, 21, 30, 39, 49] {
+            let diff = parser.get_diff_at(&lines, *start_index).unwrap().unwrap();
+            let reversed = diff.reversed();
+            assert_eq!(
+                reversed.forward.get_raw_data().unwrap(),
+                diff.reverse.get_raw_data().unwrap()
+            );
+            assert_eq!(
+                reversed.reverse.get_raw_data().unwrap(),
+                diff.forward.get_raw_data().unwrap()
+            );
+            assert!(reversed.reversed().iter().eq(diff.iter()));
+        }
+    }
+
+    #[test]
+    fn git_binary_diff_sizes() {
+        let lines = Lines::read_from(Path::new("../test_diffs/test_2.binary_diff")).unwrap();
+        let parser = GitBinaryDiffParser::new();
+        for start_index in &[2, 21, 30, 39] {
+            let diff = parser.get_diff_at(&lines, *start_index).unwrap().unwrap();
+            let (ante_size, post_size) = diff.sizes().unwrap();
+            assert_eq!(diff.reversed().sizes().unwrap(), (post_size, ante_size));
+        }
+        let diff = parser.get_diff_at(&lines, 12).unwrap().unwrap();
+        assert_eq!(diff.sizes().unwrap(), (27, 0));
+        let diff = parser.get_diff_at(&lines, 49).unwrap().unwrap();
+        assert_eq!(diff.sizes().unwrap(), (0, 21));
+    }
 }
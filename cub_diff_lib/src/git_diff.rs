@@ -0,0 +1,389 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! This module implements the generation of git style diffs including
+//! the detection of renamed and copied files by content similarity.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::diff::{DiffPlus, DiffPlusParser};
+use crate::lines::{Line, Lines};
+use crate::text_diff::TextDiffHunk;
+use crate::unified_diff::UnifiedDiffGenerator;
+
+// Percentage of the larger file's content (in bytes) that is also
+// present in the other file.
+pub fn similarity_index(ante_lines: &[Line], post_lines: &[Line]) -> u8 {
+    let ante_size: usize = ante_lines.iter().map(|line| line.len()).sum();
+    let post_size: usize = post_lines.iter().map(|line| line.len()).sum();
+    let max_size = ante_size.max(post_size);
+    if max_size == 0 {
+        return 100;
+    }
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for line in ante_lines.iter() {
+        *counts.entry(line.as_str()).or_insert(0) += 1;
+    }
+    let mut common_size = 0;
+    for line in post_lines.iter() {
+        if let Some(count) = counts.get_mut(line.as_str()) {
+            if *count > 0 {
+                *count -= 1;
+                common_size += line.len();
+            }
+        }
+    }
+    (common_size * 100 / max_size) as u8
+}
+
+#[derive(Debug, Clone)]
+pub struct FileData {
+    pub path: String,
+    pub mode: u32,
+    pub lines: Lines,
+}
+
+impl FileData {
+    pub fn new(path: &str, lines: Lines) -> Self {
+        FileData {
+            path: path.to_string(),
+            mode: 0o100644,
+            lines,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum FileChange {
+    Added(FileData),
+    Deleted(FileData),
+    Modified(FileData, FileData),
+    // only of interest as a potential copy source
+    Unchanged(FileData),
+}
+
+impl FileChange {
+    fn ante_data(&self) -> Option<&FileData> {
+        match self {
+            FileChange::Added(_) => None,
+            FileChange::Deleted(data) => Some(data),
+            FileChange::Modified(data, _) => Some(data),
+            FileChange::Unchanged(data) => Some(data),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PairingKind {
+    Rename,
+    Copy,
+}
+
+// The indices refer to the list of changes that the pairing was
+// detected in and "post_index" is always that of an added file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilePairing {
+    pub kind: PairingKind,
+    pub ante_index: usize,
+    pub post_index: usize,
+    pub similarity: u8,
+}
+
+pub struct GitDiffGenerator {
+    // minimum similarity (%) for an added/deleted pair to be a rename
+    pub rename_threshold: Option<u8>,
+    // minimum similarity (%) for an added file to be a copy
+    pub copy_threshold: Option<u8>,
+    // minimum dissimilarity (%) for a modification to be flagged as a rewrite
+    pub break_threshold: Option<u8>,
+    pub unified_diff_generator: UnifiedDiffGenerator,
+}
+
+impl Default for GitDiffGenerator {
+    fn default() -> Self {
+        GitDiffGenerator {
+            rename_threshold: Some(50),
+            copy_threshold: None,
+            break_threshold: None,
+            unified_diff_generator: UnifiedDiffGenerator::default(),
+        }
+    }
+}
+
+impl GitDiffGenerator {
+    pub fn detect_pairings(&self, changes: &[FileChange]) -> Vec<FilePairing> {
+        let added: Vec<usize> = (0..changes.len())
+            .filter(|&i| matches!(changes[i], FileChange::Added(_)))
+            .collect();
+        let mut paired = vec![false; changes.len()];
+        let mut pairings = vec![];
+        if let Some(threshold) = self.rename_threshold {
+            let mut candidates = vec![];
+            for (ante_index, change) in changes.iter().enumerate() {
+                if let FileChange::Deleted(ante) = change {
+                    for &post_index in added.iter() {
+                        if let FileChange::Added(post) = &changes[post_index] {
+                            let similarity = similarity_index(&ante.lines, &post.lines);
+                            if similarity >= threshold {
+                                candidates.push((similarity, ante_index, post_index));
+                            }
+                        }
+                    }
+                }
+            }
+            // best matches first (stable sort keeps input order for ties)
+            candidates.sort_by_key(|candidate| Reverse(candidate.0));
+            for (similarity, ante_index, post_index) in candidates {
+                if paired[ante_index] || paired[post_index] {
+                    continue;
+                }
+                paired[ante_index] = true;
+                paired[post_index] = true;
+                pairings.push(FilePairing {
+                    kind: PairingKind::Rename,
+                    ante_index,
+                    post_index,
+                    similarity,
+                });
+            }
+        }
+        if let Some(threshold) = self.copy_threshold {
+            for &post_index in added.iter() {
+                let post = match &changes[post_index] {
+                    FileChange::Added(post) if !paired[post_index] => post,
+                    _ => continue,
+                };
+                let mut best: Option<(u8, usize)> = None;
+                for (ante_index, change) in changes.iter().enumerate() {
+                    if let Some(ante) = change.ante_data() {
+                        let similarity = similarity_index(&ante.lines, &post.lines);
+                        if similarity >= threshold
+                            && !matches!(best, Some((s, _)) if s >= similarity)
+                        {
+                            best = Some((similarity, ante_index));
+                        }
+                    }
+                }
+                if let Some((similarity, ante_index)) = best {
+                    paired[post_index] = true;
+                    pairings.push(FilePairing {
+                        kind: PairingKind::Copy,
+                        ante_index,
+                        post_index,
+                        similarity,
+                    });
+                }
+            }
+        }
+        pairings.sort_by_key(|pairing| pairing.post_index);
+        pairings
+    }
+
+    fn push_text_diff(
+        &self,
+        lines: &mut Lines,
+        ante_file_path: &str,
+        post_file_path: &str,
+        ante_lines: &[Line],
+        post_lines: &[Line],
+    ) {
+        if let Some(diff) = self.unified_diff_generator.generate(
+            ante_file_path,
+            post_file_path,
+            ante_lines,
+            post_lines,
+        ) {
+            lines.extend(diff.header().lines.iter().cloned());
+            for hunk in diff.hunks().iter() {
+                lines.extend(hunk.iter().cloned());
+            }
+        }
+    }
+
+    pub fn generate_lines(&self, changes: &[FileChange]) -> Lines {
+        let pairings = self.detect_pairings(changes);
+        let renamed: Vec<usize> = pairings
+            .iter()
+            .filter(|p| p.kind == PairingKind::Rename)
+            .map(|p| p.ante_index)
+            .collect();
+        let mut lines = vec![];
+        for (index, change) in changes.iter().enumerate() {
+            match change {
+                FileChange::Added(post) => {
+                    if let Some(pairing) = pairings.iter().find(|p| p.post_index == index) {
+                        // unwrap() is safe as pairings only involve files with ante data
+                        let ante = changes[pairing.ante_index].ante_data().unwrap();
+                        let kind = match pairing.kind {
+                            PairingKind::Rename => "rename",
+                            PairingKind::Copy => "copy",
+                        };
+                        lines.push(Line::new(format!(
+                            "diff --git a/{} b/{}\n",
+                            ante.path, post.path
+                        )));
+                        if ante.mode != post.mode {
+                            lines.push(Line::new(format!("old mode {:o}\n", ante.mode)));
+                            lines.push(Line::new(format!("new mode {:o}\n", post.mode)));
+                        }
+                        lines.push(Line::new(format!(
+                            "similarity index {}%\n",
+                            pairing.similarity
+                        )));
+                        lines.push(Line::new(format!("{} from {}\n", kind, ante.path)));
+                        lines.push(Line::new(format!("{} to {}\n", kind, post.path)));
+                        self.push_text_diff(
+                            &mut lines,
+                            &format!("a/{}", ante.path),
+                            &format!("b/{}", post.path),
+                            &ante.lines,
+                            &post.lines,
+                        );
+                    } else {
+                        lines.push(Line::new(format!(
+                            "diff --git a/{} b/{}\n",
+                            post.path, post.path
+                        )));
+                        lines.push(Line::new(format!("new file mode {:o}\n", post.mode)));
+                        self.push_text_diff(
+                            &mut lines,
+                            "/dev/null",
+                            &format!("b/{}", post.path),
+                            &[],
+                            &post.lines,
+                        );
+                    }
+                }
+                FileChange::Deleted(ante) => {
+                    if renamed.contains(&index) {
+                        continue;
+                    }
+                    lines.push(Line::new(format!(
+                        "diff --git a/{} b/{}\n",
+                        ante.path, ante.path
+                    )));
+                    lines.push(Line::new(format!("deleted file mode {:o}\n", ante.mode)));
+                    self.push_text_diff(
+                        &mut lines,
+                        &format!("a/{}", ante.path),
+                        "/dev/null",
+                        &ante.lines,
+                        &[],
+                    );
+                }
+                FileChange::Modified(ante, post) => {
+                    lines.push(Line::new(format!(
+                        "diff --git a/{} b/{}\n",
+                        ante.path, post.path
+                    )));
+                    if ante.mode != post.mode {
+                        lines.push(Line::new(format!("old mode {:o}\n", ante.mode)));
+                        lines.push(Line::new(format!("new mode {:o}\n", post.mode)));
+                    }
+                    if let Some(threshold) = self.break_threshold {
+                        let dissimilarity = 100 - similarity_index(&ante.lines, &post.lines);
+                        if dissimilarity >= threshold {
+                            lines
+                                .push(Line::new(format!("dissimilarity index {dissimilarity}%\n")));
+                        }
+                    }
+                    self.push_text_diff(
+                        &mut lines,
+                        &format!("a/{}", ante.path),
+                        &format!("b/{}", post.path),
+                        &ante.lines,
+                        &post.lines,
+                    );
+                }
+                FileChange::Unchanged(_) => (),
+            }
+        }
+        lines
+    }
+
+    pub fn generate(&self, changes: &[FileChange]) -> Vec<DiffPlus> {
+        let lines = self.generate_lines(changes);
+        let parser = DiffPlusParser::new();
+        let mut diff_pluses = vec![];
+        let mut index = 0;
+        while index < lines.len() {
+            let diff_plus = parser
+                .get_diff_plus_at(&lines, index)
+                .expect("generated diffs should always parse")
+                .expect("generated diffs should always parse");
+            index += diff_plus.len();
+            diff_pluses.push(diff_plus);
+        }
+        diff_pluses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::Diff;
+    use crate::lines::LinesIfce;
+
+    static ORIGINAL: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+    static EDITED: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\n10\n";
+    static KEPT: &str = "alpha\nbeta\ngamma\ndelta\n";
+
+    #[test]
+    fn similarity_index_works() {
+        let original = Lines::from_string(ORIGINAL);
+        assert_eq!(similarity_index(&original, &original), 100);
+        assert_eq!(similarity_index(&original, &Lines::from_string(EDITED)), 91);
+        assert_eq!(similarity_index(&original, &Lines::from_string("x\n")), 0);
+        assert_eq!(similarity_index(&[], &[]), 100);
+    }
+
+    #[test]
+    fn renames_and_copies_detected() {
+        let changes = vec![
+            FileChange::Deleted(FileData::new("old", Lines::from_string(ORIGINAL))),
+            FileChange::Added(FileData::new("new", Lines::from_string(EDITED))),
+            FileChange::Unchanged(FileData::new("kept", Lines::from_string(KEPT))),
+            FileChange::Added(FileData::new("copy", Lines::from_string(KEPT))),
+            FileChange::Added(FileData::new("other", Lines::from_string("x\ny\n"))),
+        ];
+        let generator = GitDiffGenerator {
+            copy_threshold: Some(80),
+            ..GitDiffGenerator::default()
+        };
+        let diff_pluses = generator.generate(&changes);
+        assert_eq!(diff_pluses.len(), 3);
+
+        let preamble = match diff_pluses[0].preamble() {
+            Some(crate::preamble::Preamble::Git(preamble)) => preamble,
+            _ => panic!("expected a git preamble"),
+        };
+        assert_eq!(preamble.get_extra("similarity index"), Some("91%"));
+        assert_eq!(preamble.get_extra("rename from"), Some("old"));
+        assert_eq!(preamble.get_extra("rename to"), Some("new"));
+        assert!(matches!(diff_pluses[0].diff(), Diff::Unified(_)));
+
+        match diff_pluses[1].diff() {
+            Diff::GitPreambleOnly(preamble) => {
+                assert_eq!(preamble.get_extra("similarity index"), Some("100%"));
+                assert_eq!(preamble.get_extra("copy from"), Some("kept"));
+                assert_eq!(preamble.get_extra("copy to"), Some("copy"));
+            }
+            _ => panic!("expected a preamble only diff"),
+        }
+        assert_eq!(diff_pluses[2].get_file_path(1), "other");
+    }
+
+    #[test]
+    fn rewrites_flagged() {
+        let changes = vec![FileChange::Modified(
+            FileData::new("file", Lines::from_string(ORIGINAL)),
+            FileData::new("file", Lines::from_string("x\ny\n")),
+        )];
+        let generator = GitDiffGenerator {
+            break_threshold: Some(60),
+            ..GitDiffGenerator::default()
+        };
+        let lines = generator.generate_lines(&changes);
+        assert_eq!(*lines[1], "dissimilarity index 100%\n");
+    }
+}
@@ -0,0 +1,164 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Find the "function line" (i.e. the heading of the enclosing section
+//! or function) to be displayed after a unified diff hunk's ranges in
+//! the manner of "diff -p/-F" and git's userdiff drivers.
+
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::lines::Line;
+
+// Git truncates headings to this length
+const MAX_HEADING_LEN: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    C,
+    Cpp,
+    Go,
+    Java,
+    Python,
+    Rust,
+}
+
+impl Language {
+    pub fn from_file_path(file_path: &str) -> Option<Language> {
+        let extension = Path::new(file_path).extension()?.to_str()?;
+        match extension {
+            "c" | "h" => Some(Language::C),
+            "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" => Some(Language::Cpp),
+            "go" => Some(Language::Go),
+            "java" => Some(Language::Java),
+            "py" => Some(Language::Python),
+            "rs" => Some(Language::Rust),
+            _ => None,
+        }
+    }
+
+    // Patterns (taken from git's userdiff drivers) as used by HeadingMatcher::new()
+    fn patterns(self) -> &'static [&'static str] {
+        match self {
+            Language::C | Language::Cpp => &[
+                // jump targets and access declarations
+                r"!^[ \t]*[A-Za-z_][A-Za-z_0-9]*:\s*($|/[/*])",
+                // functions/methods, variables and compounds at top level
+                r"^((::\s*)?[A-Za-z_].*)$",
+            ],
+            Language::Go => &[
+                r"^[ \t]*(func[ \t]*.*(\{[ \t]*)?)$",
+                r"^[ \t]*(type[ \t].*(struct|interface)[ \t]*(\{[ \t]*)?)$",
+            ],
+            Language::Java => &[
+                r"!^[ \t]*(catch|do|for|if|instanceof|new|return|switch|throw|while)",
+                r"^[ \t]*(([A-Za-z_][\[\]?&<>.,A-Za-z_0-9]*[ \t]+)+[A-Za-z_][A-Za-z_0-9]*[ \t]*\([^;]*)$",
+            ],
+            Language::Python => &[r"^[ \t]*((class|(async[ \t]+)?def)[ \t].*)$"],
+            Language::Rust => &[
+                r"^[\t ]*((pub(\([^\)]+\))?[\t ]+)?((async|const|crate|extern|unsafe)[\t ]+)*(struct|enum|union|mod|trait|fn|impl|macro_rules!)[<\t ]+[^;]*)$",
+            ],
+        }
+    }
+}
+
+// Lines are tested against each pattern in order and the first that
+// matches decides: patterns starting with "!" reject the line and the
+// others accept it (with the heading being the first capture group if
+// there is one and the whole line otherwise).
+#[derive(Debug, Clone)]
+pub struct HeadingMatcher {
+    patterns: Vec<(bool, Regex)>,
+}
+
+impl Default for HeadingMatcher {
+    // Like "diff -p": any line starting with a letter, '_' or '$'
+    fn default() -> Self {
+        HeadingMatcher::new(&[r"^[[:alpha:]$_]"]).unwrap()
+    }
+}
+
+impl HeadingMatcher {
+    pub fn new(patterns: &[&str]) -> Result<Self, regex::Error> {
+        let mut compiled = vec![];
+        for pattern in patterns.iter() {
+            if let Some(pattern) = pattern.strip_prefix('!') {
+                compiled.push((false, Regex::new(pattern)?));
+            } else {
+                compiled.push((true, Regex::new(pattern)?));
+            }
+        }
+        Ok(HeadingMatcher { patterns: compiled })
+    }
+
+    pub fn for_language(language: Language) -> Self {
+        HeadingMatcher::new(language.patterns()).unwrap()
+    }
+
+    // The matcher for the file's language falling back to the default
+    pub fn for_file_path(file_path: &str) -> Self {
+        if let Some(language) = Language::from_file_path(file_path) {
+            HeadingMatcher::for_language(language)
+        } else {
+            HeadingMatcher::default()
+        }
+    }
+
+    // Return the heading for "line" if it is a function line
+    pub fn heading_for(&self, line: &str) -> Option<String> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        for (accept, regex) in self.patterns.iter() {
+            if let Some(captures) = regex.captures(line) {
+                if !accept {
+                    return None;
+                }
+                let text = captures.get(1).map_or(line, |m| m.as_str()).trim_end();
+                let mut end = text.len().min(MAX_HEADING_LEN);
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                return Some(text[..end].to_string());
+            }
+        }
+        None
+    }
+
+    // Return the heading for the nearest function line before "index"
+    pub fn find_heading(&self, lines: &[Line], index: usize) -> Option<String> {
+        lines[..index.min(lines.len())]
+            .iter()
+            .rev()
+            .find_map(|line| self.heading_for(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::{Lines, LinesIfce};
+
+    #[test]
+    fn rust_headings() {
+        let lines = Lines::from_string(
+            "use std::fmt;\n\npub(crate) fn outer(x: usize) -> usize {\n    let y = x;\n    y\n}\n",
+        );
+        let matcher = HeadingMatcher::for_file_path("src/lib.rs");
+        assert_eq!(matcher.find_heading(&lines, 1), None);
+        assert_eq!(
+            matcher.find_heading(&lines, 5),
+            Some("pub(crate) fn outer(x: usize) -> usize {".to_string())
+        );
+    }
+
+    #[test]
+    fn c_headings_skip_labels() {
+        let lines = Lines::from_string("int main(void)\n{\nout:\n\treturn 0;\n}\n");
+        let matcher = HeadingMatcher::for_language(Language::C);
+        assert_eq!(
+            matcher.find_heading(&lines, 4),
+            Some("int main(void)".to_string())
+        );
+        let matcher = HeadingMatcher::default();
+        assert_eq!(matcher.find_heading(&lines, 4), Some("out:".to_string()));
+    }
+}
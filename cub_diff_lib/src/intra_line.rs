@@ -0,0 +1,173 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Find the parts of a changed line that actually changed so that they
+//! can be highlighted when the diff is displayed.
+
+use std::ops::Range;
+
+use crate::diff_algorithm::{DiffAlgorithm, DiffComponent, Myers};
+use crate::lines::Line;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Granularity {
+    Word,
+    Character,
+}
+
+// Indices are into the hunk's lines and spans are byte ranges within
+// those lines (including the diff prefix characters).
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntraLineChange {
+    pub ante_line_index: usize,
+    pub ante_spans: Vec<Range<usize>>,
+    pub post_line_index: usize,
+    pub post_spans: Vec<Range<usize>>,
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+// Split the text into words, runs of white space and single
+// punctuation characters (or just characters) with their byte offsets.
+fn tokenize(text: &str, granularity: Granularity) -> Vec<(usize, &str)> {
+    let mut tokens = vec![];
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        let mut end = start + ch.len_utf8();
+        if granularity == Granularity::Word && (is_word_char(ch) || ch.is_whitespace()) {
+            let same_kind = |other: char| {
+                if is_word_char(ch) {
+                    is_word_char(other)
+                } else {
+                    other.is_whitespace()
+                }
+            };
+            while let Some(&(index, next_ch)) = chars.peek() {
+                if !same_kind(next_ch) {
+                    break;
+                }
+                end = index + next_ch.len_utf8();
+                chars.next();
+            }
+        }
+        tokens.push((start, &text[start..end]));
+    }
+    tokens
+}
+
+fn push_span(spans: &mut Vec<Range<usize>>, span: Range<usize>) {
+    if let Some(last) = spans.last_mut() {
+        if last.end == span.start {
+            last.end = span.end;
+            return;
+        }
+    }
+    spans.push(span);
+}
+
+// Return the byte ranges of the changed parts of "ante_text" and
+// "post_text" respectively.
+pub fn changed_spans(
+    ante_text: &str,
+    post_text: &str,
+    granularity: Granularity,
+) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    let ante_tokens = tokenize(ante_text, granularity);
+    let post_tokens = tokenize(post_text, granularity);
+    let ante_strs: Vec<&str> = ante_tokens.iter().map(|t| t.1).collect();
+    let post_strs: Vec<&str> = post_tokens.iter().map(|t| t.1).collect();
+    let mut ante_spans = vec![];
+    let mut post_spans = vec![];
+    for component in Myers.diff(&ante_strs, &post_strs) {
+        match component {
+            DiffComponent::Deletion(index) => {
+                let (start, token) = ante_tokens[index];
+                push_span(&mut ante_spans, start..start + token.len());
+            }
+            DiffComponent::Insertion(index) => {
+                let (start, token) = post_tokens[index];
+                push_span(&mut post_spans, start..start + token.len());
+            }
+            DiffComponent::Unchanged(_, _) => (),
+        }
+    }
+    (ante_spans, post_spans)
+}
+
+// The text of a diff line without its prefix or line terminator
+fn line_text(line: &Line, prefix_len: usize) -> &str {
+    let text = &line[prefix_len.min(line.len())..];
+    text.strip_suffix('\n').unwrap_or(text)
+}
+
+// Work out the intra line changes for the given (ante, post) pairs of
+// indices into "lines" (whose diff prefixes are "prefix_len" long).
+pub fn intra_line_changes(
+    lines: &[Line],
+    pairs: &[(usize, usize)],
+    prefix_len: usize,
+    granularity: Granularity,
+) -> Vec<IntraLineChange> {
+    let offset = |spans: Vec<Range<usize>>| -> Vec<Range<usize>> {
+        spans
+            .into_iter()
+            .map(|span| span.start + prefix_len..span.end + prefix_len)
+            .collect()
+    };
+    pairs
+        .iter()
+        .map(|&(ante_line_index, post_line_index)| {
+            let (ante_spans, post_spans) = changed_spans(
+                line_text(&lines[ante_line_index], prefix_len),
+                line_text(&lines[post_line_index], prefix_len),
+                granularity,
+            );
+            IntraLineChange {
+                ante_line_index,
+                ante_spans: offset(ante_spans),
+                post_line_index,
+                post_spans: offset(post_spans),
+            }
+        })
+        .collect()
+}
+
+// Pair off the lines in two runs of changed lines in order
+pub(crate) fn pair_runs(
+    pairs: &mut Vec<(usize, usize)>,
+    ante_run: &mut Vec<usize>,
+    post_run: &mut Vec<usize>,
+) {
+    pairs.extend(ante_run.drain(..).zip(post_run.drain(..)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_spans() {
+        let (ante, post) = changed_spans(
+            "let total = count * 2;",
+            "let total = count * factor;",
+            Granularity::Word,
+        );
+        assert_eq!(ante, vec![Range { start: 20, end: 21 }]);
+        assert_eq!(post, vec![Range { start: 20, end: 26 }]);
+    }
+
+    #[test]
+    fn character_spans() {
+        let (ante, post) = changed_spans(
+            "The Named is the mother",
+            "The named is the mother",
+            Granularity::Character,
+        );
+        assert_eq!(ante, vec![Range { start: 4, end: 5 }]);
+        assert_eq!(post, vec![Range { start: 4, end: 5 }]);
+        let (ante, post) = changed_spans("café", "cafés", Granularity::Character);
+        assert!(ante.is_empty());
+        assert_eq!(post, vec![Range { start: 5, end: 6 }]);
+    }
+}
@@ -2,7 +2,6 @@
 
 extern crate crypto_hash;
 extern crate inflate;
-extern crate lcs;
 extern crate regex;
 #[macro_use]
 extern crate lazy_static;
@@ -14,18 +13,97 @@ use std::slice::Iter;
 pub mod abstract_diff;
 pub mod context_diff;
 pub mod diff;
+pub mod diff_algorithm;
+pub mod diff_stat_renderer;
 pub mod diff_stats;
 pub mod git_binary_diff;
+pub mod git_diff;
+pub mod hunk_heading;
+pub mod intra_line;
 pub mod lines;
+pub mod mbox;
+pub mod moved_code;
 pub mod patch;
 pub mod preamble;
+pub mod range_diff;
 pub mod text_diff;
 pub mod unified_diff;
 
 pub const TIMESTAMP_RE_STR: &str = r"\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}(\.\d{9})? [-+]{1}\d{4}";
 pub const ALT_TIMESTAMP_RE_STR: &str =
     r"[A-Z][a-z]{2} [A-Z][a-z]{2} \d{2} \d{2}:\d{2}:\d{2} \d{4} [-+]{1}\d{4}";
-pub const PATH_RE_STR: &str = r###""([^"]+)"|(\S+)"###;
+pub const PATH_RE_STR: &str = r###""((?:[^"\\]|\\.)+)"|(\S+)"###;
+// As PATH_RE_STR but for where an unquoted path is known to end at a tab
+// or the end of the line so it may contain spaces
+pub const SPACED_PATH_RE_STR: &str = r###""((?:[^"\\]|\\.)+)"|([^\t\n]*\S)"###;
+
+// Git quotes paths containing '"', '\\', control or non ASCII characters
+fn needs_escape(byte: u8) -> bool {
+    byte == b'"' || byte == b'\\' || !(0x20..0x7f).contains(&byte)
+}
+
+// Quote a path (that has already been parsed) in the manner of git i.e.
+// only if it contains characters that need C style escapes
+pub(crate) fn requoted_path(file_path: &str) -> String {
+    if !file_path.bytes().any(needs_escape) {
+        return file_path.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for byte in file_path.bytes() {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            _ if needs_escape(byte) => quoted.push_str(&format!("\\{byte:03o}")),
+            _ => quoted.push(byte as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Undo the escapes in the text between a quoted path's quotes
+pub(crate) fn unquoted_path(text: &str) -> String {
+    let mut bytes = vec![];
+    let mut iter = text.bytes().peekable();
+    while let Some(byte) = iter.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match iter.next() {
+            Some(b'a') => bytes.push(0x07),
+            Some(b'b') => bytes.push(0x08),
+            Some(b't') => bytes.push(b'\t'),
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b'v') => bytes.push(0x0b),
+            Some(b'f') => bytes.push(0x0c),
+            Some(b'r') => bytes.push(b'\r'),
+            Some(digit @ b'0'..=b'7') => {
+                let mut value = digit - b'0';
+                for _ in 0..2 {
+                    match iter.peek() {
+                        Some(&digit @ b'0'..=b'7') => {
+                            value = value.wrapping_mul(8) + (digit - b'0');
+                            iter.next();
+                        }
+                        _ => break,
+                    }
+                }
+                bytes.push(value);
+            }
+            Some(other) => bytes.push(other),
+            None => bytes.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum DiffFormat {
@@ -97,7 +97,8 @@ impl LinesIfce for &[Line] {
     }
 
     fn find_first_sub_lines(&self, sub_lines: &[Line], start_index: usize) -> Option<usize> {
-        (start_index..=start_index + self.len() - sub_lines.len()).find(|&index| self.contains_sub_lines_at(sub_lines, index))
+        let last_index = self.len().checked_sub(sub_lines.len())?;
+        (start_index..=last_index).find(|&index| self.contains_sub_lines_at(sub_lines, index))
     }
 }
 
@@ -115,7 +116,8 @@ impl LinesIfce for Lines {
     }
 
     fn find_first_sub_lines(&self, sub_lines: &[Line], start_index: usize) -> Option<usize> {
-        (start_index..=start_index + self.len() - sub_lines.len()).find(|&index| self.contains_sub_lines_at(sub_lines, index))
+        let last_index = self.len().checked_sub(sub_lines.len())?;
+        (start_index..=last_index).find(|&index| self.contains_sub_lines_at(sub_lines, index))
     }
 }
 
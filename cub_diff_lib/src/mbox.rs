@@ -0,0 +1,503 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Parse patches in the email format produced by "git format-patch" and
+//! split mbox files containing a series of such emails.
+
+use std::slice::Iter;
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::lines::{Line, Lines};
+use crate::patch::{Patch, PatchParser};
+use crate::text_diff::DiffParseResult;
+
+pub struct EmailPatch {
+    lines: Lines,
+    commit: Option<String>,
+    headers: Vec<(String, String)>,
+    subject: String,
+    number: Option<(usize, usize)>,
+    signature: (usize, usize),
+    patch: Patch,
+}
+
+impl EmailPatch {
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, Line> {
+        self.lines.iter()
+    }
+
+    // The commit id from the mbox "From <commit> <date>" separator line
+    pub fn commit(&self) -> Option<&str> {
+        self.commit.as_deref()
+    }
+
+    // NB: header names are case insensitive and folded values are unfolded
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.get_header("From")
+    }
+
+    pub fn date(&self) -> Option<&str> {
+        self.get_header("Date")
+    }
+
+    // The subject without its "[PATCH n/m]" style prefix
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    // The patch's position in its series and the series length
+    pub fn number(&self) -> Option<(usize, usize)> {
+        self.number
+    }
+
+    // The commit message's body i.e. the patch description up to the
+    // "---" separator
+    pub fn iter_message(&self) -> impl Iterator<Item = &Line> {
+        self.patch
+            .header()
+            .iter_description()
+            .take_while(|line| line.as_str() != "---\n")
+    }
+
+    // The "-- " line following the diffs and whatever comes after it
+    pub fn iter_signature(&self) -> Iter<'_, Line> {
+        self.lines[self.signature.0..self.signature.1].iter()
+    }
+
+    pub fn patch(&self) -> &Patch {
+        &self.patch
+    }
+
+    pub fn into_patch(self) -> Patch {
+        self.patch
+    }
+}
+
+pub struct EmailPatchParser {
+    from_cre: Regex,
+    header_cre: Regex,
+    subject_prefix_cre: Regex,
+    patch_parser: PatchParser,
+}
+
+impl Default for EmailPatchParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EmailPatchParser {
+    pub fn new() -> Self {
+        EmailPatchParser {
+            // e.g. "From <commit> Mon Sep 17 00:00:00 2001" as per format-patch
+            from_cre: Regex::new(
+                r"^From ([0-9a-fA-F]{40}|[0-9a-fA-F]{64}) ([A-Z][a-z]{2} [A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2} \d{4})(\n)?$",
+            )
+            .unwrap(),
+            header_cre: Regex::new(r"^([!-9;-~]+):\s*(.*?)\s*(\n)?$").unwrap(),
+            subject_prefix_cre: Regex::new(r"^\[([^\]]*?)((\d+)/(\d+))?\]\s*").unwrap(),
+            patch_parser: PatchParser::new(),
+        }
+    }
+
+    fn is_message_start(&self, lines: &[Line], index: usize) -> bool {
+        self.from_cre.is_match(&lines[index]) && (index == 0 || lines[index - 1].as_str() == "\n")
+    }
+
+    // Strip the "[PATCH n/m]" style prefix from the subject
+    fn split_subject(&self, subject: &str) -> (String, Option<(usize, usize)>) {
+        if let Some(captures) = self.subject_prefix_cre.captures(subject) {
+            // NB: treat unrepresentable numbers as absent
+            let number = match (captures.get(3), captures.get(4)) {
+                (Some(number), Some(count)) => usize::from_str(number.as_str())
+                    .ok()
+                    .zip(usize::from_str(count.as_str()).ok()),
+                _ => None,
+            };
+            (
+                subject[captures.get(0).unwrap().end()..].to_string(),
+                number,
+            )
+        } else {
+            (subject.to_string(), None)
+        }
+    }
+
+    // Parse an email (which ends at the next mbox "From " line) starting
+    // at the given index.  Emails whose bodies contain no diffs or don't
+    // parse as diffs (e.g. a series' cover letter) yield None.
+    pub fn get_email_patch_at(
+        &self,
+        lines: &[Line],
+        start_index: usize,
+    ) -> DiffParseResult<Option<EmailPatch>> {
+        let mut end_index = start_index + 1;
+        while end_index < lines.len() && !self.is_message_start(lines, end_index) {
+            end_index += 1;
+        }
+        let lines = &lines[start_index..end_index.min(lines.len())];
+        let mut index = 0;
+        let commit = match lines.first().and_then(|line| self.from_cre.captures(line)) {
+            Some(captures) => {
+                index += 1;
+                Some(captures.get(1).unwrap().as_str().to_string())
+            }
+            None => None,
+        };
+        let mut headers: Vec<(String, String)> = Vec::new();
+        while index < lines.len() && lines[index].as_str() != "\n" {
+            if let Some(captures) = self.header_cre.captures(&lines[index]) {
+                headers.push((
+                    captures.get(1).unwrap().as_str().to_string(),
+                    captures.get(2).unwrap().as_str().to_string(),
+                ));
+            } else if lines[index].starts_with([' ', '\t']) {
+                // a folded continuation of the previous header
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push(' ');
+                    value.push_str(lines[index].trim());
+                }
+            } else {
+                break;
+            }
+            index += 1;
+        }
+        if headers.is_empty() {
+            return Ok(None);
+        }
+        let body_start = (index + 1).min(lines.len());
+        let mut patch = match self.patch_parser.parse_lines(&lines[body_start..]) {
+            Ok(Some(patch)) => patch,
+            Ok(None) | Err(_) => return Ok(None),
+        };
+        // The signature must follow the diffs so check that excluding it
+        // leaves the diffs intact
+        let mut signature = (lines.len(), lines.len());
+        if let Some(sig_index) = lines.iter().rposition(|line| line.as_str() == "-- \n") {
+            if sig_index > body_start {
+                if let Ok(Some(sigless)) =
+                    self.patch_parser.parse_lines(&lines[body_start..sig_index])
+                {
+                    if sigless.diff_pluses().len() == patch.diff_pluses().len()
+                        && sigless
+                            .diff_pluses()
+                            .iter()
+                            .zip(patch.diff_pluses().iter())
+                            .all(|(a, b)| a.iter().eq(b.iter()))
+                    {
+                        signature = (sig_index, lines.len());
+                        patch = sigless;
+                    }
+                }
+            }
+        }
+        let (subject, number) = match headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Subject"))
+        {
+            Some((_, subject)) => self.split_subject(subject),
+            None => (String::new(), None),
+        };
+        Ok(Some(EmailPatch {
+            lines: lines.to_vec(),
+            commit,
+            headers,
+            subject,
+            number,
+            signature,
+            patch,
+        }))
+    }
+
+    // Split an mbox into its emails and parse those containing patches
+    pub fn parse_mbox(&self, lines: &[Line]) -> DiffParseResult<Vec<EmailPatch>> {
+        let mut email_patches = Vec::new();
+        let mut index = 0;
+        while index < lines.len() {
+            if self.is_message_start(lines, index) {
+                if let Some(email_patch) = self.get_email_patch_at(lines, index)? {
+                    index += email_patch.len();
+                    email_patches.push(email_patch);
+                    continue;
+                }
+            }
+            index += 1;
+        }
+        Ok(email_patches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::LinesIfce;
+
+    #[test]
+    fn parse_mbox_works() {
+        let mbox = "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001
+From: A U Thor <author@example.com>
+Date: Sat, 1 Jun 2019 10:00:00 +1000
+Subject: [PATCH 0/2] A series
+
+Some words about the series.
+
+-- 
+2.39.0
+
+From 1111111111111111111111111111111111111111 Mon Sep 17 00:00:00 2001
+From: A U Thor <author@example.com>
+Date: Sat, 1 Jun 2019 10:00:00 +1000
+Subject: [PATCH 1/2] Change a file
+
+Why the file needed changing.
+
+Signed-off-by: A U Thor <author@example.com>
+---
+ file | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/file b/file
+index 1111111..2222222 100644
+--- a/file
++++ b/file
+@@ -1,3 +1,3 @@
+ one
+-two
++2
+ three
+-- 
+2.39.0
+
+From 2222222222222222222222222222222222222222 Mon Sep 17 00:00:00 2001
+From: A U Thor <author@example.com>
+Date: Sat, 1 Jun 2019 11:00:00 +1000
+Subject: [PATCH v2 2/2] Remove an item from a list with a subject
+ that needs folding
+
+---
+diff --git a/list b/list
+index 3333333..4444444 100644
+--- a/list
++++ b/list
+@@ -1,3 +1,2 @@
+ - one
+-- 
+ - three
+";
+        let parser = EmailPatchParser::new();
+        let email_patches = parser.parse_mbox(&Lines::from_string(mbox)).unwrap();
+        assert_eq!(email_patches.len(), 2);
+
+        let first = &email_patches[0];
+        assert_eq!(
+            first.commit(),
+            Some("1111111111111111111111111111111111111111")
+        );
+        assert_eq!(first.author(), Some("A U Thor <author@example.com>"));
+        assert_eq!(first.date(), Some("Sat, 1 Jun 2019 10:00:00 +1000"));
+        assert_eq!(first.subject(), "Change a file");
+        assert_eq!(first.number(), Some((1, 2)));
+        let message: String = first.iter_message().map(|line| line.as_str()).collect();
+        assert_eq!(
+            message,
+            "Why the file needed changing.\n\nSigned-off-by: A U Thor <author@example.com>\n"
+        );
+        let signature: String = first.iter_signature().map(|line| line.as_str()).collect();
+        assert_eq!(signature, "-- \n2.39.0\n\n");
+        assert_eq!(first.patch().diff_pluses().len(), 1);
+        assert_eq!(first.patch().diff_stat_mismatches(1), Some(vec![]));
+
+        let second = &email_patches[1];
+        assert_eq!(
+            second.subject(),
+            "Remove an item from a list with a subject that needs folding"
+        );
+        assert_eq!(second.number(), Some((2, 2)));
+        assert_eq!(second.iter_message().count(), 0);
+        // the "-- " line belongs to the diff so isn't a signature
+        assert_eq!(second.iter_signature().count(), 0);
+        assert_eq!(second.patch().diff_pluses()[0].len(), 8);
+
+        let patch = parser
+            .get_email_patch_at(&Lines::from_string(mbox), 0)
+            .unwrap();
+        assert!(patch.is_none());
+    }
+
+    #[test]
+    fn cover_letters_are_not_patches() {
+        // from "git format-patch --stdout -2 --cover-letter"
+        let mbox = "From 0ba6b04bc33eea9f41eb5b54c0cd0cd366fbcf95 Mon Sep 17 00:00:00 2001
+From: A U Thor <author@example.com>
+Date: Sun, 18 Oct 2026 23:15:52 +0000
+Subject: [PATCH 0/2] *** SUBJECT HERE ***
+
+*** BLURB HERE ***
+
+A U Thor (2):
+  Change a file
+  Change it again
+
+ file | 4 ++--
+ 1 file changed, 2 insertions(+), 2 deletions(-)
+
+-- 
+2.39.5
+
+From 6bcbbdde2f2d7b6f7b8d8d36f578a2593beccb1e Mon Sep 17 00:00:00 2001
+From: A U Thor <author@example.com>
+Date: Sat, 1 Jun 2019 10:00:00 +1000
+Subject: [PATCH 1/2] Change a file
+
+---
+ file | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/file b/file
+index 4cb29ea..f04eb26 100644
+--- a/file
++++ b/file
+@@ -1,3 +1,3 @@
+ one
+-two
++2
+ three
+-- 
+2.39.5
+
+
+From 0ba6b04bc33eea9f41eb5b54c0cd0cd366fbcf95 Mon Sep 17 00:00:00 2001
+From: A U Thor <author@example.com>
+Date: Sat, 1 Jun 2019 11:00:00 +1000
+Subject: [PATCH 2/2] Change it again
+
+---
+ file | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/file b/file
+index f04eb26..26dde9c 100644
+--- a/file
++++ b/file
+@@ -1,3 +1,3 @@
+ one
+ 2
+-three
++3
+-- 
+2.39.5
+
+";
+        let email_patches = EmailPatchParser::new()
+            .parse_mbox(&Lines::from_string(mbox))
+            .unwrap();
+        assert_eq!(email_patches.len(), 2);
+        assert_eq!(email_patches[0].subject(), "Change a file");
+        assert_eq!(email_patches[0].number(), Some((1, 2)));
+        assert_eq!(email_patches[1].subject(), "Change it again");
+        assert_eq!(email_patches[1].number(), Some((2, 2)));
+        for email_patch in email_patches.iter() {
+            assert_eq!(email_patch.patch().diff_pluses().len(), 1);
+            assert_eq!(email_patch.patch().diff_stat_mismatches(1), Some(vec![]));
+        }
+        // a blurb that doesn't parse as a diff doesn't spoil the series
+        let edited = mbox.replace("*** BLURB HERE ***", "--- BLURB HERE ---");
+        let email_patches = EmailPatchParser::new()
+            .parse_mbox(&Lines::from_string(&edited))
+            .unwrap();
+        assert_eq!(email_patches.len(), 2);
+    }
+
+    #[test]
+    fn quoted_paths_match_their_diffstat() {
+        // from "git format-patch --stdout -1"
+        let mbox = r#"From 5c64684c277e0834a61d4ab65d9668066a68edd3 Mon Sep 17 00:00:00 2001
+From: A U Thor <author@example.com>
+Date: Sat, 1 Jun 2019 10:00:00 +1000
+Subject: [PATCH] Change and rename files
+
+---
+ "caf\303\251"         | 2 +-
+ old => "na\303\257ve" | 0
+ 2 files changed, 1 insertion(+), 1 deletion(-)
+ rename old => "na\303\257ve" (100%)
+
+diff --git "a/caf\303\251" "b/caf\303\251"
+index 587be6b..975fbec 100644
+--- "a/caf\303\251"
++++ "b/caf\303\251"
+@@ -1 +1 @@
+-x
++y
+diff --git a/old "b/na\303\257ve"
+similarity index 100%
+rename from old
+rename to "na\303\257ve"
+-- 
+2.39.5
+
+"#;
+        let email_patches = EmailPatchParser::new()
+            .parse_mbox(&Lines::from_string(mbox))
+            .unwrap();
+        assert_eq!(email_patches.len(), 1);
+        let patch = email_patches[0].patch();
+        assert_eq!(patch.diff_pluses().len(), 2);
+        assert_eq!(patch.diff_stat_mismatches(1), Some(vec![]));
+    }
+
+    #[test]
+    fn body_from_lines_are_not_separators() {
+        let mbox = "From 1111111111111111111111111111111111111111 Mon Sep 17 00:00:00 2001
+From: A U Thor <author@example.com>
+Date: Sat, 1 Jun 2019 10:00:00 +1000
+Subject: [PATCH 99999999999999999999999/2] Change a file
+
+Why the file needed changing.
+
+From now on the file says 2.
+---
+diff --git a/file b/file
+index 1111111..2222222 100644
+--- a/file
++++ b/file
+@@ -1,3 +1,3 @@
+ one
+-two
++2
+ three
+";
+        let email_patches = EmailPatchParser::new()
+            .parse_mbox(&Lines::from_string(mbox))
+            .unwrap();
+        assert_eq!(email_patches.len(), 1);
+        let email_patch = &email_patches[0];
+        assert_eq!(email_patch.subject(), "Change a file");
+        assert_eq!(email_patch.number(), None);
+        let message: String = email_patch
+            .iter_message()
+            .map(|line| line.as_str())
+            .collect();
+        assert_eq!(
+            message,
+            "Why the file needed changing.\n\nFrom now on the file says 2.\n"
+        );
+        assert_eq!(email_patch.patch().diff_pluses().len(), 1);
+    }
+}
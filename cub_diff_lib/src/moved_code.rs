@@ -0,0 +1,240 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Find blocks of lines that a patch deletes in one place and adds
+//! (unchanged) in another, possibly in a different file, so that they
+//! can be shown differently (like git's "--color-moved").
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::diff::Diff;
+use crate::lines::Line;
+use crate::patch::Patch;
+
+// Indices of a line's diff (plus) within the patch, hunk within the diff
+// and line within the hunk's lines
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineLocation {
+    pub diff_index: usize,
+    pub hunk_index: usize,
+    pub line_index: usize,
+}
+
+// A block of deleted lines and the lines (with the same text) that
+// replace them elsewhere
+#[derive(Debug, Clone, PartialEq)]
+pub struct MovedBlock {
+    pub deleted: Vec<LineLocation>,
+    pub added: Vec<LineLocation>,
+}
+
+impl MovedBlock {
+    pub fn len(&self) -> usize {
+        self.added.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+    }
+}
+
+// A deleted or added line's location and text (less its prefix) and the
+// run of consecutive deleted or added lines that it belongs to
+struct ChangedLine<'a> {
+    location: LineLocation,
+    text: &'a str,
+    run: usize,
+}
+
+// A hunk's lines, their (unified diff) prefixes and indices in order and
+// the length of the prefixes
+type HunkOps<'a> = (&'a [Line], Vec<(char, usize)>, usize);
+
+// The patch's deleted and added lines in order
+fn changed_lines(patch: &Patch) -> (Vec<ChangedLine<'_>>, Vec<ChangedLine<'_>>) {
+    let mut deleted = vec![];
+    let mut added = vec![];
+    let mut run = 0;
+    for (diff_index, diff_plus) in patch.diff_pluses().iter().enumerate() {
+        let hunks: Vec<HunkOps> = match diff_plus.diff() {
+            Diff::Unified(diff) => diff
+                .hunks()
+                .iter()
+                .map(|hunk| {
+                    let ops = hunk
+                        .lines
+                        .iter()
+                        .enumerate()
+                        .skip(1)
+                        .filter(|(_, line)| !line.starts_with('\\'))
+                        .map(|(index, line)| (line.chars().next().unwrap_or(' '), index))
+                        .collect();
+                    (&hunk.lines[..], ops, 1)
+                })
+                .collect(),
+            Diff::Context(diff) => diff
+                .hunks()
+                .iter()
+                .map(|hunk| (&hunk.lines[..], hunk.indexed_ops(), 2))
+                .collect(),
+            _ => vec![],
+        };
+        for (hunk_index, (lines, ops, prefix_len)) in hunks.into_iter().enumerate() {
+            let mut previous = ' ';
+            for (prefix, line_index) in ops {
+                if prefix != previous {
+                    run += 1;
+                    previous = prefix;
+                }
+                let line = &lines[line_index];
+                let changed_line = ChangedLine {
+                    location: LineLocation {
+                        diff_index,
+                        hunk_index,
+                        line_index,
+                    },
+                    text: line[prefix_len.min(line.len())..].trim_end_matches('\n'),
+                    run,
+                };
+                match prefix {
+                    '-' => deleted.push(changed_line),
+                    '+' => added.push(changed_line),
+                    _ => (),
+                }
+            }
+        }
+    }
+    (deleted, added)
+}
+
+pub struct MovedCodeDetector {
+    // blocks with fewer lines than this are ignored
+    pub min_lines: usize,
+    // as are those with fewer alphanumeric characters than this (as git
+    // does) so that blank lines and lone braces aren't reported
+    pub min_alnum_chars: usize,
+}
+
+impl Default for MovedCodeDetector {
+    fn default() -> Self {
+        MovedCodeDetector {
+            min_lines: 1,
+            min_alnum_chars: 20,
+        }
+    }
+}
+
+impl MovedCodeDetector {
+    // The moved blocks (in order of where they are added) pairing each
+    // run of added lines with the longest matching (unpaired) run of
+    // deleted lines in the patch.
+    pub fn detect(&self, patch: &Patch) -> Vec<MovedBlock> {
+        let (deleted, added) = changed_lines(patch);
+        let mut candidates: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (index, line) in deleted.iter().enumerate() {
+            candidates.entry(line.text).or_default().push(index);
+        }
+        let mut used = vec![false; deleted.len()];
+        let mut blocks = vec![];
+        let mut i = 0;
+        while i < added.len() {
+            let match_len = |j: usize| {
+                (0..)
+                    .take_while(|k| {
+                        i + k < added.len()
+                            && j + k < deleted.len()
+                            && added[i + k].run == added[i].run
+                            && deleted[j + k].run == deleted[j].run
+                            && !used[j + k]
+                            && added[i + k].text == deleted[j + k].text
+                    })
+                    .count()
+            };
+            let best = candidates.get(added[i].text).and_then(|indices| {
+                indices
+                    .iter()
+                    .map(|&j| (match_len(j), j))
+                    .filter(|(len, _)| *len > 0)
+                    .max_by_key(|&(len, j)| (len, Reverse(j)))
+            });
+            if let Some((len, j)) = best {
+                let alnum_chars: usize = added[i..i + len]
+                    .iter()
+                    .map(|line| line.text.chars().filter(|c| c.is_alphanumeric()).count())
+                    .sum();
+                if len >= self.min_lines && alnum_chars >= self.min_alnum_chars {
+                    used[j..j + len].iter_mut().for_each(|used| *used = true);
+                    blocks.push(MovedBlock {
+                        deleted: deleted[j..j + len].iter().map(|l| l.location).collect(),
+                        added: added[i..i + len].iter().map(|l| l.location).collect(),
+                    });
+                    i += len;
+                    continue;
+                }
+            }
+            i += 1;
+        }
+        blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::{Lines, LinesIfce};
+    use crate::patch::PatchParser;
+
+    #[test]
+    fn detect_moved_code_works() {
+        let text = "--- a/a
++++ b/a
+@@ -1,6 +1,2 @@
+ keep
+-fn moved_function() {
+-    do_something_useful();
+-}
+-x
+ end
+--- a/b
++++ b/b
+@@ -1,2 +1,7 @@
+ start
++fn moved_function() {
++    do_something_useful();
++}
++y
++x
+ finish
+";
+        let patch = PatchParser::new()
+            .parse_lines(&Lines::from_string(text))
+            .unwrap()
+            .unwrap();
+        let location = |diff_index, line_index| LineLocation {
+            diff_index,
+            hunk_index: 0,
+            line_index,
+        };
+        let blocks = MovedCodeDetector::default().detect(&patch);
+        assert_eq!(
+            blocks,
+            vec![MovedBlock {
+                deleted: (2..5).map(|index| location(0, index)).collect(),
+                added: (2..5).map(|index| location(1, index)).collect(),
+            }]
+        );
+        let detector = MovedCodeDetector {
+            min_lines: 1,
+            min_alnum_chars: 1,
+        };
+        let blocks = detector.detect(&patch);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].deleted, vec![location(0, 5)]);
+        assert_eq!(blocks[1].added, vec![location(1, 6)]);
+        let detector = MovedCodeDetector {
+            min_lines: 4,
+            ..MovedCodeDetector::default()
+        };
+        assert!(detector.detect(&patch).is_empty());
+    }
+}
@@ -1,18 +1,22 @@
 //Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
 
+use std::fmt;
+use std::io;
 use std::slice::Iter;
 
-use crate::diff::{DiffPlus, DiffPlusParser};
-use crate::diff_stats::DiffStatParser;
+use crate::diff::{self, DiffPlus, DiffPlusParser};
+use crate::diff_stats::{DiffStatMismatch, DiffStatParser, DiffStats, DiffStatsLines};
 use crate::lines::*;
-use crate::text_diff::DiffParseResult;
+use crate::text_diff::{DiffParseError, DiffParseResult, HunkSelection};
 use crate::MultiListIter;
 
+#[derive(Clone)]
 pub struct PatchHeader {
     lines: Lines,
     comment: (usize, usize),
     description: (usize, usize),
     diff_stats_lines: (usize, usize),
+    diff_stats: Option<DiffStatsLines>,
 }
 
 impl PatchHeader {
@@ -40,6 +44,7 @@ impl PatchHeader {
                 comment: (0, descr_starts_at),
                 description: (descr_starts_at, diff_stats_range.0),
                 diff_stats_lines: (diff_stats_range.0, diff_stats_range.1),
+                diff_stats: parser.get_diff_stats_lines_at(lines, diff_stats_range.0),
             }
         } else {
             PatchHeader {
@@ -47,10 +52,19 @@ impl PatchHeader {
                 comment: (0, descr_starts_at),
                 description: (descr_starts_at, lines.len()),
                 diff_stats_lines: (0, 0),
+                diff_stats: None,
             }
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
     pub fn iter(&self) -> Iter<Line> {
         self.lines.iter()
     }
@@ -66,10 +80,13 @@ impl PatchHeader {
     pub fn iter_diff_stats_lnes(&self) -> Iter<Line> {
         self.lines[self.diff_stats_lines.0..self.diff_stats_lines.1].iter()
     }
+
+    pub fn diff_stats(&self) -> Option<&DiffStatsLines> {
+        self.diff_stats.as_ref()
+    }
 }
 
 pub struct Patch {
-    length: usize,
     header: PatchHeader,
     diff_pluses: Vec<DiffPlus>,
     rubbish: Vec<Lines>, // some tools put rubbish between diffs
@@ -77,11 +94,31 @@ pub struct Patch {
 
 impl Patch {
     pub fn len(&self) -> usize {
-        self.length
+        self.header.len()
+            + self.diff_pluses.iter().map(|d| d.len()).sum::<usize>()
+            + self.rubbish.iter().map(|r| r.len()).sum::<usize>()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.length == 0
+        self.len() == 0
+    }
+
+    pub fn header(&self) -> &PatchHeader {
+        &self.header
+    }
+
+    pub fn diff_pluses(&self) -> &Vec<DiffPlus> {
+        &self.diff_pluses
+    }
+
+    // NB: use the TextDiff methods to edit hunks so that headers are recounted
+    pub fn diff_plus_mut(&mut self, index: usize) -> &mut DiffPlus {
+        &mut self.diff_pluses[index]
+    }
+
+    // The lines (if any) following the diff at "index" and preceding the next one
+    pub fn rubbish_after(&self, index: usize) -> &Lines {
+        &self.rubbish[index]
     }
 
     pub fn iter(&self) -> MultiListIter<Line> {
@@ -96,6 +133,366 @@ impl Patch {
     pub fn num_files(&self) -> usize {
         self.diff_pluses.len()
     }
+
+    pub fn insert_diff_plus(&mut self, index: usize, diff_plus: DiffPlus) {
+        self.diff_pluses.insert(index, diff_plus);
+        self.rubbish.insert(index, vec![]);
+    }
+
+    pub fn push_diff_plus(&mut self, diff_plus: DiffPlus) {
+        // NB: keep any trailing rubbish (e.g. a signature) at the end
+        self.diff_pluses.push(diff_plus);
+        let index = self.rubbish.len().saturating_sub(1);
+        self.rubbish.insert(index, vec![]);
+    }
+
+    // Remove the diff (and the rubbish following it unless it's the
+    // last diff in which case the rubbish is kept at the end)
+    pub fn remove_diff_plus(&mut self, index: usize) -> DiffPlus {
+        let mut rubbish = self.rubbish.remove(index);
+        if index == self.diff_pluses.len() - 1 {
+            if index > 0 {
+                self.rubbish[index - 1].append(&mut rubbish);
+            } else if !rubbish.is_empty() {
+                // no diffs remain for the rubbish to follow so add it to the header
+                let mut lines = self.header.lines.clone();
+                lines.append(&mut rubbish);
+                self.header = PatchHeader::new(&lines);
+            }
+        }
+        self.diff_pluses.remove(index)
+    }
+
+    // Remove the diff for the file (if any) with the given path
+    pub fn remove_file(&mut self, file_path: &str, strip_level: usize) -> Option<DiffPlus> {
+        let index = self
+            .diff_pluses
+            .iter()
+            .position(|d| d.get_file_path(strip_level) == file_path)?;
+        Some(self.remove_diff_plus(index))
+    }
+
+    // A patch containing only the selected changes to the selected files
+    // (by index) or None if nothing is selected
+    pub fn selected(&self, selections: &[(usize, HunkSelection)]) -> Option<Patch> {
+        let mut diff_pluses = vec![];
+        let mut rubbish = vec![];
+        for (index, selection) in selections.iter() {
+            let diff_plus = self.diff_pluses.get(*index);
+            if let Some(diff_plus) = diff_plus.and_then(|d| d.selected(selection)) {
+                diff_pluses.push(diff_plus);
+                rubbish.push(self.rubbish[*index].clone());
+            }
+        }
+        if diff_pluses.is_empty() {
+            None
+        } else {
+            Some(Patch {
+                header: self.header.clone(),
+                diff_pluses,
+                rubbish,
+            })
+        }
+    }
+
+    // The patch that undoes this patch's changes (keeping the header and
+    // any rubbish between the diffs as is)
+    pub fn reversed(&self) -> Patch {
+        Patch {
+            header: self.header.clone(),
+            diff_pluses: self.diff_pluses.iter().map(|d| d.reversed()).collect(),
+            rubbish: self.rubbish.clone(),
+        }
+    }
+
+    // The index of the (not yet used) diff plus in this patch (which was
+    // made on top of the one containing "diff_plus") for the same file
+    fn later_diff_plus_index(
+        &self,
+        diff_plus: &DiffPlus,
+        used: &[bool],
+        strip_level: usize,
+    ) -> Option<usize> {
+        // each file is found under the path the earlier patch leaves it at
+        let source_file_path = |diff_plus: &DiffPlus| {
+            if diff_plus.get_ante_file_path(0) == "/dev/null" {
+                diff_plus.get_post_file_path(strip_level)
+            } else {
+                diff_plus.get_ante_file_path(strip_level)
+            }
+        };
+        let file_path = diff_plus.get_file_path(strip_level);
+        self.diff_pluses
+            .iter()
+            .enumerate()
+            .position(|(i, d)| !used[i] && source_file_path(d) == file_path)
+    }
+
+    // The patch equivalent to applying this patch and then "later" (a
+    // patch made on top of it) e.g. for squashing a series of fixes into
+    // one patch.  The diffs for each file are squashed (see
+    // DiffPlus::squashed()) and those for files that only one of the
+    // patches touches are kept as is.  NB: this patch's header is kept.
+    pub fn squashed(
+        &self,
+        later: &Patch,
+        strip_level: usize,
+        context_len: usize,
+    ) -> DiffParseResult<Patch> {
+        self.squashed_by(later, strip_level, |diff_plus, later_diff_plus| {
+            diff_plus.squashed(later_diff_plus, context_len)
+        })
+    }
+
+    // As for squashed() but for diffs that don't fit together exactly
+    // "contents" is asked for the contents of the file (given its path
+    // stripped by "strip_level") that this patch's diff applies to and,
+    // if they're available, both diffs are applied to them and the
+    // result rediffed (see DiffPlus::squashed_using()).
+    pub fn squashed_using<F>(
+        &self,
+        later: &Patch,
+        strip_level: usize,
+        context_len: usize,
+        contents: F,
+    ) -> DiffParseResult<Patch>
+    where
+        F: Fn(&str) -> Option<Lines>,
+    {
+        self.squashed_by(
+            later,
+            strip_level,
+            |diff_plus, later_diff_plus| match diff_plus.squashed(later_diff_plus, context_len) {
+                Err(DiffParseError::DiffConflict(index)) => {
+                    match contents(&diff_plus.get_ante_file_path(strip_level)) {
+                        Some(lines) => {
+                            diff_plus.squashed_using(later_diff_plus, &lines, context_len)
+                        }
+                        None => Err(DiffParseError::DiffConflict(index)),
+                    }
+                }
+                result => result,
+            },
+        )
+    }
+
+    fn squashed_by<F>(&self, later: &Patch, strip_level: usize, squash: F) -> DiffParseResult<Patch>
+    where
+        F: Fn(&DiffPlus, &DiffPlus) -> DiffParseResult<Option<DiffPlus>>,
+    {
+        let mut used = vec![false; later.diff_pluses.len()];
+        let mut patch = Patch {
+            header: self.header.clone(),
+            diff_pluses: vec![],
+            rubbish: vec![],
+        };
+        for diff_plus in self.diff_pluses.iter() {
+            if let Some(index) = later.later_diff_plus_index(diff_plus, &used, strip_level) {
+                used[index] = true;
+                let later_diff_plus = &later.diff_pluses[index];
+                if let Some(squashed) = squash(diff_plus, later_diff_plus)? {
+                    patch.push_diff_plus(squashed);
+                }
+            } else {
+                patch.push_diff_plus(diff_plus.clone());
+            }
+        }
+        for (diff_plus, _) in later.diff_pluses.iter().zip(used).filter(|(_, u)| !u) {
+            patch.push_diff_plus(diff_plus.clone());
+        }
+        Ok(patch)
+    }
+
+    // The patches (later', self') that make the same changes as applying
+    // this patch and then "later" (a patch made on top of it) but in the
+    // opposite order (e.g. for reordering a patch stack) or None if they
+    // don't commute.  The diffs of files that both patches touch are
+    // commuted (see DiffPlus::commuted()) and the rest kept as they are.
+    // NB: the headers are kept.
+    pub fn commuted(
+        &self,
+        later: &Patch,
+        strip_level: usize,
+        context_len: usize,
+    ) -> DiffParseResult<Option<(Patch, Patch)>> {
+        let mut used = vec![false; later.diff_pluses.len()];
+        let mut later_diff_pluses = later.diff_pluses.clone();
+        let mut patch = Patch {
+            header: self.header.clone(),
+            diff_pluses: vec![],
+            rubbish: vec![],
+        };
+        for diff_plus in self.diff_pluses.iter() {
+            if let Some(index) = later.later_diff_plus_index(diff_plus, &used, strip_level) {
+                used[index] = true;
+                match diff_plus.commuted(&later.diff_pluses[index], context_len)? {
+                    Some((later_diff_plus, diff_plus)) => {
+                        later_diff_pluses[index] = later_diff_plus;
+                        patch.push_diff_plus(diff_plus);
+                    }
+                    None => return Ok(None),
+                }
+            } else {
+                patch.push_diff_plus(diff_plus.clone());
+            }
+        }
+        let mut later_patch = Patch {
+            header: later.header.clone(),
+            diff_pluses: vec![],
+            rubbish: vec![],
+        };
+        for diff_plus in later_diff_pluses {
+            later_patch.push_diff_plus(diff_plus);
+        }
+        Ok(Some((later_patch, patch)))
+    }
+
+    // The path and number that line "line_num" of the file "file_path" has
+    // after the patch is applied (or before it if "reverse") e.g. for
+    // carrying review comments forward.  None is returned if the patch
+    // deletes the line (or the file).
+    pub fn mapped_line(
+        &self,
+        file_path: &str,
+        line_num: usize,
+        strip_level: usize,
+        reverse: bool,
+    ) -> Option<(String, usize)> {
+        // line numbers start at 1
+        if line_num == 0 {
+            return None;
+        }
+        for diff_plus in self.diff_pluses.iter() {
+            let mut file_paths = [
+                diff_plus.get_ante_file_path(strip_level),
+                diff_plus.get_post_file_path(strip_level),
+            ];
+            let mut raw_file_paths = [
+                diff_plus.get_ante_file_path(0),
+                diff_plus.get_post_file_path(0),
+            ];
+            if reverse {
+                file_paths.reverse();
+                raw_file_paths.reverse();
+            }
+            if raw_file_paths[0] != "/dev/null" && file_paths[0] == file_path {
+                if raw_file_paths[1] == "/dev/null" {
+                    return None;
+                }
+                let line_num = diff_plus.diff().mapped_line_num(line_num, reverse)?;
+                return Some((file_paths[1].clone(), line_num));
+            }
+        }
+        Some((file_path.to_string(), line_num))
+    }
+
+    // The statistics for each file (by path) in the order they appear
+    pub fn file_diff_stats(&self, strip_level: usize) -> Vec<(String, DiffStats)> {
+        self.diff_pluses
+            .iter()
+            .map(|diff_plus| (diff_plus.get_file_path(strip_level), diff_plus.diff_stats()))
+            .collect()
+    }
+
+    pub fn diff_stats(&self) -> DiffStats {
+        let mut stats = DiffStats::default();
+        for diff_plus in self.diff_pluses.iter() {
+            stats += diff_plus.diff_stats();
+        }
+        stats
+    }
+
+    // The discrepancies (if any) between the header's diffstat summary and
+    // the patch's diffs or None if the header has no diffstat summary
+    pub fn diff_stat_mismatches(&self, strip_level: usize) -> Option<Vec<DiffStatMismatch>> {
+        let diff_stats = self.header.diff_stats()?;
+        let mut unlisted: Vec<&DiffPlus> = self.diff_pluses.iter().collect();
+        let mut mismatches = vec![];
+        for entry in diff_stats.entries() {
+            let found = unlisted.iter().position(|diff_plus| {
+                entry.matches_file_path(&diff_plus.get_file_path(strip_level))
+            });
+            if let Some(index) = found {
+                let actual = unlisted.remove(index).diff_stat_change();
+                if !entry.change().agrees_with(&actual) {
+                    mismatches.push(DiffStatMismatch::Changed(entry.clone(), actual));
+                }
+            } else {
+                mismatches.push(DiffStatMismatch::Unmatched(entry.clone()));
+            }
+        }
+        for diff_plus in unlisted {
+            mismatches.push(DiffStatMismatch::Unlisted(
+                diff_plus.get_file_path(strip_level),
+            ));
+        }
+        Some(mismatches)
+    }
+
+    // The patch's id as per "git patch-id --stable" (see diff::patch_id())
+    pub fn patch_id(&self) -> Vec<u8> {
+        diff::patch_id(&self.diff_pluses)
+    }
+
+    pub fn write_to<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        for line in self.iter() {
+            writer.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.iter() {
+            write!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+// The patch that takes the result of applying "old" to the result of
+// applying "new" where they are two versions of a patch to the same
+// files (e.g. v1 and v2 of a submission) with "context_len" lines of
+// context.  Files changed by only one of the versions are included
+// (those only in "old" with their changes undone).  It's worked out
+// from the diffs alone (as "old" reversed squashed with "new") so the
+// versions must have been made against the same versions of the files
+// or an error will be returned if their hunks disagree.  NB: the header
+// is new's.
+pub fn interdiff(
+    old: &Patch,
+    new: &Patch,
+    strip_level: usize,
+    context_len: usize,
+) -> DiffParseResult<Patch> {
+    let mut patch = old.reversed().squashed(new, strip_level, context_len)?;
+    patch.header = new.header.clone();
+    Ok(patch)
+}
+
+// The path and number that line "line_num" of the file "file_path" has
+// after the series of patches is applied (or before it if "reverse" in
+// which case "line_num" is a line of the file after the series) or None
+// if the line gets deleted on the way (see Patch::mapped_line()).
+pub fn mapped_line_through(
+    patches: &[Patch],
+    file_path: &str,
+    line_num: usize,
+    strip_level: usize,
+    reverse: bool,
+) -> Option<(String, usize)> {
+    let mut mapped = (file_path.to_string(), line_num);
+    if reverse {
+        for patch in patches.iter().rev() {
+            mapped = patch.mapped_line(&mapped.0, mapped.1, strip_level, true)?;
+        }
+    } else {
+        for patch in patches.iter() {
+            mapped = patch.mapped_line(&mapped.0, mapped.1, strip_level, false)?;
+        }
+    }
+    Some(mapped)
 }
 
 #[derive(Default)]
@@ -134,7 +531,6 @@ impl PatchParser {
         if let Some(end_of_header) = diffs_start_at {
             let header = PatchHeader::new(&lines[0..end_of_header]);
             Ok(Some(Patch {
-                length: lines.len(),
                 header,
                 diff_pluses,
                 rubbish,
@@ -148,6 +544,9 @@ impl PatchParser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::diff::Diff;
+    use crate::diff_stats::{DiffStatChange, DiffStatsCategory};
+    use crate::unified_diff::UnifiedDiffGenerator;
     use std::path::Path;
 
     #[test]
@@ -187,4 +586,683 @@ mod tests {
         assert!(patch.iter().count() == patch.len());
         assert!(patch.num_files() == 6);
     }
+
+    #[test]
+    fn patch_edit_and_write() {
+        let lines = Lines::read_from(Path::new("../test_diffs/test_1.diff")).unwrap();
+        let parser = PatchParser::new();
+        let mut patch = parser.parse_lines(&lines).unwrap().unwrap();
+        if let Diff::Unified(diff) = patch.diff_plus_mut(1).diff_mut() {
+            let hunk = diff.remove_hunk(1);
+            assert_eq!(diff.hunks().len(), 6);
+            assert_eq!(
+                *diff.hunks()[1].lines[0],
+                "@@ -39,23 +40,29 @@ pub struct TextDiffHeader {\n"
+            );
+            assert!(diff.validate().is_empty());
+            assert_eq!(diff.insert_hunk(hunk), 1);
+            assert_eq!(
+                *diff.hunks()[2].lines[0],
+                "@@ -39,23 +44,29 @@ pub struct TextDiffHeader {\n"
+            );
+        } else {
+            panic!("expected a unified diff");
+        }
+        assert!(patch.iter().eq(lines.iter()));
+
+        let removed = patch.remove_file("src/lib.rs", 1).unwrap();
+        assert_eq!(patch.num_files(), 1);
+        assert_eq!(patch.len(), lines.len() - removed.len());
+        let mut text = vec![];
+        patch.write_to(&mut text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), patch.to_string());
+
+        patch.insert_diff_plus(0, removed);
+        let reparsed = parser
+            .parse_lines(&Lines::from_string(&patch.to_string()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(reparsed.num_files(), 2);
+        assert!(reparsed.iter().eq(lines.iter()));
+    }
+
+    #[test]
+    fn description_lines_arent_file_headers() {
+        let text = "A description
+*** NOTE THIS ***
+
+diff --git a/file b/file
+--- a/file
++++ b/file
+@@ -1 +1 @@
+-one
++1
+";
+        let parser = PatchParser::new();
+        let patch = parser
+            .parse_lines(&Lines::from_string(text))
+            .unwrap()
+            .unwrap();
+        assert_eq!(patch.num_files(), 1);
+        assert_eq!(patch.diff_pluses()[0].get_file_path(1), "file");
+    }
+
+    #[test]
+    fn remove_only_diff_keeps_rubbish() {
+        let text = "A description
+diff --git a/file b/file
+--- a/file
++++ b/file
+@@ -1 +1 @@
+-one
++1
+-- 
+2.39.0
+";
+        let parser = PatchParser::new();
+        let mut patch = parser
+            .parse_lines(&Lines::from_string(text))
+            .unwrap()
+            .unwrap();
+        let removed = patch.remove_diff_plus(0);
+        assert_eq!(patch.num_files(), 0);
+        assert_eq!(patch.to_string(), "A description\n-- \n2.39.0\n");
+        assert_eq!(patch.len() + removed.len(), Lines::from_string(text).len());
+    }
+
+    #[test]
+    fn patch_reversed() {
+        let parser = PatchParser::new();
+        for file_name in &[
+            "../test_diffs/test_1.diff",
+            "../test_diffs/test_2.binary_diff",
+        ] {
+            let lines = Lines::read_from(Path::new(file_name)).unwrap();
+            let patch = parser.parse_lines(&lines).unwrap().unwrap();
+            let reversed = patch.reversed();
+            assert_eq!(reversed.num_files(), patch.num_files());
+            assert!(!reversed.iter().eq(lines.iter()));
+            assert!(reversed.reversed().iter().eq(lines.iter()));
+        }
+    }
+
+    #[test]
+    fn patch_squash() {
+        let first = "diff --git a/file b/file
+index 1111111..2222222 100644
+--- a/file
++++ b/file
+@@ -1,3 +1,3 @@
+ one
+-two
++2
+ three
+diff --git a/gone b/gone
+new file mode 100644
+index 0000000..5555555
+--- /dev/null
++++ b/gone
+@@ -0,0 +1 @@
++gone
+";
+        let second = "diff --git a/file b/file
+index 2222222..3333333 100644
+--- a/file
++++ b/file
+@@ -1,3 +1,3 @@
+ one
+ 2
+-three
++3
+diff --git a/gone b/gone
+deleted file mode 100644
+index 5555555..0000000
+--- a/gone
++++ /dev/null
+@@ -1 +0,0 @@
+-gone
+diff --git a/new b/new
+new file mode 100644
+index 0000000..4444444
+--- /dev/null
++++ b/new
+@@ -0,0 +1 @@
++new
+";
+        let parser = PatchParser::new();
+        let parse = |text: &str| {
+            parser
+                .parse_lines(&Lines::from_string(text))
+                .unwrap()
+                .unwrap()
+        };
+        let squashed = parse(first).squashed(&parse(second), 1, 3).unwrap();
+        assert_eq!(
+            squashed.to_string(),
+            "diff --git a/file b/file
+index 1111111..3333333 100644
+--- a/file
++++ b/file
+@@ -1,3 +1,3 @@
+ one
+-two
+-three
++2
++3
+diff --git a/new b/new
+new file mode 100644
+index 0000000..4444444
+--- /dev/null
++++ b/new
+@@ -0,0 +1 @@
++new
+"
+        );
+    }
+
+    #[test]
+    fn patch_squash_using_contents() {
+        let contents = Lines::from_string("one\ntwo\nthree\nfour\nfive\nsix\nseven\n");
+        let first = "diff --git a/file b/file
+--- a/file
++++ b/file
+@@ -1,3 +1,3 @@
+ one
+-two
++2
+ three
+";
+        // NB: the later diff's line numbers are out by one
+        let second = "diff --git a/file b/file
+--- a/file
++++ b/file
+@@ -3,3 +3,3 @@
+ 2
+-three
++3
+ four
+";
+        let parser = PatchParser::new();
+        let parse = |text: &str| {
+            parser
+                .parse_lines(&Lines::from_string(text))
+                .unwrap()
+                .unwrap()
+        };
+        let (first, second) = (parse(first), parse(second));
+        assert!(matches!(
+            first.squashed(&second, 1, 3),
+            Err(DiffParseError::DiffConflict(0))
+        ));
+        assert!(matches!(
+            first.squashed_using(&second, 1, 3, |_| None),
+            Err(DiffParseError::DiffConflict(0))
+        ));
+        let squashed = first
+            .squashed_using(&second, 1, 3, |file_path| {
+                if file_path == "file" {
+                    Some(contents.clone())
+                } else {
+                    None
+                }
+            })
+            .unwrap();
+        assert_eq!(
+            squashed.to_string(),
+            "diff --git a/file b/file
+--- a/file
++++ b/file
+@@ -1,6 +1,6 @@
+ one
+-two
+-three
++2
++3
+ four
+ five
+ six
+"
+        );
+        let mismatched = Lines::from_string("1\n2\n3\n");
+        assert!(first
+            .squashed_using(&second, 1, 3, |_| Some(mismatched.clone()))
+            .is_err());
+    }
+
+    #[test]
+    fn patch_interdiff() {
+        let lao = Lines::from_string("one\ntwo\nthree\nfour\nfive\nsix\nseven\n");
+        let v1 = Lines::from_string("one\n2\nthree\nfour\nfive\nsix\nseven\n");
+        let v2 = Lines::from_string("one\n2\nthree\nfour\nfive\nsix\n7\n");
+        let generator = UnifiedDiffGenerator::default();
+        let diff_text = |path: &str, ante: &Lines, post: &Lines| {
+            let diff = generator
+                .generate(&format!("a/{path}"), &format!("b/{path}"), ante, post)
+                .unwrap();
+            diff.iter().map(|line| line.as_str()).collect::<String>()
+        };
+        let parser = PatchParser::new();
+        let parse = |text: &str| {
+            parser
+                .parse_lines(&Lines::from_string(text))
+                .unwrap()
+                .unwrap()
+        };
+        let old = parse(&(diff_text("file", &lao, &v1) + &diff_text("dropped", &lao, &v1)));
+        let new = parse(&(diff_text("file", &lao, &v2) + &diff_text("added", &lao, &v2)));
+
+        let patch = interdiff(&old, &new, 1, 3).unwrap();
+        assert_eq!(patch.num_files(), 3);
+        assert_eq!(
+            patch.to_string(),
+            diff_text("file", &v1, &v2)
+                + &diff_text("dropped", &v1, &lao)
+                + &diff_text("added", &lao, &v2)
+        );
+        assert!(interdiff(&old, &old, 1, 3).unwrap().is_empty());
+        let other = Lines::from_string("one\nTWO\nthree\nfour\nfive\nsix\nseven\n");
+        let rebased = parse(&diff_text("file", &other, &v2));
+        assert!(interdiff(&old, &rebased, 1, 3).is_err());
+    }
+
+    #[test]
+    fn patch_id_matches_git() {
+        let text_diffs = "diff --git a/f b/f
+index 0ff3bbb..fb3ced1 100644
+--- a/f
++++ b/f
+@@ -2,7 +2,7 @@
+ 2
+ 3
+ 4
+-5
++five
+ 6
+ 7
+ 8
+diff --git a/g b/g
+index f00c965..3180775 100644
+--- a/g
++++ b/g
+@@ -1,6 +1,6 @@
+ 1
+ 2
+-3
++three
+ 4
+ 5
+ 6
+";
+        let binary_diff = "diff --git a/bin b/bin
+new file mode 100644
+index 0000000000000000000000000000000000000000..8352675d67aed6625ece79af41c27fdb4ee2e867
+GIT binary patch
+literal 3
+KcmZQzWC8#H2LJ>B
+
+literal 0
+HcmV?d00001
+
+";
+        let parser = PatchParser::new();
+        let patch_id = |text: &str| {
+            let patch = parser
+                .parse_lines(&Lines::from_string(text))
+                .unwrap()
+                .unwrap();
+            patch
+                .patch_id()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>()
+        };
+        // expected values are from "git patch-id --stable"
+        assert_eq!(
+            patch_id(text_diffs),
+            "31218062f7217f7ba7a649dd901521b92df5f52f"
+        );
+        // git hashes the "---"/"+++" lines with their quoted paths
+        let quoted_diff = r#"diff --git "a/caf\303\251" "b/caf\303\251"
+index 587be6b..975fbec 100644
+--- "a/caf\303\251"
++++ "b/caf\303\251"
+@@ -1 +1 @@
+-x
++y
+"#;
+        assert_eq!(
+            patch_id(quoted_diff),
+            "f6c4124b47fabff679b566a6369da8bd523e4026"
+        );
+        assert_eq!(
+            patch_id(&(quoted_diff.to_string() + text_diffs)),
+            "27e692ad3e1c3f72215cb083c7b2c97680333656"
+        );
+        assert_eq!(
+            patch_id(binary_diff),
+            "2e4146cb7dcfeb0ca2734976dcefe92c2080b579"
+        );
+        // NB: git skips the "diff --git" line of a file after a binary
+        // diff so the order of the files matters
+        assert_eq!(
+            patch_id(&(text_diffs.to_string() + binary_diff)),
+            "5f62c62d75f16a88491a93536d050be64d75aba9"
+        );
+        assert_eq!(
+            patch_id(&(binary_diff.to_string() + text_diffs)),
+            "9dca23d9f248a6f29f92cf2addab5510cf14a7ce"
+        );
+        // a diff with only a preamble is hashed with the following file
+        let mode_diff = "diff --git a/m b/m
+old mode 100644
+new mode 100755
+";
+        assert_eq!(
+            patch_id(mode_diff),
+            "16c118692b762dca152e84a2f93d6f6d7e1ae9b8"
+        );
+        assert_eq!(
+            patch_id(&(mode_diff.to_string() + text_diffs)),
+            "46fc8dc045b6be1c4dd451962923efb6d65ff536"
+        );
+        assert_eq!(
+            patch_id(&(text_diffs.to_string() + mode_diff)),
+            "47e298cb2298ac45bdd4cd7f8a539026ac0fdfe8"
+        );
+        assert_eq!(
+            patch_id(&(binary_diff.to_string() + mode_diff)),
+            "296f0b40fb073c88269fa3f91313ca94b6ff50d4"
+        );
+        let patch = parser
+            .parse_lines(&Lines::from_string(binary_diff))
+            .unwrap()
+            .unwrap();
+        assert_eq!(patch.diff_pluses()[0].patch_id(), patch.patch_id());
+        let (f_diff, g_diff) = text_diffs.split_at(text_diffs.find("diff --git a/g").unwrap());
+        assert_eq!(
+            patch_id(&(g_diff.to_string() + f_diff)),
+            patch_id(text_diffs)
+        );
+        let moved = text_diffs
+            .replace("@@ -2,7 +2,7 @@", "@@ -12,7 +12,7 @@")
+            .replace("+five", "+ fi ve");
+        assert_eq!(patch_id(&moved), patch_id(text_diffs));
+        assert_ne!(
+            patch_id(&text_diffs.replace("+five", "+FIVE")),
+            patch_id(text_diffs)
+        );
+
+        let patch = parser
+            .parse_lines(&Lines::from_string(text_diffs))
+            .unwrap()
+            .unwrap();
+        match patch.diff_pluses()[0].diff() {
+            Diff::Unified(diff) => assert_ne!(diff.hunks()[0].patch_id(), vec![0u8; 20]),
+            _ => panic!("expected a unified diff"),
+        }
+    }
+
+    #[test]
+    fn patch_commute() {
+        let first = "--- a/file
++++ b/file
+@@ -1,5 +1,6 @@
+ 1
+-2
++two
++two and a half
+ 3
+ 4
+ 5
+";
+        let second = "--- a/file
++++ b/file
+@@ -7,6 +7,7 @@
+ 6
+ 7
+ 8
+-9
++nine
++nine and a half
+ 10
+ 11
+--- a/other
++++ b/other
+@@ -1 +1 @@
+-other
++OTHER
+";
+        let parser = PatchParser::new();
+        let parse = |text: &str| {
+            parser
+                .parse_lines(&Lines::from_string(text))
+                .unwrap()
+                .unwrap()
+        };
+        let (first, second) = (parse(first), parse(second));
+        let (second_first, first_second) = first.commuted(&second, 1, 3).unwrap().unwrap();
+        assert_eq!(
+            second_first.to_string(),
+            "--- a/file
++++ b/file
+@@ -6,6 +6,7 @@
+ 6
+ 7
+ 8
+-9
++nine
++nine and a half
+ 10
+ 11
+--- a/other
++++ b/other
+@@ -1 +1 @@
+-other
++OTHER
+"
+        );
+        assert_eq!(
+            first_second.to_string(),
+            "--- a/file
++++ b/file
+@@ -1,5 +1,6 @@
+ 1
+-2
++two
++two and a half
+ 3
+ 4
+ 5
+"
+        );
+        assert_eq!(
+            second_first
+                .squashed(&first_second, 1, 3)
+                .unwrap()
+                .to_string(),
+            first.squashed(&second, 1, 3).unwrap().to_string()
+        );
+
+        let touching = parse(
+            "--- a/file
++++ b/file
+@@ -1,6 +1,6 @@
+ 1
+ two
+ two and a half
+-3
++three
+ 4
+ 5
+",
+        );
+        assert!(first.commuted(&touching, 1, 3).unwrap().is_none());
+    }
+
+    #[test]
+    fn patch_line_mapping() {
+        let first = "diff --git a/file b/file
+index 1111111..2222222 100644
+--- a/file
++++ b/file
+@@ -1,3 +1,4 @@
+ one
++one and a half
+ two
+ three
+";
+        let second = "diff --git a/file b/renamed
+similarity index 80%
+rename from file
+rename to renamed
+index 2222222..3333333 100644
+--- a/file
++++ b/renamed
+@@ -2,3 +2,2 @@
+ one and a half
+-two
+ three
+";
+        let parser = PatchParser::new();
+        let parse = |text: &str| {
+            parser
+                .parse_lines(&Lines::from_string(text))
+                .unwrap()
+                .unwrap()
+        };
+        let patches = [parse(first), parse(second)];
+        assert_eq!(
+            patches[0].mapped_line("file", 2, 1, false),
+            Some(("file".to_string(), 3))
+        );
+        assert_eq!(
+            patches[0].mapped_line("other", 2, 1, false),
+            Some(("other".to_string(), 2))
+        );
+        assert_eq!(
+            mapped_line_through(&patches, "file", 3, 1, false),
+            Some(("renamed".to_string(), 3))
+        );
+        assert_eq!(mapped_line_through(&patches, "file", 2, 1, false), None);
+        assert_eq!(
+            mapped_line_through(&patches, "renamed", 3, 1, true),
+            Some(("file".to_string(), 3))
+        );
+        assert_eq!(mapped_line_through(&patches, "renamed", 2, 1, true), None);
+        assert_eq!(patches[0].mapped_line("other", 0, 1, false), None);
+        assert_eq!(mapped_line_through(&patches, "file", 0, 1, false), None);
+    }
+
+    #[test]
+    fn patch_diff_stats() {
+        let text = "diff --git a/file b/file
+index 1111111..2222222 100644
+--- a/file
++++ b/file
+@@ -1,3 +1,3 @@
+ one
+-two
++2
+ three
+diff --git a/new b/new
+new file mode 100644
+index 0000000..5555555
+--- /dev/null
++++ b/new
+@@ -0,0 +1,2 @@
++new
++lines
+";
+        let patch = PatchParser::new()
+            .parse_lines(&Lines::from_string(text))
+            .unwrap()
+            .unwrap();
+        let file_stats = patch.file_diff_stats(1);
+        assert_eq!(file_stats.len(), 2);
+        assert_eq!(file_stats[0].0, "file");
+        // a deleted line followed by an inserted one counts as modified
+        assert_eq!(file_stats[0].1.count(DiffStatsCategory::Inserted), 0);
+        assert_eq!(file_stats[0].1.count(DiffStatsCategory::Deleted), 0);
+        assert_eq!(file_stats[0].1.count(DiffStatsCategory::Modified), 1);
+        assert_eq!(file_stats[0].1.count(DiffStatsCategory::Unchanged), 2);
+        assert_eq!(file_stats[1].0, "new");
+        assert_eq!(file_stats[1].1.count(DiffStatsCategory::Inserted), 2);
+        let stats = patch.diff_stats();
+        assert_eq!(stats.count(DiffStatsCategory::Inserted), 2);
+        assert_eq!(stats.count(DiffStatsCategory::Deleted), 0);
+        assert_eq!(stats.count(DiffStatsCategory::Modified), 1);
+        assert_eq!(stats.count(DiffStatsCategory::Unchanged), 2);
+    }
+
+    #[test]
+    fn patch_diff_stat_mismatches() {
+        let diffs = "diff --git a/file b/file
+index 1111111..2222222 100644
+--- a/file
++++ b/file
+@@ -1,3 +1,3 @@
+ one
+-two
++2
+ three
+diff --git a/new b/new
+new file mode 100644
+index 0000000..5555555
+--- /dev/null
++++ b/new
+@@ -0,0 +1,2 @@
++new
++lines
+";
+        let header = "Change some files
+---
+ file | 2 +-
+ new  | 2 ++
+ 2 files changed, 3 insertions(+), 1 deletion(-)
+
+";
+        let parser = PatchParser::new();
+        let patch = parser
+            .parse_lines(&Lines::from_string(diffs))
+            .unwrap()
+            .unwrap();
+        assert!(patch.diff_stat_mismatches(1).is_none());
+        let patch = parser
+            .parse_lines(&Lines::from_string(&(header.to_string() + diffs)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(patch.diff_stat_mismatches(1), Some(vec![]));
+
+        let stale_header = header
+            .replace(" new  | 2 ++\n", " new  | 1 +\n")
+            .replace(" file |", " gone |");
+        let patch = parser
+            .parse_lines(&Lines::from_string(&(stale_header + diffs)))
+            .unwrap()
+            .unwrap();
+        let mismatches = patch.diff_stat_mismatches(1).unwrap();
+        assert_eq!(mismatches.len(), 3);
+        match &mismatches[0] {
+            DiffStatMismatch::Unmatched(entry) => assert_eq!(entry.post_file_path(), "gone"),
+            _ => panic!("expected an unmatched entry"),
+        }
+        match &mismatches[1] {
+            DiffStatMismatch::Changed(entry, actual) => {
+                assert_eq!(entry.post_file_path(), "new");
+                assert_eq!(
+                    *actual,
+                    DiffStatChange::Text {
+                        changes: 2,
+                        insertions: 2,
+                        deletions: 0
+                    }
+                );
+            }
+            _ => panic!("expected a changed entry"),
+        }
+        assert_eq!(
+            mismatches[2],
+            DiffStatMismatch::Unlisted("file".to_string())
+        );
+    }
 }
@@ -7,8 +7,12 @@ use regex::Regex;
 
 use pw_pathux::str_path::*;
 
+use crate::git_diff::PairingKind;
 use crate::lines::{Line, Lines};
-use crate::PATH_RE_STR;
+use crate::text_diff::{
+    parse_rendered, quoted_path, reversed_file_paths, DiffBuildError, DiffBuildResult,
+};
+use crate::{requoted_path, unquoted_path, PATH_RE_STR, SPACED_PATH_RE_STR};
 
 pub trait PreambleIfce {
     fn len(&self) -> usize;
@@ -24,6 +28,7 @@ pub trait PreambleParserIfce<P: PreambleIfce> {
     fn get_preamble_at(&self, lines: &[Line], start_index: usize) -> Option<P>;
 }
 
+#[derive(Clone)]
 pub struct GitPreamble {
     lines: Lines,
     ante_file_path: String,
@@ -71,6 +76,140 @@ impl GitPreamble {
     pub fn get_extra_line_index(&self, name: &str) -> Option<usize> {
         self.extras.get(name).map(|extra| extra.1)
     }
+
+    // The preamble for undoing the diff i.e. with the modes, hashes and
+    // rename/copy paths swapped and new files becoming deleted ones
+    pub fn reversed(&self) -> GitPreamble {
+        let (ante_file_path, post_file_path) =
+            reversed_file_paths(&self.ante_file_path, &self.post_file_path);
+        let mut lines = self.lines.clone();
+        lines[0] = Line::new(format!(
+            "diff --git {} {}\n",
+            requoted_path(&ante_file_path),
+            requoted_path(&post_file_path)
+        ));
+        for (name, (value, index)) in self.extras.iter() {
+            let other = |name: &str| self.get_extra(name).unwrap_or_default();
+            let line = match name.as_str() {
+                "old mode" => format!("old mode {}\n", other("new mode")),
+                "new mode" => format!("new mode {}\n", other("old mode")),
+                "deleted file mode" => format!("new file mode {value}\n"),
+                "new file mode" => format!("deleted file mode {value}\n"),
+                "copy from" => format!("copy from {}\n", other("copy to")),
+                "copy to" => format!("copy to {}\n", other("copy from")),
+                "rename from" => format!("rename from {}\n", other("rename to")),
+                "rename to" => format!("rename to {}\n", other("rename from")),
+                "index" => {
+                    let (hashes, mode) = match value.split_once(' ') {
+                        Some((hashes, mode)) => (hashes, format!(" {mode}")),
+                        None => (value.as_str(), String::new()),
+                    };
+                    let (ante_hash, post_hash) = hashes.split_once("..").unwrap_or_default();
+                    format!("index {post_hash}..{ante_hash}{mode}\n")
+                }
+                _ => continue,
+            };
+            lines[*index] = Line::new(line);
+        }
+        GitPreambleParser::new()
+            .get_preamble_at(&lines, 0)
+            .expect("reversed preambles should always parse")
+    }
+
+    fn index_mode(&self) -> Option<&str> {
+        self.get_extra("index")?
+            .split_once(' ')
+            .map(|(_, mode)| mode)
+    }
+
+    // The file's mode before and after the diff (None if it doesn't exist)
+    fn ante_mode(&self) -> Option<&str> {
+        self.get_extra("old mode")
+            .or_else(|| self.get_extra("deleted file mode"))
+            .or_else(|| self.index_mode())
+    }
+
+    fn post_mode(&self) -> Option<&str> {
+        self.get_extra("new mode")
+            .or_else(|| self.get_extra("new file mode"))
+            .or_else(|| self.index_mode())
+    }
+
+    // The preamble for the combination of this diff and "later" (a diff
+    // made on top of it) or None if this diff creates the file and
+    // "later" deletes it.  NB: similarity indices are left out as they
+    // can't be worked out without the files' contents.
+    pub fn squashed(&self, later: &GitPreamble) -> Option<GitPreamble> {
+        let is_new = self.get_extra("new file mode").is_some();
+        let is_deleted = later.get_extra("deleted file mode").is_some();
+        if is_new && is_deleted {
+            return None;
+        }
+        let (ante_prefix, mut ante_path) = match self.ante_file_path.strip_prefix("a/") {
+            Some(path) => ("a/", path),
+            None => ("", self.ante_file_path.as_str()),
+        };
+        let (post_prefix, mut post_path) = match later.post_file_path.strip_prefix("b/") {
+            Some(path) => ("b/", path),
+            None => ("", later.post_file_path.as_str()),
+        };
+        // NB: git uses the same path on both sides for new and deleted files
+        if is_new {
+            ante_path = post_path;
+        } else if is_deleted {
+            post_path = ante_path;
+        }
+        let mut lines = vec![Line::new(format!(
+            "diff --git {} {}\n",
+            requoted_path(&format!("{ante_prefix}{ante_path}")),
+            requoted_path(&format!("{post_prefix}{post_path}"))
+        ))];
+        let ante_mode = self.ante_mode();
+        let post_mode = later.post_mode().or_else(|| self.post_mode());
+        if is_new {
+            lines.push(Line::new(format!(
+                "new file mode {}\n",
+                post_mode.unwrap_or_default()
+            )));
+        } else if is_deleted {
+            lines.push(Line::new(format!(
+                "deleted file mode {}\n",
+                ante_mode.unwrap_or_default()
+            )));
+        } else if let (Some(ante_mode), Some(post_mode)) = (ante_mode, post_mode) {
+            if ante_mode != post_mode {
+                lines.push(Line::new(format!("old mode {ante_mode}\n")));
+                lines.push(Line::new(format!("new mode {post_mode}\n")));
+            }
+        }
+        if ante_path != post_path {
+            let is_copy =
+                self.get_extra("copy from").is_some() || later.get_extra("copy from").is_some();
+            let kind = if is_copy { "copy" } else { "rename" };
+            lines.push(Line::new(format!(
+                "{kind} from {}\n",
+                requoted_path(ante_path)
+            )));
+            lines.push(Line::new(format!(
+                "{kind} to {}\n",
+                requoted_path(post_path)
+            )));
+        }
+        if let (Some(ante_index), Some(post_index)) =
+            (self.get_extra("index"), later.get_extra("index"))
+        {
+            let (ante_hash, _) = ante_index.split_once("..").unwrap_or_default();
+            let (_, post_hash) = post_index.split_once("..").unwrap_or_default();
+            let post_hash = post_hash.split(' ').next().unwrap_or_default();
+            match (ante_mode, post_mode) {
+                (Some(ante_mode), Some(post_mode)) if ante_mode == post_mode => lines.push(
+                    Line::new(format!("index {ante_hash}..{post_hash} {post_mode}\n")),
+                ),
+                _ => lines.push(Line::new(format!("index {ante_hash}..{post_hash}\n"))),
+            }
+        }
+        GitPreambleParser::new().get_preamble_at(&lines, 0)
+    }
 }
 
 impl PreambleIfce for GitPreamble {
@@ -83,6 +222,30 @@ impl PreambleIfce for GitPreamble {
     }
 }
 
+// Split the unquoted paths in a "diff --git" line when they contain
+// spaces.  Like git, prefer the split that gives the same path on both
+// sides (ignoring their first components) and otherwise take the split
+// before a "b/" prefix.
+fn split_spaced_paths(text: &str) -> Option<(String, String)> {
+    let splits: Vec<(&str, &str)> = text
+        .match_indices(' ')
+        .map(|(index, _)| (&text[..index], &text[index + 1..]))
+        .filter(|(ante, post)| !ante.is_empty() && !post.is_empty())
+        .collect();
+    fn tail(path: &str) -> &str {
+        path.split_once('/').map_or(path, |(_, tail)| tail)
+    }
+    splits
+        .iter()
+        .find(|(ante, post)| tail(ante) == tail(post))
+        .or_else(|| {
+            splits
+                .iter()
+                .find(|(ante, post)| ante.starts_with("a/") && post.starts_with("b/"))
+        })
+        .map(|(ante, post)| (ante.to_string(), post.to_string()))
+}
+
 pub struct GitPreambleParser {
     diff_cre: Regex,
     extras_cres: Vec<Regex>,
@@ -109,10 +272,10 @@ impl PreambleParserIfce<GitPreamble> for GitPreambleParser {
             r"^(similarity index)\s+((\d*)%)(\n)?$",
             r"^(dissimilarity index)\s+((\d*)%)(\n)?$",
             r"^(index)\s+(([a-fA-F0-9]+)..([a-fA-F0-9]+)( (\d*))?)(\n)?$",
-            &format!(r"^(copy from)\s+({PATH_RE_STR})(\n)?$"),
-            &format!(r"^(copy to)\s+({PATH_RE_STR})(\n)?$"),
-            &format!(r"^(rename from)\s+({PATH_RE_STR})(\n)?$"),
-            &format!(r"^(rename to)\s+({PATH_RE_STR})(\n)?$"),
+            &format!(r"^(copy from)\s+({SPACED_PATH_RE_STR})(\n)?$"),
+            &format!(r"^(copy to)\s+({SPACED_PATH_RE_STR})(\n)?$"),
+            &format!(r"^(rename from)\s+({SPACED_PATH_RE_STR})(\n)?$"),
+            &format!(r"^(rename to)\s+({SPACED_PATH_RE_STR})(\n)?$"),
         ]
         .iter()
         .map(|cre_str| Regex::new(cre_str).unwrap())
@@ -125,17 +288,24 @@ impl PreambleParserIfce<GitPreamble> for GitPreambleParser {
     }
 
     fn get_preamble_at(&self, lines: &[Line], start_index: usize) -> Option<GitPreamble> {
-        let captures = self.diff_cre.captures(&lines[start_index])?;
-        let ante_file_path = if let Some(path) = captures.get(3) {
-            path.as_str().to_string()
-        } else {
-            captures.get(4).unwrap().as_str().to_string() // TODO: confirm unwrap is OK here
-        };
-        let post_file_path = if let Some(path) = captures.get(6) {
-            path.as_str().to_string()
-        } else {
-            captures.get(7).unwrap().as_str().to_string() // TODO: confirm unwrap is OK here
-        };
+        let (ante_file_path, post_file_path) =
+            if let Some(captures) = self.diff_cre.captures(&lines[start_index]) {
+                let ante_file_path = if let Some(path) = captures.get(2) {
+                    unquoted_path(path.as_str())
+                } else {
+                    captures.get(3).unwrap().as_str().to_string() // TODO: confirm unwrap is OK here
+                };
+                let post_file_path = if let Some(path) = captures.get(5) {
+                    unquoted_path(path.as_str())
+                } else {
+                    captures.get(6).unwrap().as_str().to_string() // TODO: confirm unwrap is OK here
+                };
+                (ante_file_path, post_file_path)
+            } else {
+                // git doesn't quote paths just because they contain spaces
+                let text = lines[start_index].strip_prefix("diff --git ")?;
+                split_spaced_paths(text.trim_end_matches('\n'))?
+            };
 
         let mut extras: HashMap<String, (String, usize)> = HashMap::new();
         for (index, line) in lines.iter().enumerate().skip(start_index + 1) {
@@ -166,6 +336,174 @@ impl PreambleParserIfce<GitPreamble> for GitPreambleParser {
     }
 }
 
+// The file modes that git uses
+const GIT_FILE_MODES: [u32; 4] = [0o100644, 0o100755, 0o120000, 0o160000];
+
+fn check_mode(mode: u32) -> DiffBuildResult<u32> {
+    if GIT_FILE_MODES.contains(&mode) {
+        Ok(mode)
+    } else {
+        Err(DiffBuildError::InvalidMode(mode))
+    }
+}
+
+fn check_percentage(percentage: u8) -> DiffBuildResult<u8> {
+    if percentage <= 100 {
+        Ok(percentage)
+    } else {
+        Err(DiffBuildError::InvalidPercentage(percentage))
+    }
+}
+
+fn check_hash(hash: &str) -> DiffBuildResult<&str> {
+    if !hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(hash)
+    } else {
+        Err(DiffBuildError::InvalidHash(hash.to_string()))
+    }
+}
+
+// Build a git preamble from its parts (checking that they make sense).
+// File paths are given without the "a/" and "b/" prefixes.
+pub struct GitPreambleBuilder {
+    ante_file_path: String,
+    post_file_path: String,
+    mode_change: Option<(u32, u32)>,
+    new_file_mode: Option<u32>,
+    deleted_file_mode: Option<u32>,
+    pairing: Option<(PairingKind, u8)>,
+    dissimilarity: Option<u8>,
+    index: Option<(String, String, Option<u32>)>,
+}
+
+impl GitPreambleBuilder {
+    pub fn new(ante_file_path: &str, post_file_path: &str) -> Self {
+        GitPreambleBuilder {
+            ante_file_path: ante_file_path.to_string(),
+            post_file_path: post_file_path.to_string(),
+            mode_change: None,
+            new_file_mode: None,
+            deleted_file_mode: None,
+            pairing: None,
+            dissimilarity: None,
+            index: None,
+        }
+    }
+
+    pub fn mode_change(mut self, old_mode: u32, new_mode: u32) -> Self {
+        self.mode_change = Some((old_mode, new_mode));
+        self
+    }
+
+    pub fn new_file(mut self, mode: u32) -> Self {
+        self.new_file_mode = Some(mode);
+        self
+    }
+
+    pub fn deleted_file(mut self, mode: u32) -> Self {
+        self.deleted_file_mode = Some(mode);
+        self
+    }
+
+    pub fn renamed(mut self, similarity: u8) -> Self {
+        self.pairing = Some((PairingKind::Rename, similarity));
+        self
+    }
+
+    pub fn copied(mut self, similarity: u8) -> Self {
+        self.pairing = Some((PairingKind::Copy, similarity));
+        self
+    }
+
+    pub fn dissimilarity(mut self, dissimilarity: u8) -> Self {
+        self.dissimilarity = Some(dissimilarity);
+        self
+    }
+
+    // "mode" should only be given if the file's mode is unchanged
+    pub fn index(mut self, ante_hash: &str, post_hash: &str, mode: Option<u32>) -> Self {
+        self.index = Some((ante_hash.to_string(), post_hash.to_string(), mode));
+        self
+    }
+
+    fn lines(&self) -> DiffBuildResult<Lines> {
+        let conflict = |what: &str| Err(DiffBuildError::Conflict(what.to_string()));
+        let is_new_or_deleted = self.new_file_mode.is_some() || self.deleted_file_mode.is_some();
+        if self.new_file_mode.is_some() && self.deleted_file_mode.is_some() {
+            return conflict("a file can't be both new and deleted");
+        }
+        if is_new_or_deleted && (self.mode_change.is_some() || self.pairing.is_some()) {
+            return conflict("new and deleted files can't have mode changes, renames or copies");
+        }
+        if self.pairing.is_none() && self.ante_file_path != self.post_file_path {
+            return conflict("different file paths require a rename or copy");
+        }
+        let ante_file_path = quoted_path(&self.ante_file_path)?;
+        let post_file_path = quoted_path(&self.post_file_path)?;
+        let mut lines = vec![Line::new(format!(
+            "diff --git {} {}\n",
+            quoted_path(&format!("a/{}", self.ante_file_path))?,
+            quoted_path(&format!("b/{}", self.post_file_path))?,
+        ))];
+        if let Some((old_mode, new_mode)) = self.mode_change {
+            if old_mode == new_mode {
+                return conflict("a mode change needs different modes");
+            }
+            lines.push(Line::new(format!("old mode {:o}\n", check_mode(old_mode)?)));
+            lines.push(Line::new(format!("new mode {:o}\n", check_mode(new_mode)?)));
+        }
+        if let Some(mode) = self.deleted_file_mode {
+            lines.push(Line::new(format!(
+                "deleted file mode {:o}\n",
+                check_mode(mode)?
+            )));
+        }
+        if let Some(mode) = self.new_file_mode {
+            lines.push(Line::new(format!(
+                "new file mode {:o}\n",
+                check_mode(mode)?
+            )));
+        }
+        if let Some((kind, similarity)) = self.pairing {
+            let kind = match kind {
+                PairingKind::Rename => "rename",
+                PairingKind::Copy => "copy",
+            };
+            lines.push(Line::new(format!(
+                "similarity index {}%\n",
+                check_percentage(similarity)?
+            )));
+            lines.push(Line::new(format!("{kind} from {ante_file_path}\n")));
+            lines.push(Line::new(format!("{kind} to {post_file_path}\n")));
+        }
+        if let Some(dissimilarity) = self.dissimilarity {
+            lines.push(Line::new(format!(
+                "dissimilarity index {}%\n",
+                check_percentage(dissimilarity)?
+            )));
+        }
+        if let Some((ref ante_hash, ref post_hash, mode)) = self.index {
+            let ante_hash = check_hash(ante_hash)?;
+            let post_hash = check_hash(post_hash)?;
+            if let Some(mode) = mode {
+                let mode = check_mode(mode)?;
+                lines.push(Line::new(format!(
+                    "index {ante_hash}..{post_hash} {mode:o}\n"
+                )));
+            } else {
+                lines.push(Line::new(format!("index {ante_hash}..{post_hash}\n")));
+            }
+        }
+        Ok(lines)
+    }
+
+    pub fn build(&self) -> DiffBuildResult<GitPreamble> {
+        let lines = self.lines()?;
+        parse_rendered(&lines, |lines| Ok(GitPreambleParser::new().get_preamble_at(lines, 0)))
+    }
+}
+
+#[derive(Clone)]
 pub struct DiffPreamble {
     lines: Lines,
     ante_file_path: String,
@@ -196,6 +534,30 @@ impl DiffPreamble {
             self.post_file_path.path_stripped_of_n_levels(strip_level)
         }
     }
+
+    // The preamble with the file paths swapped (and the options untouched)
+    pub fn reversed(&self) -> DiffPreamble {
+        let parser = DiffPreambleParser::new();
+        let line = &self.lines[0];
+        let captures = parser
+            .cre
+            .captures(line)
+            .expect("parsed preambles should always match");
+        let (ante, post) = (captures.get(2).unwrap(), captures.get(5).unwrap());
+        let (ante_file_path, post_file_path) =
+            reversed_file_paths(&self.ante_file_path, &self.post_file_path);
+        let lines = vec![Line::new(format!(
+            "{}{}{}{}{}",
+            &line[..ante.start()],
+            requoted_path(&ante_file_path),
+            &line[ante.end()..post.start()],
+            requoted_path(&post_file_path),
+            &line[post.end()..]
+        ))];
+        parser
+            .get_preamble_at(&lines, 0)
+            .expect("reversed preambles should always parse")
+    }
 }
 
 impl PreambleIfce for DiffPreamble {
@@ -236,12 +598,12 @@ impl PreambleParserIfce<DiffPreamble> for DiffPreambleParser {
             }
         }
         let ante_file_path = if let Some(path) = captures.get(3) {
-            path.as_str().to_string()
+            unquoted_path(path.as_str())
         } else {
             captures.get(4).unwrap().as_str().to_string() // TODO: confirm unwrap is OK here
         };
         let post_file_path = if let Some(path) = captures.get(6) {
-            path.as_str().to_string()
+            unquoted_path(path.as_str())
         } else {
             captures.get(7).unwrap().as_str().to_string() // TODO: confirm unwrap is OK here
         };
@@ -253,6 +615,48 @@ impl PreambleParserIfce<DiffPreamble> for DiffPreambleParser {
     }
 }
 
+// Build a (non git) diff preamble e.g. "diff -u ante post"
+pub struct DiffPreambleBuilder {
+    options: String,
+    ante_file_path: String,
+    post_file_path: String,
+}
+
+impl DiffPreambleBuilder {
+    pub fn new(options: &str, ante_file_path: &str, post_file_path: &str) -> Self {
+        DiffPreambleBuilder {
+            options: options.to_string(),
+            ante_file_path: ante_file_path.to_string(),
+            post_file_path: post_file_path.to_string(),
+        }
+    }
+
+    pub fn build(&self) -> DiffBuildResult<DiffPreamble> {
+        let options = self.options.trim();
+        if options.is_empty() || options.contains("--git") || options.contains(['\n', '\r']) {
+            return Err(DiffBuildError::InvalidOptions(self.options.clone()));
+        }
+        let lines = vec![Line::new(format!(
+            "diff {} {} {}\n",
+            options,
+            quoted_path(&self.ante_file_path)?,
+            quoted_path(&self.post_file_path)?
+        ))];
+        let preamble = parse_rendered(&lines, |lines| {
+            Ok(DiffPreambleParser::new().get_preamble_at(lines, 0))
+        })?;
+        // NB: the parser can't always tell where the options end
+        if preamble.ante_file_path != self.ante_file_path {
+            Err(DiffBuildError::InvalidPath(self.ante_file_path.clone()))
+        } else if preamble.post_file_path != self.post_file_path {
+            Err(DiffBuildError::InvalidPath(self.post_file_path.clone()))
+        } else {
+            Ok(preamble)
+        }
+    }
+}
+
+#[derive(Clone)]
 pub enum Preamble {
     Git(GitPreamble),
     Diff(DiffPreamble),
@@ -297,6 +701,13 @@ impl Preamble {
             Preamble::Diff(preamble) => preamble.get_file_path(strip_level),
         }
     }
+
+    pub fn reversed(&self) -> Preamble {
+        match self {
+            Preamble::Git(preamble) => Preamble::Git(preamble.reversed()),
+            Preamble::Diff(preamble) => Preamble::Diff(preamble.reversed()),
+        }
+    }
 }
 
 #[derive(Default)]
@@ -346,4 +757,115 @@ mod tests {
         let preamble = preamble.unwrap();
         assert!(preamble.get_extra_line_index("index") == Some(2));
     }
+
+    #[test]
+    fn git_preamble_parser_quoted_paths() {
+        let parser = GitPreambleParser::new();
+        for (line, ante_file_path, post_file_path) in [
+            ("diff --git a/file b/file\n", "a/file", "b/file"),
+            ("diff --git \"a/old name\" b/new\n", "a/old name", "b/new"),
+            ("diff --git a/new \"b/old name\"\n", "a/new", "b/old name"),
+            (
+                "diff --git \"a/old name\" \"b/new name\"\n",
+                "a/old name",
+                "b/new name",
+            ),
+            // as output by git
+            ("diff --git a/a b c b/a b c\n", "a/a b c", "b/a b c"),
+            ("diff --git a/old name b/new\n", "a/old name", "b/new"),
+            (
+                "diff --git \"a/q\\\"x\" \"b/t\\303\\251st\"\n",
+                "a/q\"x",
+                "b/t\u{e9}st",
+            ),
+        ] {
+            let lines = vec![Arc::new(line.to_string())];
+            let preamble = parser.get_preamble_at(&lines, 0).unwrap();
+            assert_eq!(preamble.ante_file_path_as_str(), ante_file_path);
+            assert_eq!(preamble.post_file_path_as_str(), post_file_path);
+        }
+    }
+
+    #[test]
+    fn git_preamble_builder_works() {
+        let preamble = GitPreambleBuilder::new("old name", "new")
+            .mode_change(0o100644, 0o100755)
+            .renamed(91)
+            .index("6826c6c", "a48404a", None)
+            .build()
+            .unwrap();
+        let text: String = preamble.iter().map(|line| line.as_str()).collect();
+        assert_eq!(
+            text,
+            "diff --git a/old name b/new\nold mode 100644\nnew mode 100755\n\
+             similarity index 91%\nrename from old name\nrename to new\n\
+             index 6826c6c..a48404a\n"
+        );
+        assert_eq!(preamble.ante_file_path_as_str(), "a/old name");
+        assert_eq!(preamble.get_extra("rename from"), Some("old name"));
+        assert_eq!(preamble.get_extra_line_index("index"), Some(6));
+
+        let builder = GitPreambleBuilder::new("file", "file");
+        assert_eq!(
+            builder.new_file(0o644).build().err(),
+            Some(DiffBuildError::InvalidMode(0o644))
+        );
+        assert!(GitPreambleBuilder::new("file", "other").build().is_err());
+        assert!(GitPreambleBuilder::new("file", "file")
+            .new_file(0o100644)
+            .deleted_file(0o100644)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn diff_preamble_builder_works() {
+        let preamble = DiffPreambleBuilder::new("-u", "lao", "tzu")
+            .build()
+            .unwrap();
+        assert_eq!(*preamble.lines[0], "diff -u lao tzu\n");
+        assert_eq!(preamble.get_file_path(0), "tzu");
+        assert!(DiffPreambleBuilder::new("", "lao", "tzu").build().is_err());
+        assert!(DiffPreambleBuilder::new("-u", "la o", "tzu")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn git_preamble_reversed() {
+        let preamble = GitPreambleBuilder::new("old name", "new")
+            .mode_change(0o100644, 0o100755)
+            .renamed(91)
+            .index("6826c6c", "a48404a", None)
+            .build()
+            .unwrap();
+        let text: String = preamble
+            .reversed()
+            .iter()
+            .map(|line| line.as_str())
+            .collect();
+        assert_eq!(
+            text,
+            "diff --git a/new b/old name\nold mode 100755\nnew mode 100644\n\
+             similarity index 91%\nrename from new\nrename to old name\n\
+             index a48404a..6826c6c\n"
+        );
+
+        let preamble = GitPreambleBuilder::new("file", "file")
+            .new_file(0o100644)
+            .index("0000000", "0503e55", None)
+            .build()
+            .unwrap();
+        let reversed = preamble.reversed();
+        assert_eq!(reversed.get_extra("deleted file mode"), Some("100644"));
+        assert_eq!(reversed.get_extra("new file mode"), None);
+        assert_eq!(reversed.get_extra("index"), Some("0503e55..0000000"));
+        assert!(reversed.reversed().iter().eq(preamble.iter()));
+
+        let preamble = DiffPreambleBuilder::new("-u -p", "lao", "tzu")
+            .build()
+            .unwrap()
+            .reversed();
+        assert_eq!(*preamble.lines[0], "diff -u -p tzu lao\n");
+    }
 }
@@ -0,0 +1,386 @@
+//Copyright 2019 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Compare two versions of a patch series (in the manner of "git
+//! range-diff") by matching each patch in the old series with its
+//! counterpart (if any) in the new one and showing how the diffs of
+//! the matched patches differ.
+
+use std::fmt;
+
+use crate::diff::Diff;
+use crate::diff_algorithm::{Algorithm, DiffAlgorithm, DiffComponent};
+use crate::lines::{Line, Lines};
+use crate::patch::Patch;
+use crate::preamble::{Preamble, PreambleIfce};
+use crate::text_diff::{HunkOp, TextDiffHunk};
+use crate::unified_diff::{UnifiedDiff, UnifiedDiffGenerator};
+
+fn push_ops(lines: &mut Lines, ops: &[HunkOp]) {
+    for (prefix, line) in ops.iter() {
+        if line.ends_with('\n') {
+            lines.push(Line::new(format!("{prefix}{line}")));
+        } else {
+            lines.push(Line::new(format!("{prefix}{line}\n")));
+        }
+    }
+}
+
+// A patch's changes without the line numbers and object hashes (which
+// change whenever an earlier patch in the series does) as compared
+pub(crate) fn normalized_lines(patch: &Patch, strip_level: usize) -> Lines {
+    let mut lines = vec![];
+    for diff_plus in patch.diff_pluses().iter() {
+        lines.push(Line::new(format!(
+            "## {} ##\n",
+            diff_plus.get_file_path(strip_level)
+        )));
+        match (diff_plus.preamble(), diff_plus.diff()) {
+            (Some(Preamble::Git(preamble)), _) | (None, Diff::GitPreambleOnly(preamble)) => {
+                lines.extend(
+                    preamble
+                        .iter()
+                        .skip(1)
+                        .filter(|line| !line.starts_with("index "))
+                        .cloned(),
+                );
+            }
+            _ => (),
+        }
+        match diff_plus.diff() {
+            Diff::Unified(diff) => {
+                for hunk in diff.hunks().iter() {
+                    if let Some(heading) = &hunk.heading {
+                        lines.push(Line::new(format!("@@ {heading}\n")));
+                    } else {
+                        lines.push(Line::new("@@\n".to_string()));
+                    }
+                    push_ops(&mut lines, &hunk.ops());
+                }
+            }
+            Diff::Context(diff) => {
+                for hunk in diff.hunks().iter() {
+                    lines.push(Line::new("@@\n".to_string()));
+                    push_ops(&mut lines, &hunk.ops());
+                }
+            }
+            Diff::GitBinary(diff) => lines.extend(diff.iter().cloned()),
+            Diff::GitPreambleOnly(_) => (),
+        }
+    }
+    lines
+}
+
+// The first line of the patch's description less any "Subject:" and
+// "[PATCH ...]" prefixes
+pub(crate) fn subject(patch: &Patch) -> String {
+    let line = patch
+        .header()
+        .iter_description()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    let line = line.strip_prefix("Subject:").unwrap_or(line).trim_start();
+    match line.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((_, rest)) => rest.trim_start().to_string(),
+        None => line.to_string(),
+    }
+}
+
+// The number of lines that differ between "ante" and "post"
+fn diff_size(ante: &[Line], post: &[Line]) -> usize {
+    Algorithm::default()
+        .diff_lines(ante, post)
+        .iter()
+        .filter(|component| !matches!(component, DiffComponent::Unchanged(_, _)))
+        .count()
+}
+
+// Patches are identified by their indices in their series
+pub enum RangeDiffEntry {
+    // patches that make the same changes
+    Unchanged(usize, usize),
+    // versions of a patch and the diff between their (normalized) diffs
+    Modified(usize, usize, UnifiedDiff),
+    // a patch in the old series only
+    Removed(usize),
+    // a patch in the new series only
+    Added(usize),
+}
+
+pub struct RangeDiff {
+    old_subjects: Vec<String>,
+    new_subjects: Vec<String>,
+    entries: Vec<RangeDiffEntry>,
+}
+
+impl RangeDiff {
+    // The entries in new series order with each removed patch following
+    // the patches that preceded it in the old series
+    pub fn entries(&self) -> &Vec<RangeDiffEntry> {
+        &self.entries
+    }
+
+    // The (old index, new index) pairs of matched patches
+    pub fn matches(&self) -> Vec<(usize, usize)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match entry {
+                RangeDiffEntry::Unchanged(old, new) => Some((*old, *new)),
+                RangeDiffEntry::Modified(old, new, _) => Some((*old, *new)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The matched pairs that have been moved i.e. those not in the
+    // longest sequence of matches that are in the same order in both
+    pub fn reordered(&self) -> Vec<(usize, usize)> {
+        let matches = self.matches();
+        // the length of (and the previous match in) the longest
+        // in order sequence ending at each match
+        let mut longest: Vec<(usize, Option<usize>)> = vec![];
+        for (index, (old, _)) in matches.iter().enumerate() {
+            let best = (0..index)
+                .filter(|i| matches[*i].0 < *old)
+                .max_by_key(|i| longest[*i].0);
+            match best {
+                Some(i) => longest.push((longest[i].0 + 1, Some(i))),
+                None => longest.push((1, None)),
+            }
+        }
+        let mut in_order = vec![false; matches.len()];
+        let mut index = (0..matches.len()).max_by_key(|i| longest[*i].0);
+        while let Some(i) = index {
+            in_order[i] = true;
+            index = longest[i].1;
+        }
+        matches
+            .into_iter()
+            .zip(in_order)
+            .filter(|(_, in_order)| !in_order)
+            .map(|(pair, _)| pair)
+            .collect()
+    }
+}
+
+impl fmt::Display for RangeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in self.entries.iter() {
+            match entry {
+                RangeDiffEntry::Unchanged(old, new) => writeln!(
+                    f,
+                    "{}:  = {}:  {}",
+                    old + 1,
+                    new + 1,
+                    self.new_subjects[*new]
+                )?,
+                RangeDiffEntry::Modified(old, new, diff) => {
+                    writeln!(
+                        f,
+                        "{}:  ! {}:  {}",
+                        old + 1,
+                        new + 1,
+                        self.new_subjects[*new]
+                    )?;
+                    for hunk in diff.hunks().iter() {
+                        for line in hunk.iter() {
+                            write!(f, "    {line}")?;
+                        }
+                    }
+                }
+                RangeDiffEntry::Removed(old) => {
+                    writeln!(f, "{}:  < -:  {}", old + 1, self.old_subjects[*old])?
+                }
+                RangeDiffEntry::Added(new) => {
+                    writeln!(f, "-:  > {}:  {}", new + 1, self.new_subjects[*new])?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct RangeDiffGenerator {
+    pub strip_level: usize,
+    // Patches are only matched if the size of the difference between
+    // their diffs is at most this percentage of the larger diff's size
+    // (like git range-diff's --creation-factor)
+    pub creation_factor: usize,
+    // The context for the diffs between modified patches' diffs
+    pub context_len: usize,
+}
+
+impl Default for RangeDiffGenerator {
+    fn default() -> Self {
+        RangeDiffGenerator {
+            strip_level: 1,
+            creation_factor: 60,
+            context_len: 3,
+        }
+    }
+}
+
+impl RangeDiffGenerator {
+    // NB: patches are matched greedily (closest first) rather than by
+    // git's optimal assignment which gives the same result in all but
+    // contrived cases.
+    pub fn generate(&self, old: &[Patch], new: &[Patch]) -> RangeDiff {
+        let old_lines: Vec<Lines> = old
+            .iter()
+            .map(|patch| normalized_lines(patch, self.strip_level))
+            .collect();
+        let new_lines: Vec<Lines> = new
+            .iter()
+            .map(|patch| normalized_lines(patch, self.strip_level))
+            .collect();
+        let mut candidates = vec![];
+        for (i, ante) in old_lines.iter().enumerate() {
+            for (j, post) in new_lines.iter().enumerate() {
+                let size = diff_size(ante, post);
+                if size * 100 <= self.creation_factor * ante.len().max(post.len()) {
+                    candidates.push((size, i.abs_diff(j), i, j));
+                }
+            }
+        }
+        candidates.sort_unstable();
+        let mut old_match: Vec<Option<usize>> = vec![None; old.len()];
+        let mut new_match: Vec<Option<usize>> = vec![None; new.len()];
+        for (_, _, i, j) in candidates {
+            if old_match[i].is_none() && new_match[j].is_none() {
+                old_match[i] = Some(j);
+                new_match[j] = Some(i);
+            }
+        }
+
+        let generator = UnifiedDiffGenerator {
+            context_len: self.context_len,
+            ..UnifiedDiffGenerator::default()
+        };
+        let mut entries = vec![];
+        let mut next_old = 0;
+        for (j, matched) in new_match.iter().enumerate() {
+            if let Some(i) = *matched {
+                for (k, matched) in old_match.iter().enumerate().take(i).skip(next_old) {
+                    if matched.is_none() {
+                        entries.push(RangeDiffEntry::Removed(k));
+                    }
+                }
+                next_old = next_old.max(i + 1);
+                match generator.generate("old", "new", &old_lines[i], &new_lines[j]) {
+                    Some(diff) => entries.push(RangeDiffEntry::Modified(i, j, diff)),
+                    None => entries.push(RangeDiffEntry::Unchanged(i, j)),
+                }
+            } else {
+                entries.push(RangeDiffEntry::Added(j));
+            }
+        }
+        for (k, matched) in old_match.iter().enumerate().skip(next_old) {
+            if matched.is_none() {
+                entries.push(RangeDiffEntry::Removed(k));
+            }
+        }
+
+        RangeDiff {
+            old_subjects: old.iter().map(subject).collect(),
+            new_subjects: new.iter().map(subject).collect(),
+            entries,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::LinesIfce;
+    use crate::patch::PatchParser;
+
+    fn patch(subject: &str, file_path: &str, ante: &Lines, post: &Lines) -> Patch {
+        let diff = UnifiedDiffGenerator::default()
+            .generate(
+                &format!("a/{file_path}"),
+                &format!("b/{file_path}"),
+                ante,
+                post,
+            )
+            .unwrap();
+        let text: String = diff.iter().map(|line| line.as_str()).collect();
+        let text = format!("Subject: [PATCH] {subject}\n\n{text}");
+        PatchParser::new()
+            .parse_lines(&Lines::from_string(&text))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn range_diff_works() {
+        let base: String = (1..=30).map(|n| format!("line {n}\n")).collect();
+        let shifted = format!("line 0\n{base}");
+        let lines = |text: &str| Lines::from_string(text);
+        let empty = Lines::new();
+        let old = vec![
+            patch(
+                "first",
+                "f1",
+                &lines(&base),
+                &lines(&base.replace("line 2\n", "two\n")),
+            ),
+            patch(
+                "second",
+                "f1",
+                &lines(&base),
+                &lines(&base.replace("line 25\n", "25\n")),
+            ),
+            patch("third", "f2", &empty, &lines("hello\n")),
+        ];
+        let new = vec![
+            patch(
+                "second",
+                "f1",
+                &lines(&shifted),
+                &lines(&shifted.replace("line 25\n", "25\n")),
+            ),
+            patch(
+                "first",
+                "f1",
+                &lines(&base),
+                &lines(&base.replace("line 2\n", "TWO\n")),
+            ),
+            patch("fourth", "f3", &empty, &lines("goodbye\n")),
+        ];
+        assert_eq!(subject(&old[2]), "third");
+
+        let range_diff = RangeDiffGenerator::default().generate(&old, &new);
+        assert!(matches!(
+            range_diff.entries()[0],
+            RangeDiffEntry::Unchanged(1, 0)
+        ));
+        assert!(matches!(
+            range_diff.entries()[1],
+            RangeDiffEntry::Modified(0, 1, _)
+        ));
+        assert!(matches!(range_diff.entries()[2], RangeDiffEntry::Added(2)));
+        assert!(matches!(
+            range_diff.entries()[3],
+            RangeDiffEntry::Removed(2)
+        ));
+        assert_eq!(range_diff.matches(), vec![(1, 0), (0, 1)]);
+        assert_eq!(range_diff.reordered().len(), 1);
+        assert_eq!(
+            range_diff.to_string(),
+            "2:  = 1:  second
+1:  ! 2:  first
+    @@ -2,7 +2,7 @@
+     @@
+      line 1
+     -line 2
+    -+two
+    ++TWO
+      line 3
+      line 4
+      line 5
+-:  > 3:  fourth
+3:  < -:  third
+"
+        );
+    }
+}
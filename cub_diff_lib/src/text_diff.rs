@@ -1,20 +1,29 @@
 // Copyright 2019 Peter Williams <pwil3058@gmail.com>
 
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt;
-use std::io;
+use std::io::{self, Write};
+use std::iter;
 use std::num::ParseIntError;
+use std::path::Path;
 use std::slice::Iter;
 
-use regex::Captures;
+use crypto_hash::Hasher;
+use regex::{Captures, Regex};
 
 use pw_pathux::str_path::*;
 
 use crate::abstract_diff::{AbstractDiff, AbstractHunk, ApplnResult};
+use crate::diff_algorithm::{Algorithm, IndentHeuristic};
+use crate::diff_stats::{DiffStats, DiffStatsCategory};
 use crate::git_binary_diff::git_delta::DeltaError;
+use crate::intra_line::{Granularity, IntraLineChange};
 use crate::lines::*;
-use crate::DiffFormat;
 use crate::MultiListIter;
+use crate::{
+    requoted_path, unquoted_path, ApplyOffset, DiffFormat, ALT_TIMESTAMP_RE_STR, TIMESTAMP_RE_STR,
+};
 
 // TODO: implement Error for DiffParseError
 #[derive(Debug)]
@@ -29,12 +38,47 @@ pub enum DiffParseError {
     ZLibInflateError(String),
     GitDeltaError(DeltaError),
     IOError(io::Error),
+    // the hunk (by index) doesn't match the lines it's applied to
+    SourceMismatch(usize),
+    // the later diff's hunk (by index) contradicts the earlier diff
+    DiffConflict(usize),
 }
 
 impl fmt::Display for DiffParseError {
-    // TODO: flesh out fmt::Display implementation for DiffParseError
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "bug the developer to fix this!")
+        use DiffParseError::*;
+        match self {
+            MissingAfterFileData(index) => write!(
+                f,
+                "Line {}: file data line not followed by the other file's data.",
+                index + 1
+            ),
+            ParseNumberError(error, line_num) => write!(f, "Line {line_num}: {error}."),
+            UnexpectedEndOfInput => write!(f, "Unexpected end of input."),
+            UnexpectedEndHunk(format, index) => write!(
+                f,
+                "Line {}: unexpected end of {format:?} diff hunk.",
+                index + 1
+            ),
+            UnexpectedInput(format, msg) => write!(f, "{format:?} diff: {msg}."),
+            SyntaxError(format, line_num) => {
+                write!(f, "Line {line_num}: {format:?} diff syntax error.")
+            }
+            Base85Error(msg) => write!(f, "Base85 decoding error: {msg}."),
+            ZLibInflateError(msg) => write!(f, "Zlib inflation error: {msg}."),
+            GitDeltaError(error) => write!(f, "Git delta error: {error:?}."),
+            IOError(error) => write!(f, "I/O error: {error}."),
+            SourceMismatch(index) => write!(
+                f,
+                "Hunk #{}: does not match the contents of the file it applies to.",
+                index + 1
+            ),
+            DiffConflict(index) => write!(
+                f,
+                "Hunk #{} of the later diff: contradicts the earlier diff.",
+                index + 1
+            ),
+        }
     }
 }
 
@@ -51,19 +95,205 @@ impl Error for DiffParseError {
 
 pub type DiffParseResult<T> = Result<T, DiffParseError>;
 
+#[derive(Debug, PartialEq)]
+pub enum DiffBuildError {
+    InvalidPath(String),
+    InvalidMode(u32),
+    InvalidPercentage(u8),
+    InvalidTimestamp(String),
+    InvalidHash(String),
+    InvalidOptions(String),
+    HunkOutOfOrder(usize),
+    PathMismatch(String, String),
+    Conflict(String),
+    MissingDiff,
+    Unparsable(String),
+}
+
+impl fmt::Display for DiffBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use DiffBuildError::*;
+        match self {
+            InvalidPath(path) => write!(f, "\"{path}\": invalid file path."),
+            InvalidMode(mode) => write!(f, "{mode:o}: invalid file mode."),
+            InvalidPercentage(percentage) => write!(f, "{percentage}%: invalid percentage."),
+            InvalidTimestamp(time_stamp) => write!(f, "\"{time_stamp}\": invalid timestamp."),
+            InvalidHash(hash) => write!(f, "\"{hash}\": invalid object hash."),
+            InvalidOptions(options) => write!(f, "\"{options}\": invalid diff options."),
+            HunkOutOfOrder(index) => write!(
+                f,
+                "Hunk #{}: out of order or overlaps the previous hunk.",
+                index + 1
+            ),
+            PathMismatch(expected, found) => {
+                write!(f, "\"{found}\": does not match \"{expected}\".")
+            }
+            Conflict(what) => write!(f, "{what}."),
+            MissingDiff => write!(f, "No diff or git preamble supplied."),
+            Unparsable(why) => write!(f, "Built text does not parse: {why}"),
+        }
+    }
+}
+
+impl Error for DiffBuildError {}
+
+pub type DiffBuildResult<T> = Result<T, DiffBuildError>;
+
+lazy_static! {
+    static ref TIMESTAMP_CRE: Regex =
+        Regex::new(&format!("^({TIMESTAMP_RE_STR}|{ALT_TIMESTAMP_RE_STR})$")).unwrap();
+}
+
+// Builders render what they build and parse the result so that it's
+// identical to one read from a file and anything that the parser would
+// reject (or read differently) is an error rather than a surprise later
+pub(crate) fn parse_rendered<T>(
+    lines: &[Line],
+    parse: impl FnOnce(&[Line]) -> DiffParseResult<Option<T>>,
+) -> DiffBuildResult<T> {
+    match parse(lines) {
+        Ok(Some(parsed)) => Ok(parsed),
+        Ok(None) => Err(DiffBuildError::Unparsable(format!(
+            "\"{}\": not recognised.",
+            lines.first().map_or("", |line| line.trim_end())
+        ))),
+        Err(error) => Err(DiffBuildError::Unparsable(error.to_string())),
+    }
+}
+
+// As requoted_path() but rejecting paths that can't be parsed back
+// (NB: trailing white space would be taken as a separator)
+pub(crate) fn quoted_path(file_path: &str) -> DiffBuildResult<String> {
+    if file_path.is_empty() || file_path.ends_with(char::is_whitespace) {
+        Err(DiffBuildError::InvalidPath(file_path.to_string()))
+    } else {
+        Ok(requoted_path(file_path))
+    }
+}
+
+// The "---", "+++" or "***" line text for an already quoted path.  Like
+// git, add a tab after paths containing spaces when there's no timestamp
+// to show where the path ends.
+fn file_header_line_text(prefix: &str, file_path: &str, time_stamp: Option<&str>) -> String {
+    if let Some(time_stamp) = time_stamp {
+        format!("{prefix} {file_path}\t{time_stamp}\n")
+    } else if file_path.contains(' ') {
+        format!("{prefix} {file_path}\t\n")
+    } else {
+        format!("{prefix} {file_path}\n")
+    }
+}
+
+// A "---", "+++" or "***" file header line for a text diff
+pub(crate) fn file_header_line(
+    prefix: &str,
+    file_path: &str,
+    time_stamp: Option<&str>,
+) -> DiffBuildResult<Line> {
+    let file_path = quoted_path(file_path)?;
+    if let Some(time_stamp) = time_stamp {
+        if !TIMESTAMP_CRE.is_match(time_stamp) {
+            return Err(DiffBuildError::InvalidTimestamp(time_stamp.to_string()));
+        }
+    }
+    Ok(Line::new(file_header_line_text(
+        prefix, &file_path, time_stamp,
+    )))
+}
+
+// Hunks must be in file order and must not overlap
+pub(crate) fn check_hunk_order(hunks: &[AbstractHunk]) -> DiffBuildResult<()> {
+    for (index, pair) in hunks.windows(2).enumerate() {
+        if pair[1].ante_chunk().start_index < pair[0].ante_chunk().end_index()
+            || pair[1].post_chunk().start_index < pair[0].post_chunk().end_index()
+        {
+            return Err(DiffBuildError::HunkOutOfOrder(index + 1));
+        }
+    }
+    Ok(())
+}
+
+// The file paths for the reverse of a diff from "ante" to "post" keeping
+// git's "a/" and "b/" prefixes (if any) where they are
+pub(crate) fn reversed_file_paths(ante: &str, post: &str) -> (String, String) {
+    match (ante.strip_prefix("a/"), post.strip_prefix("b/")) {
+        (Some(ante_body), Some(post_body)) => (format!("a/{post_body}"), format!("b/{ante_body}")),
+        (None, Some(post_body)) if ante == "/dev/null" => {
+            (format!("a/{post_body}"), ante.to_string())
+        }
+        (Some(ante_body), None) if post == "/dev/null" => {
+            (post.to_string(), format!("b/{ante_body}"))
+        }
+        _ => (post.to_string(), ante.to_string()),
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct PathAndTimestamp {
     file_path: String,
     time_stamp: Option<String>,
 }
 
-#[derive(Debug)]
+impl PathAndTimestamp {
+    pub fn new(file_path: &str, time_stamp: Option<&str>) -> Self {
+        PathAndTimestamp {
+            file_path: file_path.to_string(),
+            time_stamp: time_stamp.map(|ts| ts.to_string()),
+        }
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    pub fn time_stamp(&self) -> Option<&str> {
+        self.time_stamp.as_deref()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct TextDiffHeader {
     pub lines: Lines,
     pub ante_pat: PathAndTimestamp,
     pub post_pat: PathAndTimestamp,
 }
 
+impl TextDiffHeader {
+    // NB: the lines keep their "---"/"+++" (or "***"/"---") prefixes
+    fn reversed(&self) -> Self {
+        let (ante_file_path, post_file_path) =
+            reversed_file_paths(&self.ante_pat.file_path, &self.post_pat.file_path);
+        let ante_pat = PathAndTimestamp::new(&ante_file_path, self.post_pat.time_stamp());
+        let post_pat = PathAndTimestamp::new(&post_file_path, self.ante_pat.time_stamp());
+        let lines = self
+            .lines
+            .iter()
+            .zip([&ante_pat, &post_pat].iter())
+            .map(|(line, pat)| {
+                let prefix = line.split(' ').next().unwrap_or_default();
+                let file_path = requoted_path(pat.file_path());
+                Line::new(file_header_line_text(prefix, &file_path, pat.time_stamp()))
+            })
+            .collect();
+        TextDiffHeader {
+            lines,
+            ante_pat,
+            post_pat,
+        }
+    }
+
+    // The header for a diff from this header's ante file to later's post file
+    fn squashed(&self, later: &TextDiffHeader) -> Self {
+        let prefix = self.lines[1].split(' ').next().unwrap_or_default();
+        let (_, rest) = later.lines[1].split_once(' ').unwrap_or_default();
+        TextDiffHeader {
+            lines: vec![self.lines[0].clone(), Line::new(format!("{prefix} {rest}"))],
+            ante_pat: self.ante_pat.clone(),
+            post_pat: later.post_pat.clone(),
+        }
+    }
+}
+
 pub trait TextDiffHunk {
     fn len(&self) -> usize;
     fn iter(&self) -> Iter<Line>;
@@ -75,11 +305,251 @@ pub trait TextDiffHunk {
 
     fn get_abstract_diff_hunk(&self) -> AbstractHunk;
 
+    // The changed parts of each pair of corresponding removed/added lines
+    fn intra_line_changes(&self, granularity: Granularity) -> Vec<IntraLineChange>;
+
+    // The numbers of inserted, deleted, modified and unchanged lines
+    fn diff_stats(&self) -> DiffStats;
+
+    // The (start line number, length) pairs claimed by the hunk's header(s)
+    fn ante_header_range(&self) -> (usize, usize);
+    fn post_header_range(&self) -> (usize, usize);
+
+    // A copy of the hunk keeping only the changes on the given lines
+    // (indices into the hunk's lines) with unselected removals turned
+    // into context and unselected additions dropped (like "git add -p").
+    // Returns None if no changes are selected. NB: the post chunk's start
+    // is left for TextDiff::recount() to fix.
+    fn select_lines(&self, line_indices: &[usize]) -> Option<Self>
+    where
+        Self: Sized;
+
+    // Split the hunk into one hunk per group of changes (like "git add -p"'s
+    // "s" command) with the context between the groups shared out so that
+    // the pieces don't overlap
+    fn split(&self) -> Vec<Self>
+    where
+        Self: Sized;
+
+    // Join the hunk with the following hunk "other" if their contexts
+    // overlap or abut (i.e. undo split()) returning None if they don't
+    fn merge_adjacent(&self, other: &Self) -> Option<Self>
+    where
+        Self: Sized;
+
+    // The hunk that undoes this hunk's changes
+    fn reversed(&self) -> Self
+    where
+        Self: Sized;
+
+    // Rewrite the header(s) so that the lengths match the hunk's lines
+    // and the post chunk starts at "post_start_line_num"
+    fn recount(&mut self, post_start_line_num: usize);
+
+    // Bring any data derived from the hunk's lines up to date after
+    // they have been edited
+    fn resync(&mut self) {}
+
+    // The text (if any) following the ranges in the hunk's header
+    fn heading(&self) -> Option<&str> {
+        None
+    }
+
+    // Replace the hunk's heading (a no op for formats without headings)
+    fn set_heading(&mut self, _heading: Option<&str>) {}
+
     fn is_empty(&self) -> bool {
         self.len() == 0
     }
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HunkDiagnostic {
+    AnteLengthMismatch {
+        hunk_index: usize,
+        header: usize,
+        actual: usize,
+    },
+    PostLengthMismatch {
+        hunk_index: usize,
+        header: usize,
+        actual: usize,
+    },
+    AnteStartMismatch {
+        hunk_index: usize,
+        header: usize,
+        expected: usize,
+    },
+    PostStartMismatch {
+        hunk_index: usize,
+        header: usize,
+        expected: usize,
+    },
+    OverlapsPrevious {
+        hunk_index: usize,
+    },
+}
+
+impl fmt::Display for HunkDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use HunkDiagnostic::*;
+        match self {
+            AnteLengthMismatch {
+                hunk_index,
+                header,
+                actual,
+            } => write!(
+                f,
+                "Hunk #{}: header claims {} ante lines but there are {}.",
+                hunk_index + 1,
+                header,
+                actual
+            ),
+            PostLengthMismatch {
+                hunk_index,
+                header,
+                actual,
+            } => write!(
+                f,
+                "Hunk #{}: header claims {} post lines but there are {}.",
+                hunk_index + 1,
+                header,
+                actual
+            ),
+            AnteStartMismatch {
+                hunk_index,
+                header,
+                expected,
+            } => write!(
+                f,
+                "Hunk #{}: ante chunk starts at line {} but should start at line {}.",
+                hunk_index + 1,
+                header,
+                expected
+            ),
+            PostStartMismatch {
+                hunk_index,
+                header,
+                expected,
+            } => write!(
+                f,
+                "Hunk #{}: post chunk starts at line {} but should start at line {}.",
+                hunk_index + 1,
+                header,
+                expected
+            ),
+            OverlapsPrevious { hunk_index } => {
+                write!(f, "Hunk #{}: overlaps the previous hunk.", hunk_index + 1)
+            }
+        }
+    }
+}
+
+// Convert between (header) start line numbers and 0 based indices
+// noting that empty chunks are identified by the preceding line.  NB: a
+// hand edited header may give 0 as the start of a non empty chunk.
+pub(crate) fn start_index(start_line_num: usize, length: usize) -> usize {
+    if length > 0 {
+        start_line_num.saturating_sub(1)
+    } else {
+        start_line_num
+    }
+}
+
+pub(crate) fn start_line_num(start_index: usize, length: usize) -> usize {
+    if length > 0 {
+        start_index + 1
+    } else {
+        start_index
+    }
+}
+
+// The statistics for a hunk's ops (' ', '-' or '+' in unified order)
+// with the deletions and insertions in each run of changes counted as
+// modifications as far as they pair up (like "diffstat -m") so that
+// unified and context diffs of the same changes agree
+pub(crate) fn ops_diff_stats<I: Iterator<Item = char>>(ops: I) -> DiffStats {
+    let mut stats = DiffStats::default();
+    let (mut deleted, mut inserted) = (0, 0);
+    let flush = |stats: &mut DiffStats, deleted: &mut u64, inserted: &mut u64| {
+        let modified = (*deleted).min(*inserted);
+        stats.incr_count(DiffStatsCategory::Modified, modified);
+        stats.incr_count(DiffStatsCategory::Deleted, *deleted - modified);
+        stats.incr_count(DiffStatsCategory::Inserted, *inserted - modified);
+        (*deleted, *inserted) = (0, 0);
+    };
+    for op in ops {
+        match op {
+            '-' => deleted += 1,
+            '+' => inserted += 1,
+            _ => {
+                flush(&mut stats, &mut deleted, &mut inserted);
+                stats.incr_count(DiffStatsCategory::Unchanged, 1);
+            }
+        }
+    }
+    flush(&mut stats, &mut deleted, &mut inserted);
+    stats
+}
+
+pub(crate) fn patch_id_hasher() -> Hasher {
+    Hasher::new(crypto_hash::Algorithm::SHA1)
+}
+
+// Write "line" less its white space (as "git patch-id" does) to "hasher"
+pub(crate) fn write_patch_id_line(hasher: &mut Hasher, line: &str) {
+    let bytes: Vec<u8> = line
+        .bytes()
+        .filter(|byte| !matches!(byte, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r'))
+        .collect();
+    hasher.write_all(&bytes).expect("hasher blew up!!!");
+}
+
+pub(crate) fn write_patch_id_ops(hasher: &mut Hasher, ops: &[HunkOp]) {
+    for (prefix, text) in ops.iter() {
+        write_patch_id_line(hasher, &format!("{prefix}{text}"));
+    }
+}
+
+// The patch id of a set of patch ids is their sum (with carry and the
+// least significant byte first) so that it is independent of their order
+pub(crate) fn add_patch_id(sum: &mut [u8], patch_id: &[u8]) {
+    let mut carry = 0u16;
+    for (byte, patch_id_byte) in sum.iter_mut().zip(patch_id.iter()) {
+        carry += *byte as u16 + *patch_id_byte as u16;
+        *byte = carry as u8;
+        carry >>= 8;
+    }
+}
+
+// The hunks (by index) of a diff to be kept and, optionally, which of
+// their changed lines (by index into the hunk's lines) to keep
+#[derive(Debug, Clone, Default)]
+pub struct HunkSelection {
+    hunks: BTreeMap<usize, Option<Vec<usize>>>,
+}
+
+impl HunkSelection {
+    pub fn new() -> Self {
+        HunkSelection::default()
+    }
+
+    pub fn hunk(mut self, hunk_index: usize) -> Self {
+        self.hunks.insert(hunk_index, None);
+        self
+    }
+
+    pub fn lines(mut self, hunk_index: usize, line_indices: &[usize]) -> Self {
+        self.hunks.insert(hunk_index, Some(line_indices.to_vec()));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hunks.is_empty()
+    }
+}
+
+#[derive(Clone)]
 pub struct TextDiff<H: TextDiffHunk> {
     lines_consumed: usize, // time saver
     header: TextDiffHeader,
@@ -90,6 +560,15 @@ impl<H> TextDiff<H>
 where
     H: TextDiffHunk,
 {
+    pub fn new(header: TextDiffHeader, hunks: Vec<H>) -> Self {
+        let lines_consumed = header.lines.len() + hunks.iter().map(|h| h.len()).sum::<usize>();
+        TextDiff {
+            lines_consumed,
+            header,
+            hunks,
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.lines_consumed
     }
@@ -143,6 +622,31 @@ where
         }
     }
 
+    // The number of the line in the post file that was line "ante_line_num"
+    // of the ante file or None if the diff deletes (or replaces) it (or
+    // there's no such line)
+    pub fn post_line_num(&self, ante_line_num: usize) -> Option<usize> {
+        self.abstract_diff()
+            .mapped_index(ante_line_num.checked_sub(1)?, false)
+            .map(|index| index + 1)
+    }
+
+    // The number of the line in the ante file that became line
+    // "post_line_num" of the post file or None if the diff added it
+    pub fn ante_line_num(&self, post_line_num: usize) -> Option<usize> {
+        self.abstract_diff()
+            .mapped_index(post_line_num.checked_sub(1)?, true)
+            .map(|index| index + 1)
+    }
+
+    pub fn diff_stats(&self) -> DiffStats {
+        let mut stats = DiffStats::default();
+        for hunk in self.hunks.iter() {
+            stats += hunk.diff_stats();
+        }
+        stats
+    }
+
     pub fn adds_trailing_white_space(&self) -> bool {
         for hunk in self.hunks.iter() {
             if hunk.adds_trailing_white_space() {
@@ -162,13 +666,8 @@ where
     where
         W: io::Write,
     {
-        let hunks = self
-            .hunks
-            .iter()
-            .map(|h| h.get_abstract_diff_hunk())
-            .collect();
-        let abstract_diff = AbstractDiff::new(hunks);
-        abstract_diff.apply_to_lines(lines, reverse, err_w, repd_file_path)
+        self.abstract_diff()
+            .apply_to_lines(lines, reverse, err_w, repd_file_path)
     }
 
     pub fn apply_to_contents<R, W>(
@@ -185,6 +684,343 @@ where
         let lines = Lines::read(reader).map_err(DiffParseError::IOError)?;
         Ok(self.apply_to_lines(&lines, reverse, err_w, repd_file_path))
     }
+
+    // Check that the hunks' headers match their contents and each other
+    pub fn validate(&self) -> Vec<HunkDiagnostic> {
+        let mut diagnostics = vec![];
+        let mut offset: i64 = 0;
+        let mut ante_end_index = 0;
+        for (hunk_index, hunk) in self.hunks.iter().enumerate() {
+            let (ante_start, ante_header_len) = hunk.ante_header_range();
+            let (post_start, post_header_len) = hunk.post_header_range();
+            let ante_len = hunk.ante_lines().len();
+            let post_len = hunk.post_lines().len();
+            if ante_header_len != ante_len {
+                diagnostics.push(HunkDiagnostic::AnteLengthMismatch {
+                    hunk_index,
+                    header: ante_header_len,
+                    actual: ante_len,
+                });
+            }
+            if post_header_len != post_len {
+                diagnostics.push(HunkDiagnostic::PostLengthMismatch {
+                    hunk_index,
+                    header: post_header_len,
+                    actual: post_len,
+                });
+            }
+            if ante_start == 0 && ante_len > 0 {
+                diagnostics.push(HunkDiagnostic::AnteStartMismatch {
+                    hunk_index,
+                    header: ante_start,
+                    expected: 1,
+                });
+            }
+            let ante_index = start_index(ante_start, ante_len);
+            if ante_index < ante_end_index {
+                diagnostics.push(HunkDiagnostic::OverlapsPrevious { hunk_index });
+            }
+            let expected = start_line_num(ante_index.apply_offset(offset), post_len);
+            if post_start != expected {
+                diagnostics.push(HunkDiagnostic::PostStartMismatch {
+                    hunk_index,
+                    header: post_start,
+                    expected,
+                });
+            }
+            offset += post_len as i64 - ante_len as i64;
+            ante_end_index = ante_index + ante_len;
+        }
+        diagnostics
+    }
+
+    // Rewrite the hunks' headers to match their contents (like recountdiff)
+    pub fn recount(&mut self) {
+        let mut offset: i64 = 0;
+        for hunk in self.hunks.iter_mut() {
+            let ante_len = hunk.ante_lines().len();
+            let post_len = hunk.post_lines().len();
+            let ante_index = start_index(hunk.ante_header_range().0, ante_len);
+            hunk.recount(start_line_num(ante_index.apply_offset(offset), post_len));
+            offset += post_len as i64 - ante_len as i64;
+        }
+        self.lines_consumed =
+            self.header.lines.len() + self.hunks.iter().map(|h| h.len()).sum::<usize>();
+    }
+
+    // Add the hunk in ante file order recounting the headers to suit and
+    // return its index
+    pub fn insert_hunk(&mut self, hunk: H) -> usize {
+        let hunk_start_index = |hunk: &H| {
+            let (start_line_num, length) = hunk.ante_header_range();
+            start_index(start_line_num, length)
+        };
+        let new_start_index = hunk_start_index(&hunk);
+        let index = self
+            .hunks
+            .partition_point(|h| hunk_start_index(h) <= new_start_index);
+        self.hunks.insert(index, hunk);
+        self.recount();
+        index
+    }
+
+    pub fn remove_hunk(&mut self, index: usize) -> H {
+        let hunk = self.hunks.remove(index);
+        self.recount();
+        hunk
+    }
+
+    // Modify the hunk's lines and have the headers recounted afterwards
+    pub fn edit_hunk<F: FnOnce(&mut H)>(&mut self, index: usize, edit: F) {
+        edit(&mut self.hunks[index]);
+        self.hunks[index].resync();
+        self.recount();
+    }
+
+    // Replace the hunk with the hunks it splits into and return how many
+    pub fn split_hunk(&mut self, index: usize) -> usize {
+        let pieces = self.hunks[index].split();
+        let count = pieces.len();
+        self.hunks.splice(index..=index, pieces);
+        self.recount();
+        count
+    }
+
+    // Merge any consecutive hunks whose contexts overlap or abut
+    pub fn merge_adjacent_hunks(&mut self) {
+        let mut hunks: Vec<H> = vec![];
+        for hunk in self.hunks.drain(..) {
+            if let Some(last) = hunks.last_mut() {
+                if let Some(merged) = last.merge_adjacent(&hunk) {
+                    *last = merged;
+                    continue;
+                }
+            }
+            hunks.push(hunk);
+        }
+        self.hunks = hunks;
+        self.recount();
+    }
+
+    // The diff that undoes this diff's changes (like "interdiff"'s "flipdiff")
+    pub fn reversed(&self) -> Self {
+        let hunks = self.hunks.iter().map(|hunk| hunk.reversed()).collect();
+        TextDiff::new(self.header.reversed(), hunks)
+    }
+
+    pub(crate) fn abstract_diff(&self) -> AbstractDiff {
+        AbstractDiff::new(
+            self.hunks
+                .iter()
+                .map(|h| h.get_abstract_diff_hunk())
+                .collect(),
+        )
+    }
+}
+
+impl<H> TextDiff<H>
+where
+    H: TextDiffHunk + Clone,
+{
+    // A diff containing only the selected changes (with the headers of
+    // later hunks adjusted for those left out) or None if none are selected
+    pub fn selected(&self, selection: &HunkSelection) -> Option<Self> {
+        let hunks: Vec<H> = selection
+            .hunks
+            .iter()
+            .filter_map(|(&index, line_indices)| {
+                let hunk = self.hunks.get(index)?;
+                match line_indices {
+                    Some(line_indices) => hunk.select_lines(line_indices),
+                    None => Some(hunk.clone()),
+                }
+            })
+            .collect();
+        if hunks.is_empty() {
+            return None;
+        }
+        let mut diff = TextDiff::new(self.header.clone(), hunks);
+        diff.recount();
+        Some(diff)
+    }
+}
+
+impl<H> TextDiff<H>
+where
+    H: TextDiffHunk + for<'a> From<&'a AbstractHunk>,
+{
+    // The hunks of "abstract_diff" (a diff of the same ante file) with
+    // each taking the heading of the nearest of this diff's hunks
+    fn rebuilt_hunks(&self, abstract_diff: &AbstractDiff) -> Vec<H> {
+        let mut hunks: Vec<H> = abstract_diff.hunks().iter().map(H::from).collect();
+        if self.hunks.iter().all(|hunk| hunk.heading().is_none()) {
+            return hunks;
+        }
+        for hunk in hunks.iter_mut() {
+            let (start, length) = hunk.ante_header_range();
+            let nearest = self.hunks.iter().min_by_key(|old_hunk| {
+                let (old_start, old_length) = old_hunk.ante_header_range();
+                // NB: at most one of these is non zero
+                start.saturating_sub(old_start + old_length)
+                    + old_start.saturating_sub(start + length)
+            });
+            hunk.set_heading(nearest.and_then(|old_hunk| old_hunk.heading()));
+        }
+        hunks
+    }
+
+    fn rebuilt_from(&self, abstract_diff: &AbstractDiff) -> Self {
+        TextDiff::new(self.header.clone(), self.rebuilt_hunks(abstract_diff))
+    }
+
+    // Regenerate the hunks with "context_len" lines of context taken from
+    // "lines" (the contents of the file that the diff applies to).
+    pub fn with_context(&self, lines: &[Line], context_len: usize) -> DiffParseResult<Self> {
+        let abstract_diff = self
+            .abstract_diff()
+            .with_context(lines, context_len)
+            .map_err(DiffParseError::SourceMismatch)?;
+        Ok(self.rebuilt_from(&abstract_diff))
+    }
+
+    pub fn with_context_from_file(
+        &self,
+        file_path: &Path,
+        context_len: usize,
+    ) -> DiffParseResult<Self> {
+        let lines = Lines::read_from(file_path).map_err(DiffParseError::IOError)?;
+        self.with_context(&lines, context_len)
+    }
+
+    // Return a diff that makes the same changes to "lines" as this one
+    // does (when applied with offsets and/or fuzz) but whose hunks have
+    // correct line numbers and "context_len" lines of context (like
+    // quilt's refresh).  None is returned if any hunk fails to apply.
+    pub fn refreshed(&self, lines: &[Line], context_len: usize) -> Option<Self> {
+        let result = self
+            .abstract_diff()
+            .apply_to_lines(lines, false, &mut io::sink(), None);
+        if result.failures() > 0 {
+            return None;
+        }
+        let abstract_diff = AbstractDiff::from_lines(
+            lines,
+            result.lines(),
+            context_len,
+            &IndentHeuristic(Algorithm::default()),
+        );
+        Some(self.rebuilt_from(&abstract_diff))
+    }
+
+    // Return the diff equivalent to applying this diff and then "later"
+    // (a diff of the same file made on top of this one) with "context_len"
+    // lines of context worked out from the two diffs alone.  The index of
+    // the first of later's hunks that doesn't fit this diff's result is
+    // returned on failure.
+    pub fn squashed<L: TextDiffHunk>(
+        &self,
+        later: &TextDiff<L>,
+        context_len: usize,
+    ) -> DiffParseResult<Self> {
+        let abstract_diff = self
+            .abstract_diff()
+            .squashed(
+                &later.abstract_diff(),
+                context_len,
+                &IndentHeuristic(Algorithm::default()),
+            )
+            .map_err(DiffParseError::DiffConflict)?;
+        let hunks = self.rebuilt_hunks(&abstract_diff);
+        Ok(TextDiff::new(self.header.squashed(&later.header), hunks))
+    }
+
+    // Return the diffs (later', self') that make the same changes as
+    // applying this diff and then "later" but in the opposite order (see
+    // AbstractDiff::commuted()) or None if they don't commute.  The index
+    // of the first of later's hunks that doesn't fit this diff's result is
+    // returned on failure.
+    pub fn commuted<L>(
+        &self,
+        later: &TextDiff<L>,
+        context_len: usize,
+    ) -> DiffParseResult<Option<(TextDiff<L>, Self)>>
+    where
+        L: TextDiffHunk + for<'a> From<&'a AbstractHunk>,
+    {
+        let commuted = self
+            .abstract_diff()
+            .commuted(
+                &later.abstract_diff(),
+                context_len,
+                &IndentHeuristic(Algorithm::default()),
+            )
+            .map_err(DiffParseError::DiffConflict)?;
+        Ok(commuted.map(|(later_diff, diff)| {
+            let later_hunks = later_diff.hunks().iter().map(L::from).collect();
+            let hunks = diff.hunks().iter().map(H::from).collect();
+            (
+                TextDiff::new(later.header.clone(), later_hunks),
+                TextDiff::new(self.header.clone(), hunks),
+            )
+        }))
+    }
+
+    // As for squashed() but with the result of applying both diffs to
+    // "lines" (the contents of the file that this diff applies to) being
+    // rediffed so that diffs that don't fit exactly can be combined.
+    // None is returned if any hunk fails to apply.
+    pub fn squashed_using<L: TextDiffHunk>(
+        &self,
+        later: &TextDiff<L>,
+        lines: &[Line],
+        context_len: usize,
+    ) -> Option<Self> {
+        let mid = self
+            .abstract_diff()
+            .apply_to_lines(lines, false, &mut io::sink(), None);
+        if mid.failures() > 0 {
+            return None;
+        }
+        let post = later
+            .abstract_diff()
+            .apply_to_lines(mid.lines(), false, &mut io::sink(), None);
+        if post.failures() > 0 {
+            return None;
+        }
+        let abstract_diff = AbstractDiff::from_lines(
+            lines,
+            post.lines(),
+            context_len,
+            &IndentHeuristic(Algorithm::default()),
+        );
+        let hunks = self.rebuilt_hunks(&abstract_diff);
+        Some(TextDiff::new(self.header.squashed(&later.header), hunks))
+    }
+
+    pub fn refreshed_from_file(
+        &self,
+        file_path: &Path,
+        context_len: usize,
+    ) -> DiffParseResult<Option<Self>> {
+        let lines = Lines::read_from(file_path).map_err(DiffParseError::IOError)?;
+        Ok(self.refreshed(&lines, context_len))
+    }
+
+    // Expand the context of the hunk at "hunk_index" up and down by the
+    // given number of lines (merging it with any hunks it then overlaps).
+    pub fn with_expanded_hunk(
+        &self,
+        lines: &[Line],
+        hunk_index: usize,
+        extra_before: usize,
+        extra_after: usize,
+    ) -> DiffParseResult<Self> {
+        let abstract_diff = self
+            .abstract_diff()
+            .with_expanded_hunk(lines, hunk_index, extra_before, extra_after)
+            .map_err(DiffParseError::SourceMismatch)?;
+        Ok(self.rebuilt_from(&abstract_diff))
+    }
 }
 
 pub trait TextDiffParser<H: TextDiffHunk> {
@@ -195,13 +1031,15 @@ pub trait TextDiffParser<H: TextDiffHunk> {
 
     fn _get_file_data_fm_captures(&self, captures: &Captures) -> PathAndTimestamp {
         let file_path = if let Some(path) = captures.get(2) {
-            path.as_str()
+            unquoted_path(path.as_str())
         } else {
-            captures.get(3).unwrap().as_str() // TODO: confirm unwrap is OK here
+            captures.get(3).unwrap().as_str().to_string() // TODO: confirm unwrap is OK here
         };
-        let time_stamp = captures.get(4).map(|ts| ts.as_str().to_string());
+        let time_stamp = captures
+            .get(4)
+            .map(|ts| ts.as_str().trim_start().to_string());
         PathAndTimestamp {
-            file_path: file_path.to_string(),
+            file_path,
             time_stamp,
         }
     }
@@ -260,6 +1098,152 @@ pub trait TextDiffParser<H: TextDiffHunk> {
         };
         Ok(Some(diff))
     }
+
+    // Get the hunk at "index" taking its extent from its contents rather
+    // than the (possibly wrong e.g. hand edited) lengths in its header.
+    fn get_uncounted_hunk_at(&self, lines: &[Line], index: usize) -> DiffParseResult<Option<H>>;
+
+    // As for get_diff_at() but for diffs whose hunk headers may be wrong
+    // (use recount() to fix them).
+    fn get_uncounted_diff_at(
+        &self,
+        lines: &[Line],
+        start_index: usize,
+    ) -> DiffParseResult<Option<TextDiff<H>>> {
+        if lines.len() - start_index < 2 {
+            return Ok(None);
+        }
+        let mut index = start_index;
+        let header = if let Some(header) = self.get_text_diff_header_at(lines, index)? {
+            index += header.lines.len();
+            header
+        } else {
+            return Ok(None);
+        };
+        let mut hunks: Vec<H> = Vec::new();
+        while index < lines.len() {
+            if let Some(hunk) = self.get_uncounted_hunk_at(lines, index)? {
+                index += hunk.len();
+                hunks.push(hunk);
+            } else {
+                break;
+            }
+        }
+        let diff = TextDiff::<H> {
+            lines_consumed: index - start_index,
+            header,
+            hunks,
+        };
+        Ok(Some(diff))
+    }
+}
+
+// A hunk's lines in unified diff order (i.e. ' ', '-' or '+' paired with
+// the line's text) as used for splitting and merging hunks
+pub(crate) type HunkOp = (char, Line);
+
+// The numbers of ante and post lines before each piece of a split hunk
+// and the piece's ops: each group of changes with the context between
+// groups divided between the pieces (the earlier getting any odd line) so
+// that the pieces neither overlap nor leave gaps.
+pub(crate) fn split_ops(ops: &[HunkOp]) -> Vec<(usize, usize, &[HunkOp])> {
+    let mut groups: Vec<(usize, usize)> = vec![];
+    for (index, op) in ops.iter().enumerate() {
+        if op.0 == ' ' {
+            continue;
+        }
+        match groups.last_mut() {
+            Some(group) if group.1 == index => group.1 = index + 1,
+            _ => groups.push((index, index + 1)),
+        }
+    }
+    let bounds: Vec<usize> = groups
+        .windows(2)
+        .map(|pair| pair[0].1 + (pair[1].0 - pair[0].1).div_ceil(2))
+        .collect();
+    let starts = iter::once(0).chain(bounds.iter().cloned());
+    let ends = bounds.iter().cloned().chain(iter::once(ops.len()));
+    let mut pieces = vec![];
+    for (start, end) in starts.zip(ends) {
+        let ante_before = ops[..start].iter().filter(|op| op.0 != '+').count();
+        let post_before = ops[..start].iter().filter(|op| op.0 != '-').count();
+        pieces.push((ante_before, post_before, &ops[start..end]));
+    }
+    pieces
+}
+
+// Join the ops of two hunks whose ante chunks start at the given (0 based)
+// indices provided that the second starts within or at the end of the
+// first and any overlap is context common to both.
+pub(crate) fn merged_ops(
+    first_start: usize,
+    first: &[HunkOp],
+    second_start: usize,
+    second: &[HunkOp],
+) -> Option<Vec<HunkOp>> {
+    let first_end = first_start + first.iter().filter(|op| op.0 != '+').count();
+    if second_start < first_start {
+        return None;
+    }
+    let overlap = first_end.checked_sub(second_start)?;
+    if overlap > first.len() || overlap > second.len() {
+        return None;
+    }
+    let tail = &first[first.len() - overlap..];
+    let head = &second[..overlap];
+    if tail
+        .iter()
+        .zip(head.iter())
+        .all(|(a, b)| a.0 == ' ' && b.0 == ' ' && a.1 == b.1)
+    {
+        Some(
+            first
+                .iter()
+                .chain(second[overlap..].iter())
+                .cloned()
+                .collect(),
+        )
+    } else {
+        None
+    }
+}
+
+// The ops with an unchanged last line that lacks a newline (as left by
+// deselecting its deletion) deleted and reinserted with a newline when
+// insertions follow it as they'd otherwise be joined onto it
+pub(crate) fn terminated_last_line_ops(ops: Vec<HunkOp>) -> Vec<HunkOp> {
+    let len = ops.len();
+    let mut terminated = Vec::with_capacity(len + 1);
+    for (index, (prefix, text)) in ops.into_iter().enumerate() {
+        if prefix == ' ' && !text.ends_with('\n') && index + 1 < len {
+            terminated.push(('-', text.clone()));
+            terminated.push(('+', Line::new(format!("{text}\n"))));
+        } else {
+            terminated.push((prefix, text));
+        }
+    }
+    terminated
+}
+
+// The ops for undoing the given ops with the removals in each group of
+// changes placed before the additions (as diff would have them)
+pub(crate) fn reversed_ops(ops: &[HunkOp]) -> Vec<HunkOp> {
+    let mut reversed = vec![];
+    for group in ops.split_inclusive(|op| op.0 == ' ') {
+        for prefix in ['+', '-', ' '].iter() {
+            reversed.extend(
+                group
+                    .iter()
+                    .filter(|op| op.0 == *prefix)
+                    .map(|op| match op.0 {
+                        '+' => ('-', op.1.clone()),
+                        '-' => ('+', op.1.clone()),
+                        _ => op.clone(),
+                    }),
+            );
+        }
+    }
+    reversed
 }
 
 pub fn extract_source_lines<F: Fn(&Line) -> bool>(
@@ -289,6 +1273,8 @@ mod tests {
     use regex::{Captures, Regex};
 
     use crate::abstract_diff::AbstractChunk;
+    use crate::context_diff::ContextDiffParser;
+    use crate::unified_diff::UnifiedDiffParser;
     use crate::{ALT_TIMESTAMP_RE_STR, PATH_RE_STR, TIMESTAMP_RE_STR};
 
     #[derive(Debug)]
@@ -333,6 +1319,42 @@ mod tests {
         fn adds_trailing_white_space(&self) -> bool {
             false
         }
+
+        fn intra_line_changes(&self, _granularity: Granularity) -> Vec<IntraLineChange> {
+            vec![]
+        }
+
+        fn diff_stats(&self) -> DiffStats {
+            DiffStats::default()
+        }
+
+        fn ante_header_range(&self) -> (usize, usize) {
+            (0, 0)
+        }
+
+        fn post_header_range(&self) -> (usize, usize) {
+            (0, 0)
+        }
+
+        fn select_lines(&self, _line_indices: &[usize]) -> Option<Self> {
+            None
+        }
+
+        fn split(&self) -> Vec<Self> {
+            vec![]
+        }
+
+        fn merge_adjacent(&self, _other: &Self) -> Option<Self> {
+            None
+        }
+
+        fn reversed(&self) -> Self {
+            DummyDiffHunk {
+                lines: self.lines.clone(),
+            }
+        }
+
+        fn recount(&mut self, _post_start_line_num: usize) {}
     }
 
     impl TextDiffParser<DummyDiffHunk> for DummyDiffParser {
@@ -364,6 +1386,14 @@ mod tests {
         ) -> DiffParseResult<Option<DummyDiffHunk>> {
             Ok(None)
         }
+
+        fn get_uncounted_hunk_at(
+            &self,
+            _lines: &[Line],
+            _index: usize,
+        ) -> DiffParseResult<Option<DummyDiffHunk>> {
+            Ok(None)
+        }
     }
 
     #[test]
@@ -388,4 +1418,87 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn paths_are_quoted_like_git() {
+        for (path, quoted) in [
+            ("a/old name", "a/old name"),
+            ("a/q\"x", "\"a/q\\\"x\""),
+            ("a/back\\slash", "\"a/back\\\\slash\""),
+            ("a/tab\there", "\"a/tab\\there\""),
+            ("a/t\u{e9}st", "\"a/t\\303\\251st\""),
+        ] {
+            assert_eq!(requoted_path(path), quoted);
+            let text = quoted
+                .strip_prefix('"')
+                .map_or(quoted, |text| text.strip_suffix('"').unwrap());
+            if text != quoted {
+                assert_eq!(unquoted_path(text), path);
+            }
+        }
+        assert!(quoted_path("").is_err());
+        assert!(quoted_path("a/trailing ").is_err());
+
+        let lines = vec![
+            file_header_line("---", "a/old name", None).unwrap(),
+            file_header_line("+++", "b/t\u{e9}st", None).unwrap(),
+        ];
+        assert_eq!(*lines[0], "--- a/old name\t\n");
+        assert_eq!(*lines[1], "+++ \"b/t\\303\\251st\"\n");
+        let header = UnifiedDiffParser::new()
+            .get_text_diff_header_at(&lines, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.ante_pat.file_path(), "a/old name");
+        assert_eq!(header.post_pat.file_path(), "b/t\u{e9}st");
+    }
+
+    #[test]
+    fn only_paths_ending_in_a_tab_have_spaces() {
+        let lines = Lines::from_string(
+            "--- lao 2002-02-21 23:30:39.942229878 -0800
++++ a/new name\t2002-02-21 23:30:50.442260588 -0800
+",
+        );
+        let header = UnifiedDiffParser::new()
+            .get_text_diff_header_at(&lines, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.ante_pat.file_path(), "lao");
+        assert_eq!(
+            header.ante_pat.time_stamp(),
+            Some("2002-02-21 23:30:39.942229878 -0800")
+        );
+        assert_eq!(header.post_pat.file_path(), "a/new name");
+        assert_eq!(
+            header.post_pat.time_stamp(),
+            Some("2002-02-21 23:30:50.442260588 -0800")
+        );
+
+        let lines = Lines::from_string("*** NOTE THIS ***\n--- a/name\n");
+        assert!(ContextDiffParser::new()
+            .get_text_diff_header_at(&lines, 0)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn unparsable_builds_are_errors() {
+        let parse = |lines: &[Line]| UnifiedDiffParser::new().get_diff_at(lines, 0);
+        let lines = Lines::from_string("--- a/name\n+++ b/name\n@@ -1,2 +1,1 @@\n-one\n two\n");
+        assert_eq!(parse_rendered(&lines, parse).unwrap().hunks().len(), 1);
+
+        let lines = Lines::from_string("--- a/name\n+++ b/name\n@@ -1,2 +1,1 @@\n-one\n");
+        assert!(matches!(
+            parse_rendered(&lines, parse),
+            Err(DiffBuildError::Unparsable(_))
+        ));
+        let lines = Lines::from_string("+++ b/name\n");
+        assert_eq!(
+            parse_rendered(&lines, parse).err(),
+            Some(DiffBuildError::Unparsable(
+                "\"+++ b/name\": not recognised.".to_string()
+            ))
+        );
+    }
 }
@@ -4,13 +4,16 @@ use std::convert::From;
 use std::slice::Iter;
 use std::str::FromStr;
 
-use lcs::{DiffComponent, LcsTable};
 use regex::{Captures, Regex};
 
-use crate::abstract_diff::{AbstractChunk, AbstractHunk};
+use crate::abstract_diff::{AbstractChunk, AbstractDiff, AbstractHunk};
+use crate::diff_algorithm::{Algorithm, DiffAlgorithm, DiffComponent, IndentHeuristic};
+use crate::diff_stats::DiffStats;
+use crate::hunk_heading::HeadingMatcher;
+use crate::intra_line::{intra_line_changes, pair_runs, Granularity, IntraLineChange};
 use crate::lines::{Line, LineIfce, Lines};
 use crate::text_diff::*;
-use crate::{DiffFormat, ALT_TIMESTAMP_RE_STR, PATH_RE_STR, TIMESTAMP_RE_STR};
+use crate::{DiffFormat, ALT_TIMESTAMP_RE_STR, PATH_RE_STR, SPACED_PATH_RE_STR, TIMESTAMP_RE_STR};
 
 #[derive(Debug, Clone, Copy)]
 pub struct UnifiedDiffChunk {
@@ -20,14 +23,34 @@ pub struct UnifiedDiffChunk {
 
 impl From<&AbstractChunk> for UnifiedDiffChunk {
     fn from(abstract_chunk: &AbstractChunk) -> Self {
+        // An empty chunk is identified by the line that precedes it
+        let start_line_num = if abstract_chunk.lines.is_empty() {
+            abstract_chunk.start_index
+        } else {
+            abstract_chunk.start_index + 1
+        };
         UnifiedDiffChunk {
-            start_line_num: abstract_chunk.start_index + 1,
+            start_line_num,
             length: abstract_chunk.lines.len(),
         }
     }
 }
 
 impl UnifiedDiffChunk {
+    pub fn start_line_num(&self) -> usize {
+        self.start_line_num
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    // The 0 based index of the chunk's first line (or the following line
+    // if the chunk is empty)
+    fn start_index(&self) -> usize {
+        start_index(self.start_line_num, self.length)
+    }
+
     fn from_captures(
         captures: &Captures,
         line_num: usize,
@@ -56,10 +79,13 @@ impl UnifiedDiffChunk {
     }
 }
 
+#[derive(Clone)]
 pub struct UnifiedDiffHunk {
     pub lines: Lines,
     pub ante_chunk: UnifiedDiffChunk,
     pub post_chunk: UnifiedDiffChunk,
+    // the text following the "@@ ... @@" (if any)
+    pub heading: Option<String>,
 }
 
 pub type UnifiedDiff = TextDiff<UnifiedDiffHunk>;
@@ -111,11 +137,143 @@ impl TextDiffHunk for UnifiedDiffHunk {
             lines: ante_lines,
         };
         let post_chunk = AbstractChunk {
-            start_index: self.post_chunk.start_line_num - 1,
+            start_index: if !post_lines.is_empty() {
+                self.post_chunk.start_line_num - 1
+            } else {
+                self.post_chunk.start_line_num
+            },
             lines: post_lines,
         };
         AbstractHunk::new(ante_chunk, post_chunk)
     }
+
+    fn intra_line_changes(&self, granularity: Granularity) -> Vec<IntraLineChange> {
+        // pair each run of removed lines with the run of added lines that follows it
+        let mut pairs = vec![];
+        let mut removed = vec![];
+        let mut added = vec![];
+        for (index, line) in self.lines.iter().enumerate().skip(1) {
+            if line.starts_with('-') {
+                if !added.is_empty() {
+                    pair_runs(&mut pairs, &mut removed, &mut added);
+                }
+                removed.push(index);
+            } else if line.starts_with('+') {
+                added.push(index);
+            } else if !line.starts_with('\\') {
+                pair_runs(&mut pairs, &mut removed, &mut added);
+            }
+        }
+        pair_runs(&mut pairs, &mut removed, &mut added);
+        intra_line_changes(&self.lines, &pairs, 1, granularity)
+    }
+
+    fn diff_stats(&self) -> DiffStats {
+        ops_diff_stats(
+            self.lines
+                .iter()
+                .skip(1)
+                .filter_map(|line| line.chars().next())
+                .filter(|prefix| matches!(prefix, ' ' | '-' | '+')),
+        )
+    }
+
+    fn ante_header_range(&self) -> (usize, usize) {
+        (self.ante_chunk.start_line_num, self.ante_chunk.length)
+    }
+
+    fn post_header_range(&self) -> (usize, usize) {
+        (self.post_chunk.start_line_num, self.post_chunk.length)
+    }
+
+    fn select_lines(&self, line_indices: &[usize]) -> Option<Self> {
+        let mut has_changes = false;
+        let mut ops = vec![];
+        for (prefix, index) in self.indexed_ops() {
+            let is_selected = line_indices.contains(&index);
+            match prefix {
+                '-' if !is_selected => ops.push((' ', self.line_text(index))),
+                '+' if !is_selected => (),
+                _ => {
+                    has_changes |= prefix != ' ';
+                    ops.push((prefix, self.line_text(index)));
+                }
+            }
+        }
+        if has_changes {
+            Some(UnifiedDiffHunk::from_ops(
+                self.ante_chunk.start_index(),
+                self.post_chunk.start_index(),
+                self.heading.as_deref(),
+                &terminated_last_line_ops(ops),
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn split(&self) -> Vec<Self> {
+        let ops = self.ops();
+        let ante_start = self.ante_chunk.start_index();
+        let post_start = self.post_chunk.start_index();
+        split_ops(&ops)
+            .into_iter()
+            .map(|(ante_before, post_before, piece_ops)| {
+                UnifiedDiffHunk::from_ops(
+                    ante_start + ante_before,
+                    post_start + post_before,
+                    self.heading.as_deref(),
+                    piece_ops,
+                )
+            })
+            .collect()
+    }
+
+    fn merge_adjacent(&self, other: &Self) -> Option<Self> {
+        let ops = merged_ops(
+            self.ante_chunk.start_index(),
+            &self.ops(),
+            other.ante_chunk.start_index(),
+            &other.ops(),
+        )?;
+        Some(UnifiedDiffHunk::from_ops(
+            self.ante_chunk.start_index(),
+            self.post_chunk.start_index(),
+            self.heading.as_deref(),
+            &ops,
+        ))
+    }
+
+    fn reversed(&self) -> Self {
+        UnifiedDiffHunk::from_ops(
+            self.post_chunk.start_index(),
+            self.ante_chunk.start_index(),
+            self.heading.as_deref(),
+            &reversed_ops(&self.ops()),
+        )
+    }
+
+    fn recount(&mut self, post_start_line_num: usize) {
+        self.ante_chunk.length = self.ante_lines().len();
+        if self.ante_chunk.length > 0 {
+            self.ante_chunk.start_line_num = self.ante_chunk.start_line_num.max(1);
+        }
+        self.post_chunk = UnifiedDiffChunk {
+            start_line_num: post_start_line_num,
+            length: self.post_lines().len(),
+        };
+        self.lines[0] =
+            hunk_header_line(&self.ante_chunk, &self.post_chunk, self.heading.as_deref());
+    }
+
+    fn heading(&self) -> Option<&str> {
+        self.heading.as_deref()
+    }
+
+    fn set_heading(&mut self, heading: Option<&str>) {
+        self.heading = heading.map(|heading| heading.to_string());
+        self.lines[0] = hunk_header_line(&self.ante_chunk, &self.post_chunk, heading);
+    }
 }
 
 //@@ -l,s +l,s @@ optional section heading
@@ -167,38 +325,285 @@ fn hunk_header_line(
     Line::new(string)
 }
 
-// TODO: add "extra string" to abstract text content
-impl From<&AbstractHunk> for UnifiedDiffHunk {
-    fn from(abstract_hunk: &AbstractHunk) -> Self {
+fn push_hunk_line(lines: &mut Lines, prefix: char, line: &Line) {
+    if line.ends_with('\n') {
+        lines.push(Line::new(format!("{prefix}{line}")));
+    } else {
+        lines.push(Line::new(format!("{prefix}{line}\n")));
+        lines.push(Line::new("\\ No newline at end of file\n".to_string()));
+    }
+}
+
+impl UnifiedDiffHunk {
+    // A "git patch-id" style hash of the hunk's lines which ignores
+    // line numbers and white space
+    pub fn patch_id(&self) -> Vec<u8> {
+        let mut hasher = patch_id_hasher();
+        write_patch_id_ops(&mut hasher, &self.ops());
+        hasher.finish()
+    }
+
+    // The hunk's lines in unified diff order without their prefixes
+    pub(crate) fn ops(&self) -> Vec<HunkOp> {
+        self.indexed_ops()
+            .into_iter()
+            .map(|(prefix, index)| (prefix, self.line_text(index)))
+            .collect()
+    }
+
+    // The indices of the hunk's (non "\ No newline") lines paired with
+    // their prefixes
+    fn indexed_ops(&self) -> Vec<(char, usize)> {
+        (1..self.lines.len())
+            .filter(|&index| !self.lines[index].starts_with('\\'))
+            .map(|index| (self.lines[index].chars().next().unwrap_or(' '), index))
+            .collect()
+    }
+
+    // The text of the line at "index" without its prefix
+    fn line_text(&self, index: usize) -> Line {
+        let text = &self.lines[index][1.min(self.lines[index].len())..];
+        match self.lines.get(index + 1) {
+            Some(next_line) if next_line.starts_with('\\') => {
+                Line::new(text.strip_suffix('\n').unwrap_or(text).to_string())
+            }
+            _ => Line::new(text.to_string()),
+        }
+    }
+
+    fn from_ops(
+        ante_start_index: usize,
+        post_start_index: usize,
+        heading: Option<&str>,
+        ops: &[HunkOp],
+    ) -> Self {
+        let ante_length = ops.iter().filter(|op| op.0 != '+').count();
+        let post_length = ops.iter().filter(|op| op.0 != '-').count();
+        let ante_chunk = UnifiedDiffChunk {
+            start_line_num: start_line_num(ante_start_index, ante_length),
+            length: ante_length,
+        };
+        let post_chunk = UnifiedDiffChunk {
+            start_line_num: start_line_num(post_start_index, post_length),
+            length: post_length,
+        };
+        let mut lines = vec![hunk_header_line(&ante_chunk, &post_chunk, heading)];
+        for (prefix, line) in ops.iter() {
+            push_hunk_line(&mut lines, *prefix, line);
+        }
+        UnifiedDiffHunk {
+            lines,
+            ante_chunk,
+            post_chunk,
+            heading: heading.map(|heading| heading.to_string()),
+        }
+    }
+
+    pub fn from_abstract_hunk<A: DiffAlgorithm>(
+        abstract_hunk: &AbstractHunk,
+        algorithm: &A,
+        heading: Option<&str>,
+    ) -> Self {
         let abs_ante_chunk = abstract_hunk.ante_chunk();
         let ante_chunk = abs_ante_chunk.into();
         let abs_post_chunk = abstract_hunk.post_chunk();
         let post_chunk = abs_post_chunk.into();
 
         let mut lines = Vec::new();
-        lines.push(hunk_header_line(&ante_chunk, &post_chunk, None));
-        let lcs_table = LcsTable::new(&abs_ante_chunk.lines, &abs_post_chunk.lines);
-        for diff_component in lcs_table.diff() {
+        lines.push(hunk_header_line(&ante_chunk, &post_chunk, heading));
+        for diff_component in algorithm.diff_lines(&abs_ante_chunk.lines, &abs_post_chunk.lines) {
             match diff_component {
-                DiffComponent::Insertion(line) => lines.push(Line::new(format!("+{line}"))),
-                DiffComponent::Unchanged(line, _) => lines.push(Line::new(format!(" {line}"))),
-                DiffComponent::Deletion(line) => lines.push(Line::new(format!("-{line}"))),
+                DiffComponent::Insertion(index) => {
+                    push_hunk_line(&mut lines, '+', &abs_post_chunk.lines[index])
+                }
+                DiffComponent::Unchanged(index, _) => {
+                    push_hunk_line(&mut lines, ' ', &abs_ante_chunk.lines[index])
+                }
+                DiffComponent::Deletion(index) => {
+                    push_hunk_line(&mut lines, '-', &abs_ante_chunk.lines[index])
+                }
             }
         }
         UnifiedDiffHunk {
             lines,
             ante_chunk,
             post_chunk,
+            heading: heading.map(|heading| heading.to_string()),
         }
     }
 }
 
+impl From<&AbstractHunk> for UnifiedDiffHunk {
+    fn from(abstract_hunk: &AbstractHunk) -> Self {
+        UnifiedDiffHunk::from_abstract_hunk(
+            abstract_hunk,
+            &IndentHeuristic(Algorithm::default()),
+            None,
+        )
+    }
+}
+
+pub struct UnifiedDiffGenerator {
+    pub context_len: usize,
+    pub algorithm: Algorithm,
+    // Slide changed blocks to their most readable positions (like git)
+    pub indent_heuristic: bool,
+    // Used to find the hunk headings (none if None)
+    pub heading_matcher: Option<HeadingMatcher>,
+}
+
+impl Default for UnifiedDiffGenerator {
+    fn default() -> Self {
+        UnifiedDiffGenerator {
+            context_len: 3,
+            algorithm: Algorithm::default(),
+            indent_heuristic: true,
+            heading_matcher: None,
+        }
+    }
+}
+
+impl UnifiedDiffGenerator {
+    // Generate the unified diff that turns "ante_lines" into "post_lines".
+    // Returns None if there are no differences.
+    pub fn generate(
+        &self,
+        ante_file_path: &str,
+        post_file_path: &str,
+        ante_lines: &[Line],
+        post_lines: &[Line],
+    ) -> Option<UnifiedDiff> {
+        if self.indent_heuristic {
+            self.generate_using(
+                &IndentHeuristic(self.algorithm),
+                ante_file_path,
+                post_file_path,
+                ante_lines,
+                post_lines,
+            )
+        } else {
+            self.generate_using(
+                &self.algorithm,
+                ante_file_path,
+                post_file_path,
+                ante_lines,
+                post_lines,
+            )
+        }
+    }
+
+    fn generate_using<A: DiffAlgorithm>(
+        &self,
+        algorithm: &A,
+        ante_file_path: &str,
+        post_file_path: &str,
+        ante_lines: &[Line],
+        post_lines: &[Line],
+    ) -> Option<UnifiedDiff> {
+        let abstract_diff =
+            AbstractDiff::from_lines(ante_lines, post_lines, self.context_len, algorithm);
+        if abstract_diff.is_empty() {
+            return None;
+        }
+        let header = TextDiffHeader {
+            lines: vec![
+                Line::new(format!("--- {ante_file_path}\n")),
+                Line::new(format!("+++ {post_file_path}\n")),
+            ],
+            ante_pat: PathAndTimestamp::new(ante_file_path, None),
+            post_pat: PathAndTimestamp::new(post_file_path, None),
+        };
+        let hunks = abstract_diff
+            .hunks()
+            .iter()
+            .map(|hunk| {
+                let heading = self.heading_matcher.as_ref().and_then(|matcher| {
+                    matcher.find_heading(ante_lines, hunk.ante_chunk().start_index)
+                });
+                UnifiedDiffHunk::from_abstract_hunk(hunk, algorithm, heading.as_deref())
+            })
+            .collect();
+        Some(UnifiedDiff::new(header, hunks))
+    }
+}
+
+// Build a unified diff from its parts (checking that they make sense)
+pub struct UnifiedDiffBuilder {
+    ante_pat: PathAndTimestamp,
+    post_pat: PathAndTimestamp,
+    hunks: Vec<AbstractHunk>,
+}
+
+impl UnifiedDiffBuilder {
+    pub fn new(ante_file_path: &str, post_file_path: &str) -> Self {
+        UnifiedDiffBuilder {
+            ante_pat: PathAndTimestamp::new(ante_file_path, None),
+            post_pat: PathAndTimestamp::new(post_file_path, None),
+            hunks: vec![],
+        }
+    }
+
+    pub fn ante_time_stamp(mut self, time_stamp: &str) -> Self {
+        self.ante_pat = PathAndTimestamp::new(self.ante_pat.file_path(), Some(time_stamp));
+        self
+    }
+
+    pub fn post_time_stamp(mut self, time_stamp: &str) -> Self {
+        self.post_pat = PathAndTimestamp::new(self.post_pat.file_path(), Some(time_stamp));
+        self
+    }
+
+    pub fn hunk(mut self, hunk: AbstractHunk) -> Self {
+        self.hunks.push(hunk);
+        self
+    }
+
+    pub fn build(&self) -> DiffBuildResult<UnifiedDiff> {
+        let mut lines = vec![
+            file_header_line("---", self.ante_pat.file_path(), self.ante_pat.time_stamp())?,
+            file_header_line("+++", self.post_pat.file_path(), self.post_pat.time_stamp())?,
+        ];
+        check_hunk_order(&self.hunks)?;
+        for hunk in self.hunks.iter() {
+            lines.extend(UnifiedDiffHunk::from(hunk).lines);
+        }
+        parse_rendered(&lines, |lines| {
+            UnifiedDiffParser::new().get_diff_at(lines, 0)
+        })
+    }
+}
+
 pub struct UnifiedDiffParser {
     ante_file_cre: Regex,
     post_file_cre: Regex,
+    ante_spaced_file_cre: Regex,
+    post_spaced_file_cre: Regex,
     hunk_data_cre: Regex,
 }
 
+impl UnifiedDiffParser {
+    // The chunks and heading from the hunk header line at "start_index"
+    fn get_hunk_header_at(
+        &self,
+        lines: &[Line],
+        start_index: usize,
+    ) -> DiffParseResult<Option<(UnifiedDiffChunk, UnifiedDiffChunk, Option<String>)>> {
+        let captures = if let Some(captures) = self.hunk_data_cre.captures(&lines[start_index]) {
+            captures
+        } else {
+            return Ok(None);
+        };
+        let ante_chunk = UnifiedDiffChunk::from_captures(&captures, 1, 3, start_index)?;
+        let post_chunk = UnifiedDiffChunk::from_captures(&captures, 4, 6, start_index)?;
+        let heading = captures
+            .get(7)
+            .map(|m| m.as_str())
+            .filter(|heading| !heading.is_empty())
+            .map(|heading| heading.to_string());
+        Ok(Some((ante_chunk, post_chunk, heading)))
+    }
+}
+
 impl Default for UnifiedDiffParser {
     fn default() -> Self {
         Self::new()
@@ -215,22 +620,35 @@ impl TextDiffParser<UnifiedDiffHunk> for UnifiedDiffParser {
         let e = format!(r"^\+\+\+ ({PATH_RE_STR})(\s+{e_ts_re_str})?(.*)(\n)?$");
         let post_file_cre = Regex::new(&e).unwrap();
 
+        // NB: like git and diff, paths containing spaces must end with a tab
+        let e = format!(r"^--- ({SPACED_PATH_RE_STR})\t(\s*{e_ts_re_str})?(.*)(\n)?$");
+        let ante_spaced_file_cre = Regex::new(&e).unwrap();
+
+        let e = format!(r"^\+\+\+ ({SPACED_PATH_RE_STR})\t(\s*{e_ts_re_str})?(.*)(\n)?$");
+        let post_spaced_file_cre = Regex::new(&e).unwrap();
+
         let hunk_data_cre =
             Regex::new(r"^@@\s+-(\d+)(,(\d+))?\s+\+(\d+)(,(\d+))?\s+@@\s*(.*)(\n)?$").unwrap();
 
         UnifiedDiffParser {
             ante_file_cre,
             post_file_cre,
+            ante_spaced_file_cre,
+            post_spaced_file_cre,
             hunk_data_cre,
         }
     }
 
     fn ante_file_rec<'t>(&self, line: &'t Line) -> Option<Captures<'t>> {
-        self.ante_file_cre.captures(line)
+        self.ante_spaced_file_cre
+            .captures(line)
+            .or_else(|| self.ante_file_cre.captures(line))
     }
 
     fn post_file_rec<'t>(&self, line: &'t Line) -> Option<Captures<'t>> {
-        self.post_file_cre.captures(line)
+        self.post_spaced_file_cre
+            .captures(line)
+            .or_else(|| self.post_file_cre.captures(line))
     }
 
     fn get_hunk_at(
@@ -238,13 +656,12 @@ impl TextDiffParser<UnifiedDiffHunk> for UnifiedDiffParser {
         lines: &[Line],
         start_index: usize,
     ) -> DiffParseResult<Option<UnifiedDiffHunk>> {
-        let captures = if let Some(captures) = self.hunk_data_cre.captures(&lines[start_index]) {
-            captures
-        } else {
-            return Ok(None);
-        };
-        let ante_chunk = UnifiedDiffChunk::from_captures(&captures, 1, 3, start_index)?;
-        let post_chunk = UnifiedDiffChunk::from_captures(&captures, 4, 6, start_index)?;
+        let (ante_chunk, post_chunk, heading) =
+            if let Some(header) = self.get_hunk_header_at(lines, start_index)? {
+                header
+            } else {
+                return Ok(None);
+            };
         let mut index = start_index + 1;
         let mut ante_count = 0;
         let mut post_count = 0;
@@ -274,6 +691,39 @@ impl TextDiffParser<UnifiedDiffHunk> for UnifiedDiffParser {
             lines: lines[start_index..index].to_vec(),
             ante_chunk,
             post_chunk,
+            heading,
+        };
+        Ok(Some(hunk))
+    }
+
+    fn get_uncounted_hunk_at(
+        &self,
+        lines: &[Line],
+        start_index: usize,
+    ) -> DiffParseResult<Option<UnifiedDiffHunk>> {
+        let (ante_chunk, post_chunk, heading) =
+            if let Some(header) = self.get_hunk_header_at(lines, start_index)? {
+                header
+            } else {
+                return Ok(None);
+            };
+        let mut index = start_index + 1;
+        while index < lines.len() {
+            let line = &lines[index];
+            // NB: a "---" line followed by a "+++" line is the next diff's header
+            let is_next_header = line.starts_with("--- ")
+                && index + 1 < lines.len()
+                && self.post_file_cre.is_match(&lines[index + 1]);
+            if is_next_header || !(line.starts_with([' ', '-', '+', '\\'])) {
+                break;
+            }
+            index += 1;
+        }
+        let hunk = UnifiedDiffHunk {
+            lines: lines[start_index..index].to_vec(),
+            ante_chunk,
+            post_chunk,
+            heading,
         };
         Ok(Some(hunk))
     }
@@ -281,9 +731,16 @@ impl TextDiffParser<UnifiedDiffHunk> for UnifiedDiffParser {
 
 #[cfg(test)]
 mod tests {
-    use crate::lines::{Lines, LinesIfce};
-    use crate::text_diff::TextDiffParser;
-    use crate::unified_diff::UnifiedDiffParser;
+    use crate::abstract_diff::AbstractHunk;
+    use crate::hunk_heading::{HeadingMatcher, Language};
+    use crate::intra_line::{Granularity, IntraLineChange};
+    use crate::lines::{Line, LineIfce, Lines, LinesIfce};
+    use crate::text_diff::{
+        DiffBuildError, DiffParseError, HunkDiagnostic, HunkSelection, TextDiffHunk, TextDiffParser,
+    };
+    use crate::unified_diff::{UnifiedDiffBuilder, UnifiedDiffGenerator, UnifiedDiffParser};
+    use std::io;
+    use std::ops::Range;
     use std::path::Path;
 
     static UNIFIED_DIFF: &str = "--- lao	2002-02-21 23:30:39.942229878 -0800
@@ -305,6 +762,33 @@ mod tests {
 +They both may be called deep and profound.
 +Deeper and more profound,
 +The door of all subtleties!
+";
+
+    static LAO: &str = "The Way that can be told of is not the eternal Way;
+The name that can be named is not the eternal name.
+The Nameless is the origin of Heaven and Earth;
+The Named is the mother of all things.
+Therefore let there always be non-being,
+  so we may see their subtlety,
+And let there always be being,
+  so we may see their outcome.
+The two are the same,
+But after they are produced,
+  they have different names.
+";
+    static TZU: &str = "The Nameless is the origin of Heaven and Earth;
+The named is the mother of all things.
+
+Therefore let there always be non-being,
+  so we may see their subtlety,
+And let there always be being,
+  so we may see their result.
+The two are the same,
+But after they are produced,
+  they have different names.
+They both may be called deep and profound.
+Deeper and more profound,
+The door of all subtleties!
 ";
 
     #[test]
@@ -331,4 +815,489 @@ mod tests {
         let diff = result.unwrap();
         assert!(diff.iter().count() == diff.len());
     }
+
+    #[test]
+    fn unified_diff_intra_line_changes() {
+        let diff_lines = Lines::from_string(UNIFIED_DIFF);
+        let parser = UnifiedDiffParser::new();
+        let diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        let changes = diff.hunks()[0].intra_line_changes(Granularity::Word);
+        assert_eq!(
+            changes,
+            vec![IntraLineChange {
+                ante_line_index: 4,
+                ante_spans: vec![Range { start: 5, end: 10 }],
+                post_line_index: 5,
+                post_spans: vec![Range { start: 5, end: 10 }],
+            }]
+        );
+        assert!(diff.hunks()[1]
+            .intra_line_changes(Granularity::Word)
+            .is_empty());
+    }
+
+    #[test]
+    fn unified_diff_hunk_headings() {
+        let diff_lines = Lines::from_string(UNIFIED_DIFF);
+        let parser = UnifiedDiffParser::new();
+        let diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        assert!(diff.hunks().iter().all(|hunk| hunk.heading.is_none()));
+
+        let ante_lines = Lines::from_string(
+            "fn one() {\n    1\n}\n\nfn two() {\n    let a = 1;\n    let b = 2;\n    let c = 3;\n    a + b + c\n}\n",
+        );
+        let post_lines = Lines::from_string(
+            "fn one() {\n    1\n}\n\nfn two() {\n    let a = 1;\n    let b = 2;\n    let c = 4;\n    a + b + c\n}\n",
+        );
+        let generator = UnifiedDiffGenerator {
+            context_len: 1,
+            heading_matcher: Some(HeadingMatcher::for_language(Language::Rust)),
+            ..Default::default()
+        };
+        let diff = generator
+            .generate("a/two.rs", "b/two.rs", &ante_lines, &post_lines)
+            .unwrap();
+        let hunk = &diff.hunks()[0];
+        assert_eq!(hunk.heading.as_deref(), Some("fn two() {"));
+        assert_eq!(*hunk.lines[0], "@@ -7,3 +7,3 @@ fn two() {\n");
+
+        let diff_lines: Lines = diff.iter().cloned().collect();
+        let diff = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        assert_eq!(diff.hunks()[0].heading.as_deref(), Some("fn two() {"));
+
+        // rebuilt hunks keep their headings
+        let recontexted = diff.with_context(&ante_lines, 2).unwrap();
+        assert_eq!(
+            *recontexted.hunks()[0].lines[0],
+            "@@ -6,5 +6,5 @@ fn two() {\n"
+        );
+        let expanded = diff.with_expanded_hunk(&ante_lines, 0, 1, 0).unwrap();
+        assert_eq!(
+            *expanded.hunks()[0].lines[0],
+            "@@ -6,4 +6,4 @@ fn two() {\n"
+        );
+        let refreshed = diff.refreshed(&ante_lines, 3).unwrap();
+        assert_eq!(refreshed.hunks()[0].heading.as_deref(), Some("fn two() {"));
+    }
+
+    #[test]
+    fn unified_diff_recontext() {
+        let lao = Lines::from_string(LAO);
+        let tzu = Lines::from_string(TZU);
+        let generator = UnifiedDiffGenerator {
+            context_len: 0,
+            ..Default::default()
+        };
+        let diff = generator.generate("lao", "tzu", &lao, &tzu).unwrap();
+        assert_eq!(diff.hunks().len(), 4);
+
+        let expanded = diff.with_expanded_hunk(&lao, 1, 2, 1).unwrap();
+        assert_eq!(expanded.hunks().len(), 3);
+        assert_eq!(*expanded.hunks()[0].lines[0], "@@ -1,5 +1,4 @@\n");
+        assert_eq!(*expanded.hunks()[1].lines[0], "@@ -8,1 +7,1 @@\n");
+
+        let generator = UnifiedDiffGenerator::default();
+        let expected = generator.generate("lao", "tzu", &lao, &tzu).unwrap();
+        let recontexted = diff.with_context(&lao, 3).unwrap();
+        assert!(recontexted.iter().eq(expected.iter()));
+
+        assert!(diff.with_context(&tzu, 3).is_err());
+    }
+
+    #[test]
+    fn unified_diff_refresh() {
+        let lao = Lines::from_string(LAO);
+        let tzu = Lines::from_string(TZU);
+        let generator = UnifiedDiffGenerator {
+            context_len: 1,
+            ..Default::default()
+        };
+        let diff = generator.generate("lao", "tzu", &lao, &tzu).unwrap();
+
+        // shift the text and change some context
+        let mut lines = Lines::from_string("Preface\n\n");
+        lines.extend(
+            lao.iter()
+                .map(|line| Line::new(line.replace("the same", "one"))),
+        );
+        let stale = diff
+            .abstract_diff()
+            .apply_to_lines(&lines, false, &mut io::sink(), None);
+        assert!(stale.merges() > 0);
+        assert_eq!(stale.failures(), 0);
+        let mut expected = Lines::from_string("Preface\n\n");
+        expected.extend(
+            tzu.iter()
+                .map(|line| Line::new(line.replace("the same", "one"))),
+        );
+        assert_eq!(stale.lines(), &expected[..]);
+
+        let refreshed = diff.refreshed(&lines, 3).unwrap();
+        let result = refreshed
+            .abstract_diff()
+            .apply_to_lines(&lines, false, &mut io::sink(), None);
+        assert_eq!(result.successes(), refreshed.hunks().len() as u64);
+        assert_eq!(result.merges(), 0);
+        assert_eq!(result.lines(), stale.lines());
+
+        let unrelated = Lines::from_string("nothing\nin\ncommon\n");
+        assert!(diff.refreshed(&unrelated, 3).is_none());
+
+        // a hunk left between conflict markers is a failure
+        let ante = Lines::from_string("one\ntwo\nthree\n");
+        let post = Lines::from_string("one\n2\nthree\n");
+        let diff = UnifiedDiffGenerator::default()
+            .generate("file", "file", &ante, &post)
+            .unwrap();
+        let conflicting = Lines::from_string("one\nTWO\nthree\n");
+        let result =
+            diff.abstract_diff()
+                .apply_to_lines(&conflicting, false, &mut io::sink(), None);
+        assert_eq!(result.failures(), 1);
+        assert!(result.lines().contains(&Line::conflict_start_marker()));
+        assert!(diff.refreshed(&conflicting, 3).is_none());
+    }
+
+    #[test]
+    fn unified_diff_recount() {
+        // hand edited to keep the second line
+        let edited =
+            UNIFIED_DIFF.replace("-The name that can be named is not the eternal name.\n", "");
+        let diff_lines = Lines::from_string(&edited);
+        let parser = UnifiedDiffParser::new();
+        let mut diff = parser
+            .get_uncounted_diff_at(&diff_lines, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(diff.len(), diff_lines.len());
+        assert_eq!(
+            diff.validate(),
+            vec![
+                HunkDiagnostic::AnteLengthMismatch {
+                    hunk_index: 0,
+                    header: 7,
+                    actual: 6,
+                },
+                HunkDiagnostic::PostStartMismatch {
+                    hunk_index: 1,
+                    header: 8,
+                    expected: 9,
+                },
+            ]
+        );
+
+        diff.recount();
+        assert!(diff.validate().is_empty());
+        assert_eq!(*diff.hunks()[0].lines[0], "@@ -1,6 +1,6 @@\n");
+        assert_eq!(*diff.hunks()[1].lines[0], "@@ -9,3 +9,6 @@\n");
+        let diff_lines: Lines = diff.iter().cloned().collect();
+        let reparsed = parser.get_diff_at(&diff_lines, 0).unwrap().unwrap();
+        assert_eq!(reparsed.len(), diff_lines.len());
+
+        // hand edited to add context to a hunk that had none
+        let diff_lines =
+            Lines::from_string("--- a/file\n+++ b/file\n@@ -0,0 +1,1 @@\n kept\n+added\n");
+        let mut diff = parser
+            .get_uncounted_diff_at(&diff_lines, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            diff.validate(),
+            vec![
+                HunkDiagnostic::AnteLengthMismatch {
+                    hunk_index: 0,
+                    header: 0,
+                    actual: 1,
+                },
+                HunkDiagnostic::PostLengthMismatch {
+                    hunk_index: 0,
+                    header: 1,
+                    actual: 2,
+                },
+                HunkDiagnostic::AnteStartMismatch {
+                    hunk_index: 0,
+                    header: 0,
+                    expected: 1,
+                },
+            ]
+        );
+        diff.recount();
+        assert!(diff.validate().is_empty());
+        assert_eq!(*diff.hunks()[0].lines[0], "@@ -1,1 +1,2 @@\n");
+    }
+
+    #[test]
+    fn unified_diff_builder() {
+        let lao = Lines::from_string(LAO);
+        let tzu = Lines::from_string(TZU);
+        let generator = UnifiedDiffGenerator::default();
+        let expected = generator.generate("lao", "tzu", &lao, &tzu).unwrap();
+        let hunks: Vec<AbstractHunk> = expected
+            .hunks()
+            .iter()
+            .map(|hunk| hunk.get_abstract_diff_hunk())
+            .collect();
+
+        let mut builder = UnifiedDiffBuilder::new("lao", "tzu");
+        for hunk in hunks {
+            builder = builder.hunk(hunk);
+        }
+        let diff = builder.build().unwrap();
+        assert!(diff.iter().eq(expected.iter()));
+        assert_eq!(diff.len(), expected.len());
+
+        let diff = builder
+            .ante_time_stamp("2002-02-21 23:30:39.942229878 -0800")
+            .build()
+            .unwrap();
+        assert_eq!(
+            *diff.header().lines[0],
+            "--- lao\t2002-02-21 23:30:39.942229878 -0800\n"
+        );
+
+        let generator = UnifiedDiffGenerator {
+            context_len: 0,
+            ..Default::default()
+        };
+        let mut hunks: Vec<AbstractHunk> = generator
+            .generate("lao", "tzu", &lao, &tzu)
+            .unwrap()
+            .hunks()
+            .iter()
+            .map(|hunk| hunk.get_abstract_diff_hunk())
+            .collect();
+        hunks.swap(1, 2);
+        let mut builder = UnifiedDiffBuilder::new("lao", "tzu");
+        for hunk in hunks {
+            builder = builder.hunk(hunk);
+        }
+        assert_eq!(
+            builder.build().err(),
+            Some(DiffBuildError::HunkOutOfOrder(2))
+        );
+        assert!(UnifiedDiffBuilder::new("lao", "tzu")
+            .post_time_stamp("yesterday")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn unified_diff_selection() {
+        let diff_lines = Lines::from_string(UNIFIED_DIFF);
+        let diff = UnifiedDiffParser::new()
+            .get_diff_at(&diff_lines, 0)
+            .unwrap()
+            .unwrap();
+        let lao = Lines::from_string(LAO);
+
+        let selected = diff.selected(&HunkSelection::new().hunk(1)).unwrap();
+        assert_eq!(selected.hunks().len(), 1);
+        assert_eq!(*selected.hunks()[0].lines[0], "@@ -9,3 +9,6 @@\n");
+
+        // just the change of "Named" to "named"
+        let selection = HunkSelection::new().lines(0, &[4, 5]).hunk(1);
+        let selected = diff.selected(&selection).unwrap();
+        assert!(selected.validate().is_empty());
+        assert_eq!(*selected.hunks()[0].lines[0], "@@ -1,7 +1,7 @@\n");
+        assert_eq!(*selected.hunks()[1].lines[0], "@@ -9,3 +9,6 @@\n");
+        let result = selected
+            .abstract_diff()
+            .apply_to_lines(&lao, false, &mut io::sink(), None);
+        assert_eq!(result.successes(), 2);
+        let mut expected = Lines::from_string(&LAO.replace("The Named", "The named"));
+        expected.extend(Lines::from_string(
+            "They both may be called deep and profound.\n\
+             Deeper and more profound,\n\
+             The door of all subtleties!\n",
+        ));
+        assert_eq!(result.lines(), &expected[..]);
+
+        assert!(diff
+            .selected(&HunkSelection::new().lines(0, &[3]))
+            .is_none());
+        assert!(diff.selected(&HunkSelection::new()).is_none());
+
+        // a deselected removal of a last line without a newline
+        let diff_lines = Lines::from_string(
+            "--- a/file\n+++ b/file\n@@ -1,2 +1,2 @@\n x\n-a\n\\ No newline at end of file\n+b\n",
+        );
+        let diff = UnifiedDiffParser::new()
+            .get_diff_at(&diff_lines, 0)
+            .unwrap()
+            .unwrap();
+        let selected = diff.selected(&HunkSelection::new().lines(0, &[4])).unwrap();
+        assert!(selected.validate().is_empty());
+        let text: String = selected.hunks()[0].iter().map(|l| l.as_str()).collect();
+        assert_eq!(
+            text,
+            "@@ -1,2 +1,3 @@\n x\n-a\n\\ No newline at end of file\n+a\n+b\n"
+        );
+        let result = selected.abstract_diff().apply_to_lines(
+            &Lines::from_string("x\na"),
+            false,
+            &mut io::sink(),
+            None,
+        );
+        assert_eq!(result.successes(), 1);
+        assert_eq!(result.lines(), &Lines::from_string("x\na\nb\n")[..]);
+    }
+
+    #[test]
+    fn unified_diff_split_and_merge() {
+        let diff_lines = Lines::from_string(UNIFIED_DIFF);
+        let mut diff = UnifiedDiffParser::new()
+            .get_diff_at(&diff_lines, 0)
+            .unwrap()
+            .unwrap();
+        let lao = Lines::from_string(LAO);
+        let expected = diff
+            .abstract_diff()
+            .apply_to_lines(&lao, false, &mut io::sink(), None);
+
+        assert_eq!(diff.hunks()[1].split().len(), 1);
+        assert_eq!(diff.split_hunk(0), 2);
+        assert_eq!(diff.hunks().len(), 3);
+        assert_eq!(*diff.hunks()[0].lines[0], "@@ -1,3 +1,1 @@\n");
+        assert_eq!(*diff.hunks()[1].lines[0], "@@ -4,4 +2,5 @@\n");
+        assert_eq!(
+            *diff.hunks()[1].lines[1],
+            "-The Named is the mother of all things.\n"
+        );
+        // NB: the context between the pieces is shared out between them
+        assert!(diff.validate().is_empty());
+        let result = diff
+            .abstract_diff()
+            .apply_to_lines(&lao, false, &mut io::sink(), None);
+        assert_eq!(result.successes(), 3);
+        assert_eq!(result.failures(), 0);
+        assert_eq!(result.lines(), expected.lines());
+
+        assert!(diff.hunks()[1].merge_adjacent(&diff.hunks()[0]).is_none());
+        assert!(diff.hunks()[1].merge_adjacent(&diff.hunks()[2]).is_none());
+        diff.merge_adjacent_hunks();
+        assert!(diff.iter().eq(diff_lines.iter()));
+        let result = diff
+            .abstract_diff()
+            .apply_to_lines(&lao, false, &mut io::sink(), None);
+        assert_eq!(result.successes(), 2);
+        assert_eq!(result.lines(), expected.lines());
+    }
+
+    #[test]
+    fn unified_diff_reversed() {
+        let diff_lines = Lines::from_string(UNIFIED_DIFF);
+        let diff = UnifiedDiffParser::new()
+            .get_diff_at(&diff_lines, 0)
+            .unwrap()
+            .unwrap();
+        let reversed = diff.reversed();
+        assert_eq!(
+            *reversed.header().lines[0],
+            "--- tzu\t2002-02-21 23:30:50.442260588 -0800\n"
+        );
+        assert_eq!(reversed.header().post_pat.file_path(), "lao");
+        assert_eq!(*reversed.hunks()[0].lines[0], "@@ -1,6 +1,7 @@\n");
+        assert_eq!(
+            *reversed.hunks()[0].lines[4],
+            "-The named is the mother of all things.\n"
+        );
+        assert!(reversed.validate().is_empty());
+
+        let lao = Lines::from_string(LAO);
+        let post = diff
+            .abstract_diff()
+            .apply_to_lines(&lao, false, &mut io::sink(), None);
+        let result =
+            reversed
+                .abstract_diff()
+                .apply_to_lines(post.lines(), false, &mut io::sink(), None);
+        assert_eq!(result.successes(), 2);
+        assert_eq!(result.lines(), &lao);
+        assert!(reversed.reversed().iter().eq(diff_lines.iter()));
+
+        let ante_lines = Lines::from_string("one\ntwo\n");
+        let diff = UnifiedDiffGenerator::default()
+            .generate("/dev/null", "b/file", &[], &ante_lines)
+            .unwrap()
+            .reversed();
+        assert_eq!(diff.get_ante_file_path(0), "a/file");
+        assert_eq!(diff.get_post_file_path(0), "/dev/null");
+        assert_eq!(*diff.hunks()[0].lines[0], "@@ -1,2 +0,0 @@\n");
+    }
+
+    #[test]
+    fn unified_diff_squash() {
+        let lao = Lines::from_string(LAO);
+        let tzu = Lines::from_string(TZU);
+        let mid = Lines::from_string(
+            &LAO.replacen("Named", "named", 1)
+                .replace("outcome", "result"),
+        );
+        let generator = UnifiedDiffGenerator::default();
+        let first = generator.generate("a/lao", "b/lao", &lao, &mid).unwrap();
+        let second = generator.generate("a/lao", "b/tzu", &mid, &tzu).unwrap();
+        let expected = generator.generate("a/lao", "b/tzu", &lao, &tzu).unwrap();
+
+        let squashed = first.squashed(&second, 3).unwrap();
+        assert!(squashed.iter().eq(expected.iter()));
+        let result = squashed
+            .abstract_diff()
+            .apply_to_lines(&lao, false, &mut io::sink(), None);
+        assert_eq!(result.successes(), expected.hunks().len() as u64);
+        assert_eq!(result.lines(), &tzu);
+        let squashed = first.squashed_using(&second, &lao, 3).unwrap();
+        assert!(squashed.iter().eq(expected.iter()));
+
+        let undone = first.squashed(&first.reversed(), 3).unwrap();
+        assert!(undone.hunks().is_empty());
+        match first.squashed(&expected, 3) {
+            Err(error @ DiffParseError::DiffConflict(_)) => {
+                assert!(error.to_string().ends_with("contradicts the earlier diff."))
+            }
+            _ => panic!("expected the diffs to conflict"),
+        }
+    }
+
+    #[test]
+    fn unified_diff_line_mapping() {
+        let ante = Lines::from_string("1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n");
+        let post = Lines::from_string("1\n2\n4\nfive\n6\n7\n8\n8.5\n9\n10\n11\n12\n");
+        let generator = UnifiedDiffGenerator {
+            context_len: 1,
+            ..UnifiedDiffGenerator::default()
+        };
+        let diff = generator
+            .generate("a/file", "b/file", &ante, &post)
+            .unwrap();
+        assert_eq!(diff.hunks().len(), 2);
+        let post_line_nums: Vec<Option<usize>> = (1..=12)
+            .map(|line_num| diff.post_line_num(line_num))
+            .collect();
+        assert_eq!(
+            post_line_nums,
+            vec![
+                Some(1),
+                Some(2),
+                None,
+                Some(3),
+                None,
+                Some(5),
+                Some(6),
+                Some(7),
+                Some(9),
+                Some(10),
+                Some(11),
+                Some(12)
+            ]
+        );
+        for (ante_line_num, post_line_num) in post_line_nums.iter().enumerate() {
+            if let Some(post_line_num) = post_line_num {
+                assert_eq!(diff.ante_line_num(*post_line_num), Some(ante_line_num + 1));
+            }
+        }
+        assert_eq!(diff.ante_line_num(4), None);
+        assert_eq!(diff.post_line_num(0), None);
+        assert_eq!(diff.ante_line_num(0), None);
+        assert_eq!(diff.ante_line_num(8), None);
+    }
 }
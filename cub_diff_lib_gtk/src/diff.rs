@@ -14,6 +14,7 @@
 
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::rc::Rc;
 
 use lazy_static;
@@ -28,6 +29,7 @@ use pw_gix::pango;
 use cub_diff_lib::context_diff::ContextDiff;
 use cub_diff_lib::diff::{Diff, DiffPlus};
 use cub_diff_lib::git_binary_diff::GitBinaryDiff;
+use cub_diff_lib::intra_line::{Granularity, IntraLineChange};
 use cub_diff_lib::lines::*;
 use cub_diff_lib::preamble::*;
 use cub_diff_lib::text_diff::TextDiffHunk;
@@ -85,13 +87,14 @@ lazy_static! {
     static ref TWS_CHECK_CRE: Regex = Regex::new(r"^([\+!].*\S)(\s+\n?)$").unwrap();
 }
 
+#[derive(Clone, Copy)]
 enum MarkupType {
     Header,
     Ante,
     Post,
     Added,
     Removed,
-    _Changed,
+    Changed,
     Unchanged,
     AddedTWS,
     Stats,
@@ -123,7 +126,7 @@ macro_rules! markup_as {
                 "<span foreground=\"#008800\" face=\"monospace\">{}</span>",
                 e_text
             ),
-            MarkupType::_Changed => format!(
+            MarkupType::Changed => format!(
                 "<span foreground=\"#AA6600\" face=\"monospace\">{}</span>",
                 e_text
             ),
@@ -151,6 +154,46 @@ macro_rules! markup_as {
     }};
 }
 
+// Map hunk line indices to the spans within them that have changed
+fn changed_spans_by_line(changes: &[IntraLineChange]) -> HashMap<usize, &[Range<usize>]> {
+    let mut changed_spans = HashMap::new();
+    for change in changes.iter() {
+        changed_spans.insert(change.ante_line_index, &change.ante_spans[..]);
+        changed_spans.insert(change.post_line_index, &change.post_spans[..]);
+    }
+    changed_spans
+}
+
+// Mark up the line with its changed spans highlighted
+fn changed_line_markup(line: &str, markup_type: MarkupType, spans: &[Range<usize>]) -> String {
+    let tws_start = match markup_type {
+        MarkupType::Added => TWS_CHECK_CRE
+            .captures(line)
+            .map_or(line.len(), |captures| captures.get(2).unwrap().start()),
+        _ => line.len(),
+    };
+    let mut segments = vec![];
+    let mut index = 0;
+    for span in spans.iter() {
+        segments.push((index..span.start, markup_type));
+        segments.push((span.clone(), MarkupType::Changed));
+        index = span.end;
+    }
+    segments.push((index..line.len(), markup_type));
+    let mut markup = String::new();
+    for (range, segment_type) in segments {
+        if range.start < tws_start && !range.is_empty() {
+            let text = &line[range.start..range.end.min(tws_start)];
+            markup.push_str(&markup_as!(segment_type, text));
+        }
+        if range.end > tws_start {
+            let text = &line[range.start.max(tws_start)..range.end];
+            markup.push_str(&markup_as!(MarkupType::AddedTWS, text));
+        }
+    }
+    markup
+}
+
 pub trait DiffPlusTextBuffer: TextBufferExt {
     fn append_markup(&mut self, markup: &str) {
         self.insert_markup(&mut self.get_end_iter(), markup);
@@ -189,13 +232,22 @@ pub trait DiffPlusTextBuffer: TextBufferExt {
         let post_header_line = &unified_diff.header().lines[1];
         self.append_markup(&markup_as!(MarkupType::Post, post_header_line));
         for hunk in unified_diff.hunks().iter() {
-            let mut iter = hunk.iter();
-            let first_line = iter.next().unwrap();
+            let changes = hunk.intra_line_changes(Granularity::Word);
+            let changed_spans = changed_spans_by_line(&changes);
+            let mut iter = hunk.iter().enumerate();
+            let (_, first_line) = iter.next().unwrap();
             let i = first_line[2..].find("@@").unwrap();
             self.append_markup(&markup_as!(MarkupType::Stats, &first_line[..i + 4]));
             self.append_markup(&markup_as!(MarkupType::ContextAid, &first_line[i + 4..]));
-            for line in iter {
-                if line.starts_with('+') {
+            for (index, line) in iter {
+                if let Some(spans) = changed_spans.get(&index) {
+                    let markup_type = if line.starts_with('+') {
+                        MarkupType::Added
+                    } else {
+                        MarkupType::Removed
+                    };
+                    self.append_markup(&changed_line_markup(line, markup_type, spans));
+                } else if line.starts_with('+') {
                     self.append_added_line(line);
                 } else if line.starts_with('-') {
                     self.append_markup(&markup_as!(MarkupType::Removed, line));
@@ -212,12 +264,21 @@ pub trait DiffPlusTextBuffer: TextBufferExt {
         let post_header_line = &context_diff.header().lines[1];
         self.append_markup(&markup_as!(MarkupType::Post, post_header_line));
         for hunk in context_diff.hunks().iter() {
-            let mut iter = hunk.iter();
-            let first_line = iter.next().unwrap();
+            let changes = hunk.intra_line_changes(Granularity::Word);
+            let changed_spans = changed_spans_by_line(&changes);
+            let mut iter = hunk.iter().enumerate();
+            let (_, first_line) = iter.next().unwrap();
             self.append_markup(&markup_as!(MarkupType::Separator, first_line));
             let mut in_post = false;
-            for line in iter {
-                if line.starts_with("***") {
+            for (index, line) in iter {
+                if let Some(spans) = changed_spans.get(&index) {
+                    let markup_type = if in_post {
+                        MarkupType::Added
+                    } else {
+                        MarkupType::Removed
+                    };
+                    self.append_markup(&changed_line_markup(line, markup_type, spans));
+                } else if line.starts_with("***") {
                     self.append_markup(&markup_as!(MarkupType::Ante, line));
                 } else if line.starts_with("---") {
                     in_post = true;